@@ -14,10 +14,10 @@ import ArcadeForm from "./ArcadeForm.jsx";
 const SLOT_HASHES_SYSVAR = new PublicKey("SysvarS1otHashes111111111111111111111111111");
 const house = new PublicKey('Gt74tMkBPNXoUQSYGm8SzBBaqPCVXHsSQ9JwWPZcbay1');
 const programId = new PublicKey('7Ah8WAJw7CDxwbPQono7rKaRAmZ4ymjguouz1CfHScXY');
-const reservePDA = new PublicKey('DUPoC5h6LAwGUuAoLzkWdnEShHPJx2bmvPqkZQU5hWbg')
-const reserveBump = 252
-const reserveKeyPDA = new PublicKey('3gx2Mi1ze8VJWmUAAAEYhGeakXwGBMU2xwE6jKCNtfLM')
-const reserveKeyBump = 253
+const tableId = 0
+const tableSeed = Uint8Array.of(tableId & 0xff, tableId >> 8) // u16 le
+const [reservePDA] = PublicKey.findProgramAddressSync([utils.bytes.utf8.encode('reserve-1'), tableSeed], programId)
+const [reserveKeyPDA, reserveKeyBump] = PublicKey.findProgramAddressSync([utils.bytes.utf8.encode('reserve-key-1'), tableSeed], programId)
 const edge = 0.5
 const ratio = 5
 const testnet = false
@@ -32,8 +32,10 @@ const Dice = () => {
     const [message, setMessage] = useState('');
     const [userBalance, setUserBalance] = useState(0)
     const [reserveKeyBalance, setReserveKeyBalance] = useState(0)
+    const [pendingPayout, setPendingPayout] = useState(0)
     const [txLink, setTxLink] = useState(null)
     const [program, setProgram] = useState(null);
+    const [playerStats, setPlayerStats] = useState(null)
 
     const wallet = useAnchorWallet();
     const { connected } = useWallet();
@@ -62,18 +64,78 @@ const Dice = () => {
         return r
     }
 
-    function recomputeBalances() {
+    function recomputeBalances(program) {
         connection.getBalance(reserveKeyPDA).then(balance => {
             // console.log('Reserve key balance:', balance);
             setReserveKeyBalance(balance)
         })
 
+        if (program) {
+            // wins of bets that are still settling can't be bet against
+            program.account.reserve.fetch(reservePDA).then(reserve => {
+                setPendingPayout(reserve.pendingPayout.toNumber())
+            })
+        }
+
         connection.getBalance(wallet.publicKey).then(balance => {
             // console.log('User balance:', balance);
             setUserBalance(balance)
         });
     }
 
+    function getPlayerStatsPDA(player) {
+        const [playerStatsPDA, _] = PublicKey.findProgramAddressSync(
+            [utils.bytes.utf8.encode("player-stats"), player.toBuffer()],
+            programId
+        );
+        return playerStatsPDA
+    }
+
+    function getBetPDA(player, seed) {
+        const [betPDA, _] = PublicKey.findProgramAddressSync(
+            [utils.bytes.utf8.encode("bet"), player.toBuffer(), Uint8Array.from(seed.toArray("le", 8))],
+            programId
+        );
+        return betPDA
+    }
+
+    async function waitForSlot(slot) {
+        while (await connection.getSlot('confirmed') <= slot) {
+            await new Promise(resolve => setTimeout(resolve, 400));
+        }
+    }
+
+    function getReferrerPDA(referrer) {
+        const [referrerPDA, _] = PublicKey.findProgramAddressSync(
+            [utils.bytes.utf8.encode("referrer"), referrer.toBuffer()],
+            programId
+        );
+        return referrerPDA
+    }
+
+    // the referrer bound on the first roll, otherwise the one from a ?ref=<pubkey> link
+    async function getReferrer() {
+        if (playerStats && playerStats.referrer && !playerStats.referrer.equals(PublicKey.default)) {
+            return getReferrerPDA(playerStats.referrer)
+        }
+        const ref = new URLSearchParams(window.location.search).get('ref')
+        if (!ref || !program.account.referrer) {
+            return null
+        }
+        const referrerPDA = getReferrerPDA(new PublicKey(ref))
+        const referrer = await program.account.referrer.fetchNullable(referrerPDA)
+        return referrer ? referrerPDA : null
+    }
+
+    function refreshPlayerStats(program) {
+        if (!program.account.playerStats) {
+            return
+        }
+        program.account.playerStats.fetchNullable(getPlayerStatsPDA(wallet.publicKey)).then(stats => {
+            setPlayerStats(stats)
+        })
+    }
+
     function recomputeBetSizes() {
         if (reserveKeyBalance === 0) {
             setBetSizes([0])
@@ -82,7 +144,7 @@ const Dice = () => {
         }
 
         const numIntervals = 16;
-        const maxBet = getMaxBetSize(reserveKeyBalance)
+        const maxBet = getMaxBetSize(reserveKeyBalance - pendingPayout)
         const minBet = 100000
         // Calculate the logarithmic step size
         // const logMaxBet = Math.log(maxBet);
@@ -204,21 +266,23 @@ const Dice = () => {
             const seed = new BN(randomInteger(1, 10000))
             const bet = new BN(betSize * web3.LAMPORTS_PER_SOL)
             const mult = new BN(multiplier * 10_000)
+            const betPDA = getBetPDA(wallet.publicKey, seed)
             const accounts = {
                 player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                referrer: await getReferrer(),
                 reserve: reservePDA,
                 reserveKey: reserveKeyPDA,
-                house: house,
-                systemProgram: web3.SystemProgram.programId,
-                slotHashes: SLOT_HASHES_SYSVAR
+                bet: betPDA,
+                systemProgram: web3.SystemProgram.programId
             }
             // for (let value of [wallet.publicKey, reservePDA, reserveKeyPDA, house, web3.SystemProgram.programId, SLOT_HASHES_SYSVAR] ) {
             //     const accountInfo = await connection.getAccountInfo(value, 'confirmed');
             //     console.log(value.toBase58(), " account info: ", accountInfo);
             // }
 
-            // Call the roll_dice function
-            const setup = await program.methods.rollDice(seed, mult, bet, reserveKeyBump).accounts(accounts)
+            // Commit the bet, it settles from the hash of a slot after the commit
+            const setup = await program.methods.commitRoll(seed, {multiplier: {multiplierBp: mult}}, bet).accounts(accounts)
 
             // // simulate tx
             // const ix = await setup.instruction();
@@ -266,8 +330,26 @@ const Dice = () => {
             //     return
             // }
 
-            const tx = await setup.signers([]).rpc();
-            setMessage('Bet placed successfully!');
+            const commitTx = await setup.signers([]).rpc();
+            setMessage('Bet placed, rolling...');
+            await connection.confirmTransaction(commitTx, 'confirmed');
+            const placed = await program.account.bet.fetch(betPDA, 'confirmed')
+            await waitForSlot(placed.targetSlot.toNumber())
+
+            // the referrer bound when the bet was placed takes the referral fee at settle
+            const betReferrer = placed.referrer.equals(PublicKey.default) ? null : getReferrerPDA(placed.referrer)
+            const tx = await program.methods.settleRoll(reserveKeyBump).accounts({
+                settler: wallet.publicKey,
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                referrer: betReferrer,
+                bet: betPDA,
+                reserve: reservePDA,
+                house: house,
+                reserveKey: reserveKeyPDA,
+                systemProgram: web3.SystemProgram.programId,
+                slotHashes: SLOT_HASHES_SYSVAR
+            }).signers([]).rpc();
             const win = await getWinLose(tx)
             if (win) {
                 setMessage("You're a WINNER! You won " + betSize * multiplier + " SOL")
@@ -280,7 +362,8 @@ const Dice = () => {
                 link += '?cluster=testnet'
             }
             setTxLink(link)
-            recomputeBalances()
+            recomputeBalances(program)
+            refreshPlayerStats(program)
             // const tx = await setup.signers([]).rpc();
 
         } catch (error) {
@@ -295,7 +378,8 @@ const Dice = () => {
             const provider = new AnchorProvider(connection, wallet);
             const program = new Program(idl, provider);
             setProgram(program);
-            recomputeBalances();
+            recomputeBalances(program);
+            refreshPlayerStats(program);
         }
     }, [wallet, connection]);
 
@@ -311,7 +395,7 @@ const Dice = () => {
     useEffect(() => {
         recomputeBetSizes()
         // TODO: make this more reload after every bet
-    }, [reserveKeyBalance])
+    }, [reserveKeyBalance, pendingPayout])
 
     return (
         <Container>
@@ -370,6 +454,13 @@ const Dice = () => {
                     User Balance: {(userBalance / web3.LAMPORTS_PER_SOL).toFixed(5)} SOL
                 </Typography>
             )}
+            {playerStats && (
+                <Typography variant="body1" color="textSecondary" align="center" margin="normal">
+                    Rolls: {playerStats.rollCount.toString()} -
+                    Wagered: {(playerStats.totalWagered.toNumber() / web3.LAMPORTS_PER_SOL).toFixed(5)} SOL -
+                    Won: {(playerStats.totalWon.toNumber() / web3.LAMPORTS_PER_SOL).toFixed(5)} SOL
+                </Typography>
+            )}
             {reserveKeyBalance > 0 && (
                 <Typography variant="body1" color="textSecondary" align="center" margin="normal">
                     Arcade Balance: {(reserveKeyBalance / web3.LAMPORTS_PER_SOL).toFixed(5)} SOL
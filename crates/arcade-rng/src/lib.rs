@@ -0,0 +1,226 @@
+//! Randomness derivation shared by the arcade programs.
+//!
+//! Every game mixes its inputs through [`derive_seed`] under its own domain so that the
+//! same inputs never produce correlated outcomes across games, then draws numbers from
+//! the resulting seed with [`uniform`], which rejection samples instead of reducing a
+//! hash modulo the range.
+
+use sha3::{Digest, Sha3_256};
+
+pub const DICE_DOMAIN: &[u8] = b"arcade-dice";
+pub const LOTTERY_DOMAIN: &[u8] = b"arcade-lottery";
+
+const U64_LENGTH: usize = 8;
+const HASH_LENGTH: usize = 32;
+const SLOT_HASH_ENTRY_LENGTH: usize = U64_LENGTH + HASH_LENGTH;
+
+/// Plain Sha3-256 over the concatenation of `inputs`.
+pub fn hash(inputs: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    for input in inputs {
+        hasher.update(input);
+    }
+    hasher.finalize().into()
+}
+
+/// Hashes `inputs` under `domain`. Every part is length prefixed so inputs can't be
+/// shifted between each other or into the domain.
+pub fn derive_seed(domain: &[u8], inputs: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    hasher.update((domain.len() as u64).to_le_bytes());
+    hasher.update(domain);
+    for input in inputs {
+        hasher.update((input.len() as u64).to_le_bytes());
+        hasher.update(input);
+    }
+    hasher.finalize().into()
+}
+
+/// Endless stream of `u64`s expanded from a seed by hashing it with a counter.
+pub struct RandomStream {
+    seed: [u8; 32],
+    counter: u64,
+}
+
+impl RandomStream {
+    pub fn new(seed: [u8; 32]) -> Self {
+        RandomStream { seed, counter: 0 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let block = hash(&[&self.seed, &self.counter.to_le_bytes()]);
+        self.counter += 1;
+        let mut bytes = [0u8; U64_LENGTH];
+        bytes.copy_from_slice(&block[..U64_LENGTH]);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Draws a number in `0..bound` without modulo bias, `None` if the range is empty.
+    pub fn next_below(&mut self, bound: u64) -> Option<u64> {
+        if bound == 0 {
+            return None;
+        }
+        // largest multiple of `bound` that fits in a u64, anything at or above it is redrawn
+        let zone = u64::MAX - (u64::MAX % bound + 1) % bound;
+        loop {
+            let value = self.next_u64();
+            if value <= zone {
+                return Some(value % bound);
+            }
+        }
+    }
+}
+
+/// Draws a single number in `0..bound` from `seed`, `None` if the range is empty.
+pub fn uniform(seed: [u8; 32], bound: u64) -> Option<u64> {
+    RandomStream::new(seed).next_below(bound)
+}
+
+/// Read-only view over the raw data of the `SlotHashes` sysvar: a little-endian `u64`
+/// entry count followed by `(slot, hash)` entries, newest slot first.
+pub struct SlotHashesData<'a> {
+    entries: &'a [u8],
+}
+
+impl<'a> SlotHashesData<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let len = read_u64(data.get(..U64_LENGTH)?) as usize;
+        let end = len.checked_mul(SLOT_HASH_ENTRY_LENGTH)?.checked_add(U64_LENGTH)?;
+        Some(SlotHashesData { entries: data.get(U64_LENGTH..end)? })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() / SLOT_HASH_ENTRY_LENGTH
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<(u64, [u8; 32])> {
+        let start = index.checked_mul(SLOT_HASH_ENTRY_LENGTH)?;
+        let entry = self.entries.get(start..start + SLOT_HASH_ENTRY_LENGTH)?;
+        let mut hash = [0u8; HASH_LENGTH];
+        hash.copy_from_slice(&entry[U64_LENGTH..]);
+        Some((read_u64(&entry[..U64_LENGTH]), hash))
+    }
+
+    /// The most recently recorded slot and its hash.
+    pub fn most_recent(&self) -> Option<(u64, [u8; 32])> {
+        self.get(0)
+    }
+
+    /// The hash recorded for `slot`, `None` if it was skipped or is outside the window.
+    pub fn find(&self, slot: u64) -> Option<[u8; 32]> {
+        // entries are sorted by descending slot
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let (entry_slot, hash) = self.get(mid)?;
+            if entry_slot == slot {
+                return Some(hash);
+            } else if entry_slot > slot {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        None
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; U64_LENGTH];
+    buf.copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn slot_hashes_data(entries: &[(u64, [u8; 32])]) -> Vec<u8> {
+        let mut data = (entries.len() as u64).to_le_bytes().to_vec();
+        for (slot, hash) in entries {
+            data.extend_from_slice(&slot.to_le_bytes());
+            data.extend_from_slice(hash);
+        }
+        data
+    }
+
+    #[test]
+    fn empty_range_has_no_value() {
+        assert_eq!(uniform([7u8; 32], 0), None);
+    }
+
+    #[test]
+    fn buckets_are_roughly_even() {
+        let mut stream = RandomStream::new(derive_seed(DICE_DOMAIN, &[b"buckets"]));
+        let mut counts = [0u32; 10];
+        for _ in 0..100_000 {
+            counts[stream.next_below(10).unwrap() as usize] += 1;
+        }
+        for count in counts {
+            assert!((9_500..10_500).contains(&count), "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_slot_hashes() {
+        let data = slot_hashes_data(&[(10, [1u8; 32]), (9, [2u8; 32])]);
+        assert!(SlotHashesData::new(&data[..data.len() - 1]).is_none());
+        assert!(SlotHashesData::new(&data[..4]).is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn uniform_stays_in_range(seed in any::<[u8; 32]>(), bound in 1u64..) {
+            prop_assert!(uniform(seed, bound).unwrap() < bound);
+        }
+
+        #[test]
+        fn uniform_handles_bounds_near_the_top(seed in any::<[u8; 32]>(), offset in 0u64..1_000) {
+            let bound = u64::MAX / 2 + 1 + offset;
+            prop_assert!(uniform(seed, bound).unwrap() < bound);
+        }
+
+        #[test]
+        fn uniform_is_deterministic(seed in any::<[u8; 32]>(), bound in 1u64..) {
+            prop_assert_eq!(uniform(seed, bound), uniform(seed, bound));
+        }
+
+        #[test]
+        fn domains_are_separated(input in any::<Vec<u8>>()) {
+            prop_assert_ne!(derive_seed(DICE_DOMAIN, &[&input]), derive_seed(LOTTERY_DOMAIN, &[&input]));
+        }
+
+        #[test]
+        fn inputs_cannot_be_shifted(a in any::<Vec<u8>>(), b in any::<Vec<u8>>()) {
+            prop_assume!(!a.is_empty());
+            let (head, tail) = a.split_at(a.len() - 1);
+            let moved = [tail, &b[..]].concat();
+            prop_assert_ne!(derive_seed(DICE_DOMAIN, &[&a, &b]), derive_seed(DICE_DOMAIN, &[head, &moved]));
+        }
+
+        #[test]
+        fn finds_every_recorded_slot(newest in 512u64..u64::MAX / 2, gaps in proptest::collection::vec(1u64..4, 1..512)) {
+            let mut slot = newest;
+            let mut entries = vec![];
+            for (i, gap) in gaps.iter().enumerate() {
+                entries.push((slot, [i as u8; 32]));
+                slot -= gap;
+            }
+            let data = slot_hashes_data(&entries);
+            let slot_hashes = SlotHashesData::new(&data).unwrap();
+
+            prop_assert_eq!(slot_hashes.len(), entries.len());
+            prop_assert_eq!(slot_hashes.most_recent(), Some(entries[0]));
+            for (slot, hash) in &entries {
+                prop_assert_eq!(slot_hashes.find(*slot), Some(*hash));
+            }
+            prop_assert_eq!(slot_hashes.find(newest + 1), None);
+            prop_assert_eq!(slot_hashes.find(slot), None);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Randomness oracle interface shared by the arcade programs.
+//!
+//! The games don't link against any particular oracle. They talk to whatever program the
+//! admin configured as `vrf_program` through the interface below: a `request` instruction
+//! that takes a 32 byte seed and creates a randomness account, and the layout of that
+//! account once the oracle has fulfilled it. A deployment points `vrf_program` at an
+//! oracle, or at an adapter in front of one (ORAO, Switchboard), that serves this
+//! interface and derives its randomness from a key the requester doesn't know.
+//!
+//! `mock-vrf` implements the interface for local clusters only, its fulfillment is
+//! permissionless and derived from the seed alone, so anyone can predict it.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke;
+
+/// Anchor discriminator of the oracle's `request` instruction.
+pub const REQUEST_DISCRIMINATOR: [u8; 8] = [46, 101, 67, 11, 76, 137, 12, 173];
+/// Anchor discriminator of the oracle's `RandomnessAccount`.
+pub const RANDOMNESS_DISCRIMINATOR: [u8; 8] = [45, 94, 173, 50, 141, 108, 112, 51];
+
+const SEED_LENGTH: usize = 32;
+const PUBLIC_KEY_LENGTH: usize = 32;
+const RANDOMNESS_LENGTH: usize = 64;
+
+/// Asks `vrf_program` for randomness over `seed`. The oracle creates `randomness` and
+/// `payer` funds it.
+pub fn request<'info>(
+    vrf_program: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    randomness: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    seed: [u8; 32],
+) -> Result<()> {
+    let mut data = REQUEST_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&seed);
+
+    invoke(
+        &Instruction {
+            program_id: vrf_program.key(),
+            accounts: vec![
+                AccountMeta::new(payer.key(), true),
+                AccountMeta::new(randomness.key(), false),
+                AccountMeta::new_readonly(system_program.key(), false),
+            ],
+            data,
+        },
+        &[payer.clone(), randomness.clone(), system_program.clone(), vrf_program.clone()],
+    )?;
+
+    Ok(())
+}
+
+/// A randomness account as the oracle writes it.
+pub struct Randomness {
+    pub seed: [u8; 32],
+    pub requester: Pubkey,
+    pub randomness: [u8; 64],
+}
+
+impl Randomness {
+    /// Reads `account`, `None` if it doesn't hold a randomness account. The caller checks
+    /// that the configured oracle owns it.
+    pub fn load(account: &AccountInfo) -> Option<Self> {
+        let data = account.try_borrow_data().ok()?;
+        let data = data.strip_prefix(&RANDOMNESS_DISCRIMINATOR)?;
+        if data.len() < SEED_LENGTH + PUBLIC_KEY_LENGTH + RANDOMNESS_LENGTH {
+            return None;
+        }
+
+        let (seed, data) = data.split_at(SEED_LENGTH);
+        let (requester, data) = data.split_at(PUBLIC_KEY_LENGTH);
+        let randomness = &data[..RANDOMNESS_LENGTH];
+
+        Some(Randomness {
+            seed: seed.try_into().ok()?,
+            requester: Pubkey::try_from(requester).ok()?,
+            randomness: randomness.try_into().ok()?,
+        })
+    }
+
+    /// The oracle leaves the randomness zeroed until it fulfills the request.
+    pub fn is_fulfilled(&self) -> bool {
+        self.randomness != [0u8; 64]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Discriminator;
+    use mock_vrf::RandomnessAccount;
+
+    #[test]
+    fn discriminators_match_mock_vrf() {
+        assert_eq!(REQUEST_DISCRIMINATOR, mock_vrf::instruction::Request::DISCRIMINATOR);
+        assert_eq!(RANDOMNESS_DISCRIMINATOR, RandomnessAccount::DISCRIMINATOR);
+    }
+
+    #[test]
+    fn loads_mock_vrf_accounts() {
+        let seed = [7u8; 32];
+        let requester = Pubkey::new_unique();
+        let account = RandomnessAccount {
+            seed,
+            requester,
+            randomness: mock_vrf::derive_randomness(&seed),
+            bump: 255,
+        };
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let info = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &mock_vrf::ID, false, 0);
+        let randomness = Randomness::load(&info).unwrap();
+
+        assert_eq!(randomness.seed, seed);
+        assert_eq!(randomness.requester, requester);
+        assert_eq!(randomness.randomness, mock_vrf::derive_randomness(&seed));
+        assert!(randomness.is_fulfilled());
+
+        let mut short = data[..40].to_vec();
+        let info = AccountInfo::new(&key, false, false, &mut lamports, &mut short, &mock_vrf::ID, false, 0);
+        assert!(Randomness::load(&info).is_none());
+    }
+}
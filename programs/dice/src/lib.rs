@@ -1,8 +1,14 @@
 use anchor_lang::prelude::*;
-use arrayref::array_ref;
 use solana_program::sysvar::slot_hashes;
 use solana_program::program::{invoke, invoke_signed};
 use solana_program::system_instruction::{transfer, create_account};
+use arcade_rng::{derive_seed, hash, uniform, SlotHashesData, DICE_DOMAIN};
+use arcade_vrf::Randomness;
+use anchor_spl::associated_token::AssociatedToken;
+use math::{get_available_balance, get_fee, get_max_bet, get_max_payout, get_min_bet, get_multiplier_bp, get_payout, get_shares_for_deposit, get_threshold_bp, get_withdrawal_for_shares};
+use anchor_spl::token_interface::{self, Burn, Mint, MintTo, TokenAccount, TokenInterface, TransferChecked};
+
+pub mod math;
 
 declare_id!("7Ah8WAJw7CDxwbPQono7rKaRAmZ4ymjguouz1CfHScXY");
 
@@ -10,41 +16,109 @@ const DISCRIMINATOR_LENGTH: usize = 8;
 const U64_LENGTH: usize = 8;
 const PUBLIC_KEY_LENGTH: usize = 32;
 const U8_LENGTH: usize = 1;
-const TIMESTAMP_LENGTH: usize = 8;
+const U16_LENGTH: usize = 2;
+/// Number of slots between `commit_roll` and the slot whose hash settles the bet.
+pub const COMMIT_SLOT_DELAY: u64 = 2;
+/// Slots the house has to reveal a `roll_dice_fair` bet before the player can claim it as a win.
+pub const REVEAL_TIMEOUT_SLOTS: u64 = 150;
+/// Number of recent rolls kept in a player's history.
+pub const HISTORY_LENGTH: usize = 10;
+/// Rolls are uniform on `0..ROLL_OUTCOMES`.
+pub const ROLL_OUTCOMES: u64 = 10_000;
+/// Roll recorded in the history for a committed bet that expired before it was settled,
+/// or a fair roll the house didn't reveal in time.
+pub const EXPIRED_ROLL_BP: u64 = 10_000;
+/// Most rolls a single `roll_dice_batch` takes, keeps the batch within compute and log limits.
+pub const MAX_BATCH_ROLLS: usize = 10;
+/// Bounds for `edge_bp`, an edge of 0 gives the house nothing to win.
+pub const MIN_EDGE_BP: u64 = 1;
+pub const MAX_EDGE_BP: u64 = 10_000;
+pub const MIN_RATIO: u64 = 1;
+/// Bounds for the multiplier range, below 1x `get_threshold_bp` goes above 10_000 and every roll wins.
+pub const MIN_MULTIPLIER_BP: u64 = 10_000;
+pub const MAX_MULTIPLIER_BP: u64 = 1_000_000;
+/// Number of extra fee recipients a reserve can pay besides `house`.
+pub const MAX_FEE_RECIPIENTS: usize = 3;
+/// Cap on the share of a lost bet paid out as fees, the rest stays in the reserve.
+pub const MAX_TOTAL_FEE_BP: u64 = 2_500;
+/// Decimals of the lp share mint, shares start out 1:1 with lamports.
+pub const LP_DECIMALS: u8 = 9;
+/// Seconds after `unlock_time` that an lp withdrawal can be claimed, afterwards it has to be requested again.
+pub const LP_CLAIM_WINDOW: i64 = 3_600;
+/// Deployer key accepted as authority of every reserve until the reserve revokes it.
 pub const UPDATE_AUTHORITY: Pubkey = solana_program::pubkey!("73NW3yAewSmh8FHpk4fXiaZ7CVawnedakrnpHYckTFoB");
 
 #[program]
 pub mod dice {
-    use std::hash::Hash;
-    use sha3::{Digest, Sha3_256};
     use super::*;
 
-    pub fn change_config(ctx: Context<ChangeConfig>, edge_bp: u64, ratio: u64, house: Pubkey) -> Result<()> {
+    pub fn change_config(ctx: Context<ChangeConfig>, edge_bp: u64, ratio: u64) -> Result<()> {
         let reserve = &mut ctx.accounts.reserve;
+        validate_config(edge_bp, ratio, reserve.house)?;
 
         reserve.edge_bp = edge_bp;
         reserve.ratio = ratio;
+
+        emit!(ConfigChanged {
+            authority: ctx.accounts.authority.key(),
+            edge_bp,
+            ratio,
+            house: reserve.house,
+            house_fee_bp: reserve.house_fee_bp,
+        });
+
+        Ok(())
+    }
+
+    /// Redirects the house fee, so it belongs to the treasury admin rather than the config admin.
+    pub fn set_house(ctx: Context<ChangeTreasury>, house: Pubkey, house_fee_bp: u64) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        validate_config(reserve.edge_bp, reserve.ratio, house)?;
+        validate_fees(house_fee_bp, &reserve.fee_recipients[..reserve.fee_recipient_count as usize])?;
+
         reserve.house = house;
+        reserve.house_fee_bp = house_fee_bp;
+
+        emit!(ConfigChanged {
+            authority: ctx.accounts.authority.key(),
+            edge_bp: reserve.edge_bp,
+            ratio: reserve.ratio,
+            house,
+            house_fee_bp,
+        });
 
         Ok(())
     }
 
-    pub fn setup_dice(ctx: Context<SetupDice>, edge_bp: u64, ratio: u64, house: Pubkey, update_authority: Pubkey, initial_funds: u64, bump: u8) -> Result<()> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn setup_dice(ctx: Context<SetupDice>, table_id: u16, edge_bp: u64, ratio: u64, house: Pubkey, house_fee_bp: u64, update_authority: Pubkey, initial_funds: u64, bump: u8) -> Result<()> {
         let creator = &mut ctx.accounts.creator;
         let reserve = &mut ctx.accounts.reserve;
         let reserve_key = &mut ctx.accounts.reserve_key;
+        validate_config(edge_bp, ratio, house)?;
+        validate_fees(house_fee_bp, &[])?;
 
         reserve.edge_bp = edge_bp;
         reserve.ratio = ratio;
         reserve.house = house;
+        reserve.house_fee_bp = house_fee_bp;
+        reserve.min_multiplier_bp = MIN_MULTIPLIER_BP;
+        reserve.max_multiplier_bp = MAX_MULTIPLIER_BP;
+        reserve.min_bet = 1;
         reserve.reserve_key = reserve_key.key();
         reserve.update_authority = update_authority;
+        reserve.treasury_admin = update_authority;
+        reserve.pauser = update_authority;
         reserve.bump = ctx.bumps.reserve;
+        reserve.table_id = table_id;
+        reserve.mint = Pubkey::default();
+        reserve.decimals = 9;
 
         let rent = Rent::get()?;
         let minimum_balance = rent.minimum_balance(0); // No data, only lamports
 
-        let seeds = &[b"reserve-key-1".as_ref(), &[bump]];
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
         let signer_seeds = &[&seeds[..]];
 
         // create reserve key account
@@ -77,10 +151,596 @@ pub mod dice {
             ],
         )?;
 
+        emit!(ConfigChanged {
+            authority: update_authority,
+            edge_bp,
+            ratio,
+            house,
+            house_fee_bp,
+        });
+        emit!(ReserveFunded {
+            funder: creator.key(),
+            reserve_key: reserve_key.key(),
+            amount: initial_funds,
+            balance: reserve_key.lamports(),
+        });
+
+        Ok(())
+    }
+
+    /// Moves the reserve from before tables, seeded by `b"reserve-1"` alone, to `table_id`.
+    /// The legacy `reserve_key` is emptied into the table's and the legacy reserve is closed to the authority.
+    pub fn migrate_legacy_reserve(ctx: Context<MigrateLegacyReserve>, table_id: u16) -> Result<()> {
+        let legacy_reserve = ctx.accounts.legacy_reserve.to_account_info();
+        let legacy_reserve_key = &ctx.accounts.legacy_reserve_key;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let authority = &ctx.accounts.authority;
+
+        let legacy = LegacyReserve::load(&legacy_reserve)?;
+        require!(authority.key() == legacy.update_authority || authority.key() == UPDATE_AUTHORITY, ErrorCode::NotLegacyAuthority);
+
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.edge_bp = legacy.edge_bp;
+        reserve.ratio = legacy.ratio;
+        reserve.house = legacy.house;
+        reserve.min_multiplier_bp = MIN_MULTIPLIER_BP;
+        reserve.max_multiplier_bp = MAX_MULTIPLIER_BP;
+        reserve.min_bet = 1;
+        reserve.reserve_key = reserve_key.key();
+        reserve.update_authority = legacy.update_authority;
+        reserve.treasury_admin = legacy.update_authority;
+        reserve.pauser = legacy.update_authority;
+        reserve.bump = ctx.bumps.reserve;
+        reserve.table_id = table_id;
+        reserve.mint = Pubkey::default();
+        reserve.decimals = 9;
+
+        // the whole balance moves, rent included, the new reserve_key is created by the transfer
+        let amount = legacy_reserve_key.lamports();
+        let seeds = &[b"reserve-key-1".as_ref(), &[ctx.bumps.legacy_reserve_key]];
+        let signer_seeds = &[&seeds[..]];
+        invoke_signed(
+            &transfer(
+                legacy_reserve_key.key,
+                reserve_key.key,
+                amount,
+            ),
+            &[
+                legacy_reserve_key.to_account_info(),
+                reserve_key.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
+
+        let legacy_rent = legacy_reserve.lamports();
+        legacy_reserve.sub_lamports(legacy_rent)?;
+        authority.add_lamports(legacy_rent)?;
+        legacy_reserve.assign(&System::id());
+        legacy_reserve.realloc(0, false)?;
+
+        emit!(LegacyReserveMigrated {
+            authority: authority.key(),
+            table_id,
+            reserve_key: reserve_key.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    pub fn deposit_reserve(ctx: Context<DepositReserve>, amount: u64) -> Result<()> {
+        let depositor = &ctx.accounts.depositor;
+        let reserve_key = &ctx.accounts.reserve_key;
+
+        invoke(
+            &transfer(
+                depositor.key,
+                reserve_key.key,
+                amount,
+            ),
+            &[
+                depositor.to_account_info(),
+                reserve_key.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        emit!(ReserveFunded {
+            funder: depositor.key(),
+            reserve_key: reserve_key.key(),
+            amount,
+            balance: reserve_key.lamports(),
+        });
+
+        Ok(())
+    }
+
+    /// Replaces the extra fee recipients, lost bets pay them in order after `house`.
+    pub fn set_fee_recipients(ctx: Context<ChangeTreasury>, fee_recipients: Vec<FeeRecipient>) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        require!(fee_recipients.len() <= MAX_FEE_RECIPIENTS, ErrorCode::TooManyFeeRecipients);
+        validate_fees(reserve.house_fee_bp, &fee_recipients)?;
+
+        reserve.fee_recipients = [FeeRecipient::default(); MAX_FEE_RECIPIENTS];
+        reserve.fee_recipients[..fee_recipients.len()].copy_from_slice(&fee_recipients);
+        reserve.fee_recipient_count = fee_recipients.len() as u8;
+
+        emit!(FeeRecipientsChanged {
+            authority: ctx.accounts.authority.key(),
+            house_fee_bp: reserve.house_fee_bp,
+            fee_recipients,
+        });
+
+        Ok(())
+    }
+
+    /// Proposing `Pubkey::default()` cancels a pending transfer.
+    pub fn propose_authority(ctx: Context<ChangeConfig>, new_authority: Pubkey) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.pending_authority = new_authority;
+
+        emit!(AuthorityProposed {
+            authority: reserve.update_authority,
+            pending_authority: new_authority,
+        });
+
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        let old_authority = reserve.update_authority;
+
+        reserve.update_authority = ctx.accounts.new_authority.key();
+        reserve.pending_authority = Pubkey::default();
+
+        emit!(AuthorityChanged {
+            old_authority,
+            new_authority: reserve.update_authority,
+        });
+
+        Ok(())
+    }
+
+    pub fn revoke_backdoor_authority(ctx: Context<ChangeConfig>) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.backdoor_revoked = true;
+
+        emit!(BackdoorAuthorityRevoked {
+            authority: ctx.accounts.authority.key(),
+        });
+
+        Ok(())
+    }
+
+    /// The treasury admin and pauser can be handed to separate keys, e.g. a multisig vault.
+    /// Both the config admin and the current treasury admin have to sign, so neither can
+    /// take the other's role alone.
+    pub fn set_roles(ctx: Context<ChangeRoles>, treasury_admin: Pubkey, pauser: Pubkey) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.treasury_admin = treasury_admin;
+        reserve.pauser = pauser;
+
+        emit!(RolesChanged {
+            config_admin: reserve.update_authority,
+            treasury_admin,
+            pauser,
+        });
+
+        Ok(())
+    }
+
+    /// Stops new bets and liquidity deposits, bets already placed can still be settled.
+    pub fn set_paused(ctx: Context<PauseReserve>, paused: bool) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.paused = paused;
+
+        emit!(PausedChanged {
+            authority: ctx.accounts.authority.key(),
+            mint: reserve.mint,
+            paused,
+        });
+
+        Ok(())
+    }
+
+    /// A `drawdown_bp` of 0 turns the circuit breaker off.
+    pub fn set_circuit_breaker(ctx: Context<ChangeConfig>, drawdown_bp: u64, window_slots: u64) -> Result<()> {
+        require!(drawdown_bp <= 10_000 && (drawdown_bp == 0 || window_slots > 0), ErrorCode::InvalidCircuitBreaker);
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.breaker_drawdown_bp = drawdown_bp;
+        reserve.breaker_window_slots = window_slots;
+        // start a fresh window with the next roll
+        reserve.breaker_window_start = 0;
+
+        emit!(CircuitBreakerChanged {
+            authority: ctx.accounts.authority.key(),
+            drawdown_bp,
+            window_slots,
+        });
+
+        Ok(())
+    }
+
+    /// The oracle has to serve the `arcade_vrf` interface and derive its randomness from
+    /// something the requester can't predict, `mock_vrf` derives it from the seed alone and
+    /// is only fit for tests.
+    pub fn set_vrf_program(ctx: Context<ChangeConfig>, vrf_program: Pubkey) -> Result<()> {
+        ctx.accounts.reserve.vrf_program = vrf_program;
+
+        emit!(VrfProgramChanged {
+            authority: ctx.accounts.authority.key(),
+            vrf_program,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_instant_rolls(ctx: Context<ChangeConfig>, instant_rolls: bool) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.instant_rolls = instant_rolls;
+
+        emit!(InstantRollsChanged {
+            authority: ctx.accounts.authority.key(),
+            mint: reserve.mint,
+            instant_rolls,
+        });
+
+        Ok(())
+    }
+
+    /// A limit of 0 turns it off.
+    pub fn set_exposure_limits(ctx: Context<ChangeConfig>, max_net_payout: u64, exposure_window_slots: u64, max_player_payout: u64) -> Result<()> {
+        require!(max_net_payout == 0 || exposure_window_slots > 0, ErrorCode::InvalidExposureLimits);
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.max_net_payout = max_net_payout;
+        reserve.exposure_window_slots = exposure_window_slots;
+        reserve.max_player_payout = max_player_payout;
+
+        emit!(ExposureLimitsChanged {
+            authority: ctx.accounts.authority.key(),
+            max_net_payout,
+            exposure_window_slots,
+            max_player_payout,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_bet_limits(ctx: Context<ChangeConfig>, min_multiplier_bp: u64, max_multiplier_bp: u64, min_bet: u64) -> Result<()> {
+        require!(
+            MIN_MULTIPLIER_BP <= min_multiplier_bp && min_multiplier_bp <= max_multiplier_bp && max_multiplier_bp <= MAX_MULTIPLIER_BP,
+            ErrorCode::InvalidMultiplierRange
+        );
+        require!(min_bet > 0, ErrorCode::InvalidMinBet);
+
+        let reserve = &mut ctx.accounts.reserve;
+        reserve.min_multiplier_bp = min_multiplier_bp;
+        reserve.max_multiplier_bp = max_multiplier_bp;
+        reserve.min_bet = min_bet;
+
+        emit!(BetLimitsChanged {
+            authority: ctx.accounts.authority.key(),
+            min_multiplier_bp,
+            max_multiplier_bp,
+            min_bet,
+        });
+
+        Ok(())
+    }
+
+    /// Share of the house fee, in bp, that goes to the player's referrer on a lost `roll_dice`.
+    pub fn set_referral_fee(ctx: Context<ChangeTreasury>, referral_fee_bp: u64) -> Result<()> {
+        require!(referral_fee_bp <= 10_000, ErrorCode::BadReferralFee);
+        ctx.accounts.reserve.referral_fee_bp = referral_fee_bp;
+
+        emit!(ReferralFeeChanged {
+            authority: ctx.accounts.authority.key(),
+            referral_fee_bp,
+        });
+
+        Ok(())
+    }
+
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referrer = &mut ctx.accounts.referrer;
+        referrer.referrer = ctx.accounts.authority.key();
+        referrer.bump = ctx.bumps.referrer;
+
+        Ok(())
+    }
+
+    /// Referral fees accumulate as lamports in the referrer account, everything above rent is claimable.
+    pub fn claim_referral(ctx: Context<ClaimReferral>) -> Result<()> {
+        let referrer = &ctx.accounts.referrer.to_account_info();
+        let authority = &ctx.accounts.authority.to_account_info();
+
+        let rent = Rent::get()?;
+        let amount = referrer.lamports().checked_sub(rent.minimum_balance(Referrer::LEN)).ok_or(ErrorCode::MathOverflow)?;
+
+        **referrer.try_borrow_mut_lamports()? -= amount;
+        **authority.try_borrow_mut_lamports()? += amount;
+
+        emit!(ReferralClaimed {
+            referrer: authority.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// The timelock can only be lengthened, otherwise it could be dropped right before a withdrawal.
+    pub fn set_withdraw_policy(ctx: Context<ChangeConfig>, withdraw_floor: u64, withdraw_timelock: i64) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        require!(withdraw_timelock >= reserve.withdraw_timelock, ErrorCode::TimelockDecrease);
+
+        reserve.withdraw_floor = withdraw_floor;
+        reserve.withdraw_timelock = withdraw_timelock;
+
+        emit!(WithdrawPolicyChanged {
+            authority: ctx.accounts.authority.key(),
+            withdraw_floor,
+            withdraw_timelock,
+        });
+
+        Ok(())
+    }
+
+    /// Queues a withdrawal when the reserve has a timelock, an amount of 0 cancels it.
+    pub fn request_withdrawal(ctx: Context<ChangeTreasury>, amount: u64) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        let clock = Clock::get()?;
+
+        reserve.pending_withdrawal = amount;
+        reserve.withdrawal_unlock_time = clock.unix_timestamp.checked_add(reserve.withdraw_timelock).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(WithdrawalRequested {
+            amount,
+            unlock_time: reserve.withdrawal_unlock_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn withdraw_reserve(ctx: Context<WithdrawReserve>, amount: u64, bump: u8) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let authority = &ctx.accounts.authority;
+
+        // once the reserve is a house pool the owner exits through their shares like everyone else
+        require!(reserve.lp_mint == Pubkey::default(), ErrorCode::LpPoolActive);
+        reserve.take_pending_withdrawal(amount)?;
+
+        let rent = Rent::get()?;
+        let minimum_balance = rent.minimum_balance(0);
+        let balance = reserve_key.lamports();
+        let required = minimum_balance
+            .checked_add(reserve.withdraw_floor)
+            .and_then(|required| required.checked_add(reserve.pending_payout))
+            .and_then(|required| required.checked_add(amount))
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(balance >= required, ErrorCode::BelowWithdrawFloor);
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        invoke_signed(
+            &transfer(
+                reserve_key.key,
+                authority.key,
+                amount,
+            ),
+            &[
+                reserve_key.to_account_info(),
+                authority.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
+
+        emit!(ReserveWithdrawn {
+            authority: authority.key(),
+            amount,
+            balance: reserve_key.lamports(),
+        });
+
+        Ok(())
+    }
+
+    /// Turns the reserve into a house pool, the authority receives shares for the current balance.
+    pub fn init_lp_pool(ctx: Context<InitLpPool>, lp_cooldown: i64) -> Result<()> {
+        require!(lp_cooldown >= 0, ErrorCode::InvalidCooldown);
+        let reserve = &mut ctx.accounts.reserve;
+        let lp_mint = &ctx.accounts.lp_mint;
+
+        reserve.lp_mint = lp_mint.key();
+        reserve.lp_cooldown = lp_cooldown;
+
+        let rent = Rent::get()?;
+        let shares = reserve.get_lp_value_ceiling(ctx.accounts.reserve_key.lamports(), rent.minimum_balance(0))?;
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-1".as_ref(), table_id.as_ref(), &[reserve.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: lp_mint.to_account_info(),
+                    to: ctx.accounts.authority_shares.to_account_info(),
+                    authority: reserve.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            shares,
+        )?;
+
+        emit!(LiquidityDeposited {
+            provider: ctx.accounts.authority.key(),
+            amount: shares,
+            shares,
+        });
+
+        Ok(())
+    }
+
+    /// Shares are priced against the reserve balance, so they gain from lost bets and lose from won ones.
+    /// Deposits buy in as if every unsettled bet loses and can't be withdrawn for `lp_cooldown` seconds.
+    pub fn deposit_liquidity(ctx: Context<DepositLiquidity>, amount: u64) -> Result<()> {
+        let reserve = &ctx.accounts.reserve;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let provider = &ctx.accounts.provider;
+        let lp_mint = &ctx.accounts.lp_mint;
+        let position = &mut ctx.accounts.lp_position;
+        let clock = Clock::get()?;
+        require!(!reserve.paused, ErrorCode::Paused);
+
+        let rent = Rent::get()?;
+        let pool_value = reserve.get_lp_value_ceiling(reserve_key.lamports(), rent.minimum_balance(0))?;
+        let shares = get_shares_for_deposit(amount, lp_mint.supply, pool_value)?;
+        require!(shares > 0, ErrorCode::DepositTooSmall);
+
+        position.provider = provider.key();
+        position.deposit_unlock_time = clock.unix_timestamp.checked_add(reserve.lp_cooldown).ok_or(ErrorCode::MathOverflow)?;
+        position.bump = ctx.bumps.lp_position;
+
+        invoke(
+            &transfer(
+                provider.key,
+                reserve_key.key,
+                amount,
+            ),
+            &[
+                provider.to_account_info(),
+                reserve_key.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-1".as_ref(), table_id.as_ref(), &[reserve.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: lp_mint.to_account_info(),
+                    to: ctx.accounts.provider_shares.to_account_info(),
+                    authority: reserve.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            shares,
+        )?;
+
+        emit!(LiquidityDeposited {
+            provider: provider.key(),
+            amount,
+            shares,
+        });
+
+        Ok(())
+    }
+
+    /// Starts the cooldown, the shares stay exposed to rolls until they are redeemed.
+    /// A new request replaces the previous one and restarts the cooldown, an unclaimed request
+    /// expires `LP_CLAIM_WINDOW` seconds after it unlocks. The withdrawal pays out the lower of
+    /// its value now and at the claim, so waiting for a better moment to claim gains nothing.
+    pub fn request_lp_withdrawal(ctx: Context<RequestLpWithdrawal>, shares: u64) -> Result<()> {
+        let reserve = &ctx.accounts.reserve;
+        let position = &mut ctx.accounts.lp_position;
+        let clock = Clock::get()?;
+
+        require!(shares <= ctx.accounts.provider_shares.amount, ErrorCode::InsufficientShares);
+        require!(clock.unix_timestamp >= position.deposit_unlock_time, ErrorCode::DepositLocked);
+
+        let rent = Rent::get()?;
+        let pool_value = reserve.get_lp_value_floor(ctx.accounts.reserve_key.lamports(), rent.minimum_balance(0))?;
+
+        position.provider = ctx.accounts.provider.key();
+        position.pending_shares = shares;
+        position.pending_amount = get_withdrawal_for_shares(shares, ctx.accounts.lp_mint.supply, pool_value)?;
+        position.unlock_time = clock.unix_timestamp.checked_add(reserve.lp_cooldown).ok_or(ErrorCode::MathOverflow)?;
+        position.bump = ctx.bumps.lp_position;
+
+        emit!(LpWithdrawalRequested {
+            provider: position.provider,
+            shares,
+            unlock_time: position.unlock_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn withdraw_liquidity(ctx: Context<WithdrawLiquidity>, bump: u8) -> Result<()> {
+        let reserve = &ctx.accounts.reserve;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let provider = &ctx.accounts.provider;
+        let lp_mint = &ctx.accounts.lp_mint;
+        let position = &mut ctx.accounts.lp_position;
+        let clock = Clock::get()?;
+
+        let shares = position.pending_shares;
+        require!(shares > 0, ErrorCode::NoPendingWithdrawal);
+        require!(clock.unix_timestamp >= position.unlock_time, ErrorCode::WithdrawalLocked);
+        let expiry = position.unlock_time.checked_add(LP_CLAIM_WINDOW).ok_or(ErrorCode::MathOverflow)?;
+        require!(clock.unix_timestamp < expiry, ErrorCode::WithdrawalExpired);
+        require!(shares <= ctx.accounts.provider_shares.amount, ErrorCode::InsufficientShares);
+
+        let rent = Rent::get()?;
+        let pool_value = reserve.get_lp_value_floor(reserve_key.lamports(), rent.minimum_balance(0))?;
+        let amount = get_withdrawal_for_shares(shares, lp_mint.supply, pool_value)?.min(position.pending_amount);
+        position.pending_shares = 0;
+        position.pending_amount = 0;
+
+        token_interface::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: lp_mint.to_account_info(),
+                    from: ctx.accounts.provider_shares.to_account_info(),
+                    authority: provider.to_account_info(),
+                },
+            ),
+            shares,
+        )?;
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        invoke_signed(
+            &transfer(
+                reserve_key.key,
+                provider.key,
+                amount,
+            ),
+            &[
+                reserve_key.to_account_info(),
+                provider.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
+
+        emit!(LiquidityWithdrawn {
+            provider: provider.key(),
+            shares,
+            amount,
+            balance: reserve_key.lamports(),
+        });
+
         Ok(())
     }
 
-    pub fn roll_dice(ctx: Context<RollDice>, user_seed: u64, multiplier_bp: u64, bet_size: u64, bump: u8) -> Result<()> {
+    pub fn roll_dice<'info>(ctx: Context<'_, '_, 'info, 'info, RollDice<'info>>, user_seed: u64, bet_type: BetType, bet_size: u64, bump: u8) -> Result<()> {
+        let (multiplier_bp, threshold_bp) = bet_type.get_odds(ctx.accounts.reserve.edge_bp)?;
+        let referred = bind_referrer(&mut ctx.accounts.player_stats.referrer, ctx.accounts.player.key(), &mut ctx.accounts.referrer)?;
+        let booked_payout = book_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.bumps.player_exposure, ctx.accounts.player.key(), bet_size, multiplier_bp)?;
         let player = &ctx.accounts.player;
         let reserve = &ctx.accounts.reserve;
         let house = &ctx.accounts.house;
@@ -91,11 +751,11 @@ pub mod dice {
         let minimum_balance = rent.minimum_balance(0); // Account needs to have the minimum balance for rent-exemption
         let balance: u64 = reserve_key.to_account_info().lamports();
 
-        let minimum_balance_reserve = rent.minimum_balance(Reserve::LEN);
-        let reserve_balance = reserve.to_account_info().lamports();
         let house_balance = house.to_account_info().lamports();
 
-        let max_bet = get_max_bet(reserve, balance - minimum_balance, multiplier_bp);
+        validate_bet(reserve, multiplier_bp, bet_size)?;
+
+        let max_bet = get_max_bet(reserve.get_free_balance(balance, minimum_balance)?, multiplier_bp, reserve.ratio)?;
         require!(max_bet >= bet_size, ErrorCode::BetTooBig);
 
         // transfer sol from player to reserve
@@ -112,148 +772,3208 @@ pub mod dice {
             ],
         )?;
 
-        let seeds = &[b"reserve-key-1".as_ref(), &[bump]];
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
         let signer_seeds = &[&seeds[..]];
 
         let data = slot_hashes.data.borrow();
-        let most_recent = array_ref![data, 12, 8];
+        let (recent_slot, recent_hash) = SlotHashesData::new(&data)
+            .and_then(|slot_hashes| slot_hashes.most_recent())
+            .ok_or(ErrorCode::InvalidSlotHashes)?;
         let clock = Clock::get()?;
         let timestamp = clock.unix_timestamp;
 
         // generate random number
-        let mut hasher = Sha3_256::new();
-        hasher.update(user_seed.to_le_bytes());
-        hasher.update(timestamp.to_le_bytes());
-        hasher.update(*most_recent);
-        let hashed_data = hasher.finalize();
-        let hash_bytes = hashed_data[..8].try_into().map_err(|_| ErrorCode::HashConversionFailed)?;
-        let winning_number = u64::from_le_bytes(hash_bytes);
-        let p = winning_number % 10_000; // 1m bp == 100
-        let threshold_bp = get_threshold_bp(reserve, multiplier_bp);
-
-        msg!("p: {:?} - threshold_bp: {:?} - edge_bp: {:?} - multiplier_bp: {:?}", p, threshold_bp, reserve.edge_bp, multiplier_bp);
-        msg!("Hash inputs: user_seed {:?} - timestamp {:?} - most_recent_blockhash[12:20]: {:?}", user_seed, timestamp, most_recent);
+        let seed = derive_seed(DICE_DOMAIN, &[&user_seed.to_le_bytes(), &timestamp.to_le_bytes(), &recent_hash]);
+        let p = get_random_bp(seed)?;
+        let outcome = bet_type.get_outcome(p);
+
+        msg!("p: {:?} - threshold_bp: {:?} - edge_bp: {:?} - multiplier_bp: {:?} - bet_type: {:?}", p, threshold_bp, reserve.edge_bp, multiplier_bp, bet_type);
+        msg!("Hash inputs: user_seed {:?} - timestamp {:?} - slot {:?} - slot_hash {:?}", user_seed, timestamp, recent_slot, recent_hash);
         msg!("Reserve key balance: {:?} - minimum_balance: {:?}", balance, minimum_balance);
         msg!("House rent: {:?} - house balance: {:?}", minimum_balance, house_balance);
 
-        if p < threshold_bp {
-            // transfer sol from reserve to player
-            msg!("Win! p < threshold {:?} < {:?}", p, threshold_bp);
-            invoke_signed(
-                &transfer(
-                    &reserve_key.key(),
-                    &player.key(),
-                    (bet_size * multiplier_bp) / 10_000,
-                ),
-                &[
-                    player.to_account_info(),
-                    reserve_key.to_account_info(),
+        let balance_before = reserve_key.lamports();
+        let payout = pay_out(
+            reserve,
+            outcome,
+            threshold_bp,
+            bet_size,
+            multiplier_bp,
+            player.to_account_info(),
+            house.to_account_info(),
+            ctx.accounts.referrer.as_ref().filter(|_| referred).map(|referrer| referrer.to_account_info()),
+            get_fee_accounts(reserve, ctx.remaining_accounts)?,
+            reserve_key.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            signer_seeds,
+        )?;
+
+        if referred && outcome >= threshold_bp {
+            let referrer = ctx.accounts.referrer.as_mut().unwrap();
+            referrer.total_earned = referrer.total_earned.checked_add(get_referral_fee(reserve, bet_size)?).ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let player_stats = &mut ctx.accounts.player_stats;
+        player_stats.player = player.key();
+        player_stats.bump = ctx.bumps.player_stats;
+        player_stats.record_roll(bet_size, multiplier_bp, p, payout, clock.slot)?;
+
+        emit!(DiceRolled {
+            player: player.key(),
+            table_id: reserve.table_id,
+            mint: reserve.mint,
+            kind: BetKind::Instant,
+            bet_type,
+            user_seed,
+            multiplier_bp,
+            bet_size,
+            edge_bp: reserve.edge_bp,
+            threshold_bp,
+            randomness: seed,
+            roll_bp: p,
+            payout,
+            slot: clock.slot,
+        });
+
+        check_circuit_breaker(&mut ctx.accounts.reserve, balance_before, ctx.accounts.reserve_key.lamports(), clock.slot)?;
+        if payout == 0 {
+            release_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, booked_payout);
+        }
+
+        Ok(())
+    }
+
+    /// Rolls every bet in `bets` with its own seed derived from one slot hash, taking the stakes in
+    /// and paying the wins and fees out in one transfer each.
+    pub fn roll_dice_batch<'info>(ctx: Context<'_, '_, 'info, 'info, RollDice<'info>>, user_seed: u64, bets: Vec<BatchBet>, bump: u8) -> Result<()> {
+        require!((1..=MAX_BATCH_ROLLS).contains(&bets.len()), ErrorCode::InvalidBatchSize);
+        let referred = bind_referrer(&mut ctx.accounts.player_stats.referrer, ctx.accounts.player.key(), &mut ctx.accounts.referrer)?;
+        let mut odds = Vec::with_capacity(bets.len());
+        for bet in &bets {
+            let (multiplier_bp, threshold_bp) = bet.bet_type.get_odds(ctx.accounts.reserve.edge_bp)?;
+            let booked_payout = book_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.bumps.player_exposure, ctx.accounts.player.key(), bet.bet_size, multiplier_bp)?;
+            odds.push((multiplier_bp, threshold_bp, booked_payout));
+        }
+        let player = &ctx.accounts.player;
+        let reserve = &ctx.accounts.reserve;
+        let house = &ctx.accounts.house;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let slot_hashes = &ctx.accounts.slot_hashes;
+
+        let rent = Rent::get()?;
+        let minimum_balance = rent.minimum_balance(0);
+        let balance: u64 = reserve_key.to_account_info().lamports();
+
+        let mut total_bet: u64 = 0;
+        let mut max_total_payout: u64 = 0;
+        for (bet, &(multiplier_bp, _, booked_payout)) in bets.iter().zip(&odds) {
+            validate_bet(reserve, multiplier_bp, bet.bet_size)?;
+            total_bet = total_bet.checked_add(bet.bet_size).ok_or(ErrorCode::MathOverflow)?;
+            max_total_payout = max_total_payout.checked_add(booked_payout).ok_or(ErrorCode::MathOverflow)?;
+        }
+        // every roll of the batch may win, so the wins are capped together
+        let max_payout = get_max_payout(reserve.get_free_balance(balance, minimum_balance)?, reserve.ratio)?;
+        require!(max_payout >= max_total_payout, ErrorCode::BetTooBig);
+
+        // transfer the stakes of every roll from player to reserve
+        invoke(
+            &transfer(
+                player.key,
+                reserve_key.key,
+                total_bet,
+            ),
+            &[
+                player.to_account_info(),
+                reserve_key.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        let data = slot_hashes.data.borrow();
+        let (recent_slot, recent_hash) = SlotHashesData::new(&data)
+            .and_then(|slot_hashes| slot_hashes.most_recent())
+            .ok_or(ErrorCode::InvalidSlotHashes)?;
+        let clock = Clock::get()?;
+        let timestamp = clock.unix_timestamp;
+        let seed = derive_seed(DICE_DOMAIN, &[&user_seed.to_le_bytes(), &timestamp.to_le_bytes(), &recent_hash]);
+
+        msg!("Hash inputs: user_seed {:?} - timestamp {:?} - slot {:?} - slot_hash {:?}", user_seed, timestamp, recent_slot, recent_hash);
+        msg!("Reserve key balance: {:?} - minimum_balance: {:?}", balance, minimum_balance);
+
+        let mut rolls = Vec::with_capacity(bets.len());
+        let mut total_payout: u64 = 0;
+        let mut fees = LossFees::default();
+        for (index, (bet, &(multiplier_bp, threshold_bp, _))) in bets.iter().zip(&odds).enumerate() {
+            let p = get_random_bp(derive_seed(DICE_DOMAIN, &[&seed, &(index as u8).to_le_bytes()]))?;
+            let outcome = bet.bet_type.get_outcome(p);
+            let payout = if outcome < threshold_bp {
+                msg!("Win! p < threshold {:?} < {:?}", outcome, threshold_bp);
+                get_payout(bet.bet_size, multiplier_bp)?
+            } else {
+                msg!("Lose! p >= threshold {:?} >= {:?}", outcome, threshold_bp);
+                fees.add(&LossFees::new(reserve, bet.bet_size, referred)?)?;
+                0
+            };
+            total_payout = total_payout.checked_add(payout).ok_or(ErrorCode::MathOverflow)?;
+            rolls.push(BatchRoll {
+                bet_type: bet.bet_type,
+                multiplier_bp,
+                bet_size: bet.bet_size,
+                threshold_bp,
+                roll_bp: p,
+                payout,
+            });
+        }
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let balance_before = reserve_key.lamports();
+        if total_payout > 0 {
+            // transfer the wins of every roll from reserve to player
+            invoke_signed(
+                &transfer(
+                    reserve_key.key,
+                    player.key,
+                    total_payout,
+                ),
+                &[
+                    player.to_account_info(),
+                    reserve_key.to_account_info(),
                     ctx.accounts.system_program.to_account_info(),
                 ],
                 signer_seeds,
             )?;
+        }
+        if rolls.iter().any(|roll| roll.payout == 0) {
+            send_fees(
+                &fees,
+                house.to_account_info(),
+                ctx.accounts.referrer.as_ref().filter(|_| referred).map(|referrer| referrer.to_account_info()),
+                get_fee_accounts(reserve, ctx.remaining_accounts)?,
+                reserve_key.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                signer_seeds,
+            )?;
+        }
+
+        if referred {
+            let referrer = ctx.accounts.referrer.as_mut().unwrap();
+            referrer.total_earned = referrer.total_earned.checked_add(fees.referral).ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        let player_stats = &mut ctx.accounts.player_stats;
+        player_stats.player = player.key();
+        player_stats.bump = ctx.bumps.player_stats;
+        for roll in &rolls {
+            player_stats.record_roll(roll.bet_size, roll.multiplier_bp, roll.roll_bp, roll.payout, clock.slot)?;
+        }
+
+        emit!(DiceBatchRolled {
+            player: player.key(),
+            table_id: reserve.table_id,
+            mint: reserve.mint,
+            user_seed,
+            edge_bp: reserve.edge_bp,
+            randomness: seed,
+            total_bet,
+            total_payout,
+            rolls: rolls.clone(),
+            slot: clock.slot,
+        });
+
+        check_circuit_breaker(&mut ctx.accounts.reserve, balance_before, ctx.accounts.reserve_key.lamports(), clock.slot)?;
+        for (roll, &(_, _, booked_payout)) in rolls.iter().zip(&odds) {
+            if roll.payout == 0 {
+                release_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, booked_payout);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn setup_token_dice(ctx: Context<SetupTokenDice>, table_id: u16, edge_bp: u64, ratio: u64, house: Pubkey, house_fee_bp: u64, update_authority: Pubkey, initial_funds: u64) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        let mint = &ctx.accounts.mint;
+        validate_config(edge_bp, ratio, house)?;
+        validate_fees(house_fee_bp, &[])?;
+
+        reserve.edge_bp = edge_bp;
+        reserve.ratio = ratio;
+        reserve.house = house;
+        reserve.house_fee_bp = house_fee_bp;
+        reserve.min_multiplier_bp = MIN_MULTIPLIER_BP;
+        reserve.max_multiplier_bp = MAX_MULTIPLIER_BP;
+        reserve.reserve_key = ctx.accounts.vault.key();
+        reserve.update_authority = update_authority;
+        reserve.treasury_admin = update_authority;
+        reserve.pauser = update_authority;
+        reserve.bump = ctx.bumps.reserve;
+        reserve.table_id = table_id;
+        reserve.mint = mint.key();
+        reserve.decimals = mint.decimals;
+        reserve.min_bet = get_min_bet(reserve.decimals)?;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.creator_token_account.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.creator.to_account_info(),
+                },
+            ),
+            initial_funds,
+            mint.decimals,
+        )?;
+        ctx.accounts.vault.reload()?;
+
+        emit!(ConfigChanged {
+            authority: update_authority,
+            edge_bp,
+            ratio,
+            house,
+            house_fee_bp,
+        });
+        emit!(ReserveFunded {
+            funder: ctx.accounts.creator.key(),
+            reserve_key: ctx.accounts.vault.key(),
+            amount: initial_funds,
+            balance: ctx.accounts.vault.amount,
+        });
+
+        Ok(())
+    }
+
+    pub fn deposit_token_reserve(ctx: Context<DepositTokenReserve>, amount: u64) -> Result<()> {
+        let mint = &ctx.accounts.mint;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.depositor_token_account.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.depositor.to_account_info(),
+                },
+            ),
+            amount,
+            mint.decimals,
+        )?;
+        ctx.accounts.vault.reload()?;
+
+        emit!(ReserveFunded {
+            funder: ctx.accounts.depositor.key(),
+            reserve_key: ctx.accounts.vault.key(),
+            amount,
+            balance: ctx.accounts.vault.amount,
+        });
+
+        Ok(())
+    }
+
+    /// Same policy as `withdraw_reserve`, the floor is counted in tokens.
+    pub fn withdraw_token_reserve(ctx: Context<WithdrawTokenReserve>, amount: u64) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        let mint = &ctx.accounts.mint;
+        let vault = &ctx.accounts.vault;
+
+        reserve.take_pending_withdrawal(amount)?;
+        let required = reserve.withdraw_floor.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        require!(vault.amount >= required, ErrorCode::BelowWithdrawFloor);
+
+        let mint_key = mint.key();
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-1".as_ref(), table_id.as_ref(), mint_key.as_ref(), &[reserve.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: vault.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.authority_token_account.to_account_info(),
+                    authority: reserve.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+            mint.decimals,
+        )?;
+        ctx.accounts.vault.reload()?;
+
+        emit!(ReserveWithdrawn {
+            authority: ctx.accounts.authority.key(),
+            amount,
+            balance: ctx.accounts.vault.amount,
+        });
+
+        Ok(())
+    }
+
+    /// Token tables don't take referrers or batches yet, their fees go to the house and the fee recipients only.
+    pub fn roll_dice_token<'info>(ctx: Context<'_, '_, 'info, 'info, RollDiceToken<'info>>, user_seed: u64, bet_type: BetType, bet_size: u64) -> Result<()> {
+        let (multiplier_bp, threshold_bp) = bet_type.get_odds(ctx.accounts.reserve.edge_bp)?;
+        let booked_payout = book_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.bumps.player_exposure, ctx.accounts.player.key(), bet_size, multiplier_bp)?;
+
+        validate_bet(&ctx.accounts.reserve, multiplier_bp, bet_size)?;
+
+        let vault_balance = ctx.accounts.vault.amount;
+        let max_bet = get_max_bet(vault_balance, multiplier_bp, ctx.accounts.reserve.ratio)?;
+        require!(max_bet >= bet_size, ErrorCode::BetTooBig);
+
+        // transfer tokens from player to the vault
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.player_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.player.to_account_info(),
+                },
+            ),
+            bet_size,
+            ctx.accounts.mint.decimals,
+        )?;
+        // a mint that withholds a fee on transfer would pay out more than the vault received
+        ctx.accounts.vault.reload()?;
+        let balance_before = ctx.accounts.vault.amount;
+        require!(balance_before.checked_sub(vault_balance) == Some(bet_size), ErrorCode::TransferFeeMint);
+
+        let player = &ctx.accounts.player;
+        let reserve = &ctx.accounts.reserve;
+        let mint = &ctx.accounts.mint;
+        let vault = &ctx.accounts.vault;
+        let slot_hashes = &ctx.accounts.slot_hashes;
+
+        let data = slot_hashes.data.borrow();
+        let (recent_slot, recent_hash) = SlotHashesData::new(&data)
+            .and_then(|slot_hashes| slot_hashes.most_recent())
+            .ok_or(ErrorCode::InvalidSlotHashes)?;
+        let clock = Clock::get()?;
+        let timestamp = clock.unix_timestamp;
+
+        // generate random number
+        let seed = derive_seed(DICE_DOMAIN, &[&user_seed.to_le_bytes(), &timestamp.to_le_bytes(), &recent_hash]);
+        let p = get_random_bp(seed)?;
+        let outcome = bet_type.get_outcome(p);
+
+        msg!("p: {:?} - threshold_bp: {:?} - edge_bp: {:?} - multiplier_bp: {:?} - bet_type: {:?}", p, threshold_bp, reserve.edge_bp, multiplier_bp, bet_type);
+        msg!("Hash inputs: user_seed {:?} - timestamp {:?} - slot {:?} - slot_hash {:?}", user_seed, timestamp, recent_slot, recent_hash);
+
+        let (transfers, payout) = if outcome < threshold_bp {
+            msg!("Win! p < threshold {:?} < {:?}", outcome, threshold_bp);
+            let payout = get_payout(bet_size, multiplier_bp)?;
+            (vec![(ctx.accounts.player_token_account.to_account_info(), payout)], payout)
         } else {
-            // transfer 10% of the bet to the house
-            msg!("Lose! p >= threshold {:?} >= {:?}", p, threshold_bp);
+            // transfer the configured fee shares of the bet to the house and the fee recipients
+            msg!("Lose! p >= threshold {:?} >= {:?}", outcome, threshold_bp);
+            let mut transfers = vec![(ctx.accounts.house_token_account.to_account_info(), get_fee(bet_size, reserve.house_fee_bp)?)];
+            let fee_accounts = ctx.remaining_accounts.get(..reserve.fee_recipient_count as usize).ok_or(ErrorCode::MismatchedFeeRecipient)?;
+            for (fee_recipient, account) in reserve.fee_recipients.iter().zip(fee_accounts) {
+                let token_account = InterfaceAccount::<TokenAccount>::try_from(account)?;
+                require!(token_account.owner == fee_recipient.recipient && token_account.mint == mint.key(), ErrorCode::MismatchedFeeRecipient);
+                transfers.push((account.clone(), get_fee(bet_size, fee_recipient.fee_bp)?));
+            }
+            (transfers, 0)
+        };
+
+        let balance_after = transfers.iter().try_fold(balance_before, |balance, (_, amount)| balance.checked_sub(*amount)).ok_or(ErrorCode::MathOverflow)?;
+
+        let mint_key = mint.key();
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-1".as_ref(), table_id.as_ref(), mint_key.as_ref(), &[reserve.bump]];
+        let signer_seeds = &[&seeds[..]];
+        for (destination, amount) in transfers {
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: vault.to_account_info(),
+                        mint: mint.to_account_info(),
+                        to: destination,
+                        authority: reserve.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                amount,
+                mint.decimals,
+            )?;
+        }
+
+        emit!(DiceRolled {
+            player: player.key(),
+            table_id: reserve.table_id,
+            mint: reserve.mint,
+            kind: BetKind::Instant,
+            bet_type,
+            user_seed,
+            multiplier_bp,
+            bet_size,
+            edge_bp: reserve.edge_bp,
+            threshold_bp,
+            randomness: seed,
+            roll_bp: p,
+            payout,
+            slot: clock.slot,
+        });
+
+        let player_stats = &mut ctx.accounts.player_stats;
+        player_stats.player = player.key();
+        player_stats.bump = ctx.bumps.player_stats;
+        player_stats.record_roll(bet_size, multiplier_bp, p, payout, clock.slot)?;
+
+        check_circuit_breaker(&mut ctx.accounts.reserve, balance_before, balance_after, clock.slot)?;
+        if payout == 0 {
+            release_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, booked_payout);
+        }
+
+        Ok(())
+    }
+
+    pub fn commit_roll(ctx: Context<CommitRoll>, user_seed: u64, bet_type: BetType, bet_size: u64) -> Result<()> {
+        let (multiplier_bp, _) = bet_type.get_odds(ctx.accounts.reserve.edge_bp)?;
+        let referred = bind_referrer(&mut ctx.accounts.player_stats.referrer, ctx.accounts.player.key(), &mut ctx.accounts.referrer)?;
+        let bet_referrer = if referred { ctx.accounts.player_stats.referrer } else { Pubkey::default() };
+        let booked_payout = book_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.bumps.player_exposure, ctx.accounts.player.key(), bet_size, multiplier_bp)?;
+        let player = &ctx.accounts.player;
+        let reserve = &mut ctx.accounts.reserve;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let bet = &mut ctx.accounts.bet;
+
+        let rent = Rent::get()?;
+        let minimum_balance = rent.minimum_balance(0);
+        let balance: u64 = reserve_key.to_account_info().lamports();
+
+        validate_bet(reserve, multiplier_bp, bet_size)?;
+
+        let max_bet = get_max_bet(reserve.get_free_balance(balance, minimum_balance)?, multiplier_bp, reserve.ratio)?;
+        require!(max_bet >= bet_size, ErrorCode::BetTooBig);
+        // the win stays held back until the bet settles, so the reserve can always pay it
+        reserve.hold_pending_bet(bet_size, booked_payout)?;
+
+        let clock = Clock::get()?;
+        let commitment = derive_seed(DICE_DOMAIN, &[
+            &user_seed.to_le_bytes(),
+            player.key.as_ref(),
+            &bet_type.try_to_vec()?,
+            &bet_size.to_le_bytes(),
+            &clock.slot.to_le_bytes(),
+        ]);
+
+        bet.player = player.key();
+        bet.kind = BetKind::SlotHash;
+        bet.user_seed = user_seed;
+        bet.bet_size = bet_size;
+        bet.commitment = commitment;
+        bet.commit_slot = clock.slot;
+        bet.target_slot = clock.slot.checked_add(COMMIT_SLOT_DELAY).ok_or(ErrorCode::MathOverflow)?;
+        bet.bump = ctx.bumps.bet;
+        bet.reserve = reserve.key();
+        bet.referrer = bet_referrer;
+        bet.set_odds(reserve, bet_type)?;
+
+        let player_stats = &mut ctx.accounts.player_stats;
+        player_stats.player = player.key();
+        player_stats.bump = ctx.bumps.player_stats;
+
+        // escrow the bet in the bet account until it is settled
+        invoke(
+            &transfer(
+                player.key,
+                &bet.key(),
+                bet_size,
+            ),
+            &[
+                player.to_account_info(),
+                bet.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        msg!("Committed bet {:?} - target_slot: {:?} - commitment: {:?}", bet.key(), bet.target_slot, commitment);
+        emit_bet_placed(bet);
+
+        Ok(())
+    }
+
+    pub fn settle_roll<'info>(ctx: Context<'_, '_, 'info, 'info, SettleRoll<'info>>, bump: u8) -> Result<()> {
+        ctx.accounts.reserve.release_pending_bet(ctx.accounts.bet.bet_size, ctx.accounts.bet.payout()?)?;
+        let player = &ctx.accounts.player;
+        let reserve = &ctx.accounts.reserve;
+        let house = &ctx.accounts.house;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let bet = &ctx.accounts.bet;
+        let referred = check_bet_referrer(bet.referrer, &ctx.accounts.referrer)?;
+        let slot_hashes = &ctx.accounts.slot_hashes;
+
+        let clock = Clock::get()?;
+        require!(clock.slot > bet.target_slot, ErrorCode::RollNotReady);
+
+        // release the escrowed bet into the reserve before paying out
+        **bet.to_account_info().try_borrow_mut_lamports()? -= bet.bet_size;
+        **reserve_key.to_account_info().try_borrow_mut_lamports()? += bet.bet_size;
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let data = slot_hashes.data.borrow();
+        let threshold_bp = bet.threshold_bp;
+
+        let slot_hashes = SlotHashesData::new(&data).ok_or(ErrorCode::InvalidSlotHashes)?;
+        match slot_hashes.find(bet.target_slot) {
+            Some(target_hash) => {
+                let seed = derive_seed(DICE_DOMAIN, &[&bet.commitment, &target_hash]);
+                let p = get_random_bp(seed)?;
+                msg!("p: {:?} - threshold_bp: {:?} - edge_bp: {:?} - multiplier_bp: {:?}", p, threshold_bp, bet.edge_bp, bet.multiplier_bp);
+                msg!("Hash inputs: commitment {:?} - target_slot {:?} - slot_hash {:?}", bet.commitment, bet.target_slot, target_hash);
+
+                let balance_before = reserve_key.lamports();
+                let payout = pay_out(
+                    reserve,
+                    bet.bet_type.get_outcome(p),
+                    threshold_bp,
+                    bet.bet_size,
+                    bet.multiplier_bp,
+                    player.to_account_info(),
+                    house.to_account_info(),
+                    ctx.accounts.referrer.as_ref().filter(|_| referred).map(|referrer| referrer.to_account_info()),
+                    get_fee_accounts(reserve, ctx.remaining_accounts)?,
+                    reserve_key.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                    signer_seeds,
+                )?;
+                if referred && payout == 0 {
+                    credit_referrer(&mut ctx.accounts.referrer, get_referral_fee(reserve, bet.bet_size)?)?;
+                }
+                ctx.accounts.player_stats.record_roll(bet.bet_size, bet.multiplier_bp, p, payout, clock.slot)?;
+                emit_dice_rolled(bet, reserve, threshold_bp, seed, p, payout, clock.slot);
+                check_circuit_breaker(&mut ctx.accounts.reserve, balance_before, reserve_key.lamports(), clock.slot)?;
+                if payout == 0 {
+                    release_bet_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.accounts.bet.commit_slot, ctx.accounts.bet.payout()?, 0)?;
+                }
+            }
+            None => {
+                // the target slot fell out of the sysvar window, the bet is forfeited to the reserve
+                msg!("Expired! target_slot {:?} is no longer in slot hashes", bet.target_slot);
+                ctx.accounts.player_stats.record_roll(bet.bet_size, bet.multiplier_bp, EXPIRED_ROLL_BP, 0, clock.slot)?;
+                emit_dice_rolled(bet, reserve, threshold_bp, [0u8; 32], EXPIRED_ROLL_BP, 0, clock.slot);
+                release_bet_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.accounts.bet.commit_slot, ctx.accounts.bet.payout()?, 0)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Commits a batch of bets that settles from the hash of one target slot like `commit_roll`,
+    /// each roll gets its own seed the way `roll_dice_batch` derives them.
+    pub fn commit_roll_batch(ctx: Context<CommitRollBatch>, user_seed: u64, bets: Vec<BatchBet>) -> Result<()> {
+        require!((1..=MAX_BATCH_ROLLS).contains(&bets.len()), ErrorCode::InvalidBatchSize);
+        let referred = bind_referrer(&mut ctx.accounts.player_stats.referrer, ctx.accounts.player.key(), &mut ctx.accounts.referrer)?;
+        let batch_referrer = if referred { ctx.accounts.player_stats.referrer } else { Pubkey::default() };
+        let mut rolls = Vec::with_capacity(bets.len());
+        let mut total_bet: u64 = 0;
+        let mut max_total_payout: u64 = 0;
+        for bet in &bets {
+            let (multiplier_bp, threshold_bp) = bet.bet_type.get_odds(ctx.accounts.reserve.edge_bp)?;
+            validate_bet(&ctx.accounts.reserve, multiplier_bp, bet.bet_size)?;
+            let booked_payout = book_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.bumps.player_exposure, ctx.accounts.player.key(), bet.bet_size, multiplier_bp)?;
+            total_bet = total_bet.checked_add(bet.bet_size).ok_or(ErrorCode::MathOverflow)?;
+            max_total_payout = max_total_payout.checked_add(booked_payout).ok_or(ErrorCode::MathOverflow)?;
+            rolls.push(CommittedRoll {
+                bet_type: bet.bet_type,
+                bet_size: bet.bet_size,
+                multiplier_bp,
+                threshold_bp,
+            });
+        }
+        let player = &ctx.accounts.player;
+        let reserve = &mut ctx.accounts.reserve;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let batch = &mut ctx.accounts.batch;
+
+        let rent = Rent::get()?;
+        let minimum_balance = rent.minimum_balance(0);
+        let balance: u64 = reserve_key.to_account_info().lamports();
+
+        // every roll of the batch may win, so the wins are capped together
+        let max_payout = get_max_payout(reserve.get_free_balance(balance, minimum_balance)?, reserve.ratio)?;
+        require!(max_payout >= max_total_payout, ErrorCode::BetTooBig);
+        reserve.hold_pending_bet(total_bet, max_total_payout)?;
+
+        let clock = Clock::get()?;
+        let commitment = derive_seed(DICE_DOMAIN, &[
+            &user_seed.to_le_bytes(),
+            player.key.as_ref(),
+            &rolls.try_to_vec()?,
+            &clock.slot.to_le_bytes(),
+        ]);
+
+        batch.player = player.key();
+        batch.reserve = reserve.key();
+        batch.referrer = batch_referrer;
+        batch.user_seed = user_seed;
+        batch.commitment = commitment;
+        batch.commit_slot = clock.slot;
+        batch.target_slot = clock.slot.checked_add(COMMIT_SLOT_DELAY).ok_or(ErrorCode::MathOverflow)?;
+        batch.edge_bp = reserve.edge_bp;
+        batch.total_bet = total_bet;
+        batch.bump = ctx.bumps.batch;
+        batch.rolls = rolls;
+
+        let player_stats = &mut ctx.accounts.player_stats;
+        player_stats.player = player.key();
+        player_stats.bump = ctx.bumps.player_stats;
+
+        // escrow the stakes of every roll in the batch account until it is settled
+        invoke(
+            &transfer(
+                player.key,
+                &batch.key(),
+                total_bet,
+            ),
+            &[
+                player.to_account_info(),
+                batch.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        msg!("Committed batch {:?} - target_slot: {:?} - commitment: {:?}", batch.key(), batch.target_slot, commitment);
+        emit!(BatchPlaced {
+            batch: batch.key(),
+            player: batch.player,
+            user_seed,
+            total_bet,
+            rolls: batch.rolls.clone(),
+            commitment,
+            target_slot: batch.target_slot,
+        });
+
+        Ok(())
+    }
+
+    pub fn settle_roll_batch<'info>(ctx: Context<'_, '_, 'info, 'info, SettleRollBatch<'info>>, bump: u8) -> Result<()> {
+        let max_total_payout = ctx.accounts.batch.max_payout()?;
+        ctx.accounts.reserve.release_pending_bet(ctx.accounts.batch.total_bet, max_total_payout)?;
+        let player = &ctx.accounts.player;
+        let reserve = &ctx.accounts.reserve;
+        let house = &ctx.accounts.house;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let batch = &ctx.accounts.batch;
+        let referred = check_bet_referrer(batch.referrer, &ctx.accounts.referrer)?;
+        let slot_hashes = &ctx.accounts.slot_hashes;
+
+        let clock = Clock::get()?;
+        require!(clock.slot > batch.target_slot, ErrorCode::RollNotReady);
+
+        // release the escrowed stakes into the reserve before paying out
+        **batch.to_account_info().try_borrow_mut_lamports()? -= batch.total_bet;
+        **reserve_key.to_account_info().try_borrow_mut_lamports()? += batch.total_bet;
+
+        let data = slot_hashes.data.borrow();
+        let target_hash = SlotHashesData::new(&data).ok_or(ErrorCode::InvalidSlotHashes)?.find(batch.target_slot);
+        let seed = match target_hash {
+            Some(target_hash) => {
+                msg!("Hash inputs: commitment {:?} - target_slot {:?} - slot_hash {:?}", batch.commitment, batch.target_slot, target_hash);
+                Some(derive_seed(DICE_DOMAIN, &[&batch.commitment, &target_hash]))
+            }
+            None => {
+                // the target slot fell out of the sysvar window, every roll is forfeited to the reserve
+                msg!("Expired! target_slot {:?} is no longer in slot hashes", batch.target_slot);
+                None
+            }
+        };
+
+        let mut rolls = Vec::with_capacity(batch.rolls.len());
+        let mut total_payout: u64 = 0;
+        let mut fees = LossFees::default();
+        for (index, roll) in batch.rolls.iter().enumerate() {
+            let (roll_bp, payout) = match seed {
+                Some(seed) => {
+                    let p = get_random_bp(derive_seed(DICE_DOMAIN, &[&seed, &(index as u8).to_le_bytes()]))?;
+                    let outcome = roll.bet_type.get_outcome(p);
+                    if outcome < roll.threshold_bp {
+                        msg!("Win! p < threshold {:?} < {:?}", outcome, roll.threshold_bp);
+                        (p, get_payout(roll.bet_size, roll.multiplier_bp)?)
+                    } else {
+                        msg!("Lose! p >= threshold {:?} >= {:?}", outcome, roll.threshold_bp);
+                        fees.add(&LossFees::new(reserve, roll.bet_size, referred)?)?;
+                        (p, 0)
+                    }
+                }
+                None => (EXPIRED_ROLL_BP, 0),
+            };
+            total_payout = total_payout.checked_add(payout).ok_or(ErrorCode::MathOverflow)?;
+            rolls.push(BatchRoll {
+                bet_type: roll.bet_type,
+                multiplier_bp: roll.multiplier_bp,
+                bet_size: roll.bet_size,
+                threshold_bp: roll.threshold_bp,
+                roll_bp,
+                payout,
+            });
+        }
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let balance_before = reserve_key.lamports();
+        if total_payout > 0 {
+            // transfer the wins of every roll from reserve to player
             invoke_signed(
                 &transfer(
-                    &reserve_key.key(),
-                    &house.key(),
-                    bet_size / 10,
+                    reserve_key.key,
+                    player.key,
+                    total_payout,
                 ),
                 &[
+                    player.to_account_info(),
                     reserve_key.to_account_info(),
-                    house.to_account_info(),
                     ctx.accounts.system_program.to_account_info(),
                 ],
                 signer_seeds,
             )?;
         }
+        if seed.is_some() && rolls.iter().any(|roll| roll.payout == 0) {
+            send_fees(
+                &fees,
+                house.to_account_info(),
+                ctx.accounts.referrer.as_ref().filter(|_| referred).map(|referrer| referrer.to_account_info()),
+                get_fee_accounts(reserve, ctx.remaining_accounts)?,
+                reserve_key.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                signer_seeds,
+            )?;
+            if referred {
+                credit_referrer(&mut ctx.accounts.referrer, fees.referral)?;
+            }
+        }
+
+        let player_stats = &mut ctx.accounts.player_stats;
+        for roll in &rolls {
+            player_stats.record_roll(roll.bet_size, roll.multiplier_bp, roll.roll_bp, roll.payout, clock.slot)?;
+        }
+
+        emit!(DiceBatchRolled {
+            player: batch.player,
+            table_id: reserve.table_id,
+            mint: reserve.mint,
+            user_seed: batch.user_seed,
+            edge_bp: batch.edge_bp,
+            randomness: seed.unwrap_or_default(),
+            total_bet: batch.total_bet,
+            total_payout,
+            rolls: rolls.clone(),
+            slot: clock.slot,
+        });
+
+        check_circuit_breaker(&mut ctx.accounts.reserve, balance_before, ctx.accounts.reserve_key.lamports(), clock.slot)?;
+        for roll in rolls.iter().filter(|roll| roll.payout == 0) {
+            let payout = get_payout(roll.bet_size, roll.multiplier_bp)?;
+            release_bet_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.accounts.batch.commit_slot, payout, 0)?;
+        }
 
         Ok(())
     }
-}
 
-fn get_max_bet(reserve: &Account<Reserve>, balance: u64, multiplier_bp: u64) -> u64 {
+    pub fn rotate_seed_chain(ctx: Context<ChangeConfig>, seed_chain_head: [u8; 32]) -> Result<()> {
+        let reserve = &mut ctx.accounts.reserve;
+        require!(reserve.revealed_nonce == reserve.next_nonce, ErrorCode::PendingFairRolls);
+
+        msg!("Rotating seed chain: old head {:?} - new head {:?} - nonce {:?}", reserve.seed_chain_head, seed_chain_head, reserve.next_nonce);
+        emit!(SeedChainRotated {
+            old_head: reserve.seed_chain_head,
+            new_head: seed_chain_head,
+            nonce: reserve.next_nonce,
+        });
+        reserve.seed_chain_head = seed_chain_head;
+        reserve.skipped_reveals = 0;
 
-    return (balance * 10_000) / (multiplier_bp * reserve.ratio);
-}
+        Ok(())
+    }
+
+    pub fn roll_dice_fair(ctx: Context<RollDiceFair>, user_seed: u64, bet_type: BetType, bet_size: u64) -> Result<()> {
+        let (multiplier_bp, _) = bet_type.get_odds(ctx.accounts.reserve.edge_bp)?;
+        let referred = bind_referrer(&mut ctx.accounts.player_stats.referrer, ctx.accounts.player.key(), &mut ctx.accounts.referrer)?;
+        let bet_referrer = if referred { ctx.accounts.player_stats.referrer } else { Pubkey::default() };
+        let booked_payout = book_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.bumps.player_exposure, ctx.accounts.player.key(), bet_size, multiplier_bp)?;
+        let player = &ctx.accounts.player;
+        let reserve = &mut ctx.accounts.reserve;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let bet = &mut ctx.accounts.bet;
+
+        require!(reserve.seed_chain_head != [0u8; 32], ErrorCode::NoSeedChain);
+
+        let rent = Rent::get()?;
+        let minimum_balance = rent.minimum_balance(0);
+        let balance: u64 = reserve_key.to_account_info().lamports();
+
+        validate_bet(reserve, multiplier_bp, bet_size)?;
+
+        let max_bet = get_max_bet(reserve.get_free_balance(balance, minimum_balance)?, multiplier_bp, reserve.ratio)?;
+        require!(max_bet >= bet_size, ErrorCode::BetTooBig);
+        // the win stays held back until the bet settles, so the reserve can always pay it
+        reserve.hold_pending_bet(bet_size, booked_payout)?;
+
+        let clock = Clock::get()?;
+        bet.player = player.key();
+        bet.kind = BetKind::HouseSeed;
+        bet.user_seed = user_seed;
+        bet.bet_size = bet_size;
+        bet.nonce = reserve.next_nonce;
+        bet.commit_slot = clock.slot;
+        bet.bump = ctx.bumps.bet;
+        bet.reserve = reserve.key();
+        bet.referrer = bet_referrer;
+        bet.set_odds(reserve, bet_type)?;
 
-fn get_threshold_bp(reserve: &Account<Reserve>, multiplier_bp: u64) -> u64 {
+        let player_stats = &mut ctx.accounts.player_stats;
+        player_stats.player = player.key();
+        player_stats.bump = ctx.bumps.player_stats;
+        reserve.next_nonce = reserve.next_nonce.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
 
-    return (10_000 * 10_000) / (multiplier_bp + reserve.edge_bp);
+        // escrow the bet in the bet account until the house reveals its seed
+        invoke(
+            &transfer(
+                player.key,
+                &bet.key(),
+                bet_size,
+            ),
+            &[
+                player.to_account_info(),
+                bet.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        msg!("Fair bet {:?} - nonce: {:?} - user_seed: {:?}", bet.key(), bet.nonce, user_seed);
+        emit_bet_placed(bet);
+
+        Ok(())
+    }
+
+    pub fn reveal_roll<'info>(ctx: Context<'_, '_, 'info, 'info, RevealRoll<'info>>, server_seed: [u8; 32], bump: u8) -> Result<()> {
+        ctx.accounts.reserve.release_pending_bet(ctx.accounts.bet.bet_size, ctx.accounts.bet.payout()?)?;
+        let player = &ctx.accounts.player;
+        let reserve = &mut ctx.accounts.reserve;
+        let house = &ctx.accounts.house;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let bet = &ctx.accounts.bet;
+        let referred = check_bet_referrer(bet.referrer, &ctx.accounts.referrer)?;
+
+        require!(bet.nonce == reserve.revealed_nonce, ErrorCode::WrongRevealOrder);
+        // the preimages of bets claimed unrevealed were never published, so the seed sits that many links further down
+        let mut head = hash(&[&server_seed]);
+        for _ in 0..reserve.skipped_reveals {
+            head = hash(&[&head]);
+        }
+        require!(head == reserve.seed_chain_head, ErrorCode::InvalidServerSeed);
+
+        // the revealed preimage becomes the head the next roll has to hash to
+        reserve.seed_chain_head = server_seed;
+        reserve.skipped_reveals = 0;
+        reserve.revealed_nonce = reserve.revealed_nonce.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        // release the escrowed bet into the reserve before paying out
+        **bet.to_account_info().try_borrow_mut_lamports()? -= bet.bet_size;
+        **reserve_key.to_account_info().try_borrow_mut_lamports()? += bet.bet_size;
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let seed = hash(&[&server_seed, &bet.user_seed.to_le_bytes(), &bet.nonce.to_le_bytes()]);
+        let p = get_random_bp(seed)?;
+        let threshold_bp = bet.threshold_bp;
+
+        msg!("p: {:?} - threshold_bp: {:?} - edge_bp: {:?} - multiplier_bp: {:?}", p, threshold_bp, bet.edge_bp, bet.multiplier_bp);
+        msg!("Hash inputs: server_seed {:?} - user_seed {:?} - nonce {:?}", server_seed, bet.user_seed, bet.nonce);
+
+        let balance_before = reserve_key.lamports();
+        let payout = pay_out(
+            reserve,
+            bet.bet_type.get_outcome(p),
+            threshold_bp,
+            bet.bet_size,
+            bet.multiplier_bp,
+            player.to_account_info(),
+            house.to_account_info(),
+            ctx.accounts.referrer.as_ref().filter(|_| referred).map(|referrer| referrer.to_account_info()),
+            get_fee_accounts(reserve, ctx.remaining_accounts)?,
+            reserve_key.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            signer_seeds,
+        )?;
+        if referred && payout == 0 {
+            credit_referrer(&mut ctx.accounts.referrer, get_referral_fee(reserve, bet.bet_size)?)?;
+        }
+        let slot = Clock::get()?.slot;
+        ctx.accounts.player_stats.record_roll(bet.bet_size, bet.multiplier_bp, p, payout, slot)?;
+        emit_dice_rolled(bet, reserve, threshold_bp, seed, p, payout, slot);
+        check_circuit_breaker(reserve, balance_before, reserve_key.lamports(), slot)?;
+        if payout == 0 {
+            release_bet_exposure(reserve, &mut ctx.accounts.player_exposure, bet.commit_slot, bet.payout()?, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Pays a fair roll the house didn't reveal in time as a win. The house knows every outcome once the
+    /// bet is placed, so withholding a seed must never be cheaper than revealing it.
+    /// Claims follow the reveal order, the skipped chain position is hashed through by the next reveal.
+    pub fn claim_unrevealed_bet(ctx: Context<ClaimUnrevealedBet>, bump: u8) -> Result<()> {
+        let payout = ctx.accounts.bet.payout()?;
+        let reserve = &mut ctx.accounts.reserve;
+        let player = &ctx.accounts.player;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let bet = &ctx.accounts.bet;
+
+        let clock = Clock::get()?;
+        let timeout_slot = bet.commit_slot.checked_add(REVEAL_TIMEOUT_SLOTS).ok_or(ErrorCode::MathOverflow)?;
+        require!(clock.slot >= timeout_slot, ErrorCode::RevealNotTimedOut);
+        require!(bet.nonce == reserve.revealed_nonce, ErrorCode::WrongRevealOrder);
+        reserve.revealed_nonce = reserve.revealed_nonce.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        reserve.skipped_reveals = reserve.skipped_reveals.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        reserve.release_pending_bet(bet.bet_size, payout)?;
+
+        // release the escrowed bet into the reserve before paying out
+        **bet.to_account_info().try_borrow_mut_lamports()? -= bet.bet_size;
+        **reserve_key.to_account_info().try_borrow_mut_lamports()? += bet.bet_size;
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        msg!("Unrevealed bet {:?} - nonce: {:?} - commit_slot: {:?} paid as a win", bet.key(), bet.nonce, bet.commit_slot);
+        let balance_before = reserve_key.lamports();
+        invoke_signed(
+            &transfer(
+                reserve_key.key,
+                player.key,
+                payout,
+            ),
+            &[
+                player.to_account_info(),
+                reserve_key.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
+
+        ctx.accounts.player_stats.record_roll(bet.bet_size, bet.multiplier_bp, EXPIRED_ROLL_BP, payout, clock.slot)?;
+        emit_dice_rolled(bet, reserve, bet.threshold_bp, [0u8; 32], EXPIRED_ROLL_BP, payout, clock.slot);
+        check_circuit_breaker(reserve, balance_before, reserve_key.lamports(), clock.slot)?;
+
+        Ok(())
+    }
+
+    pub fn request_vrf_roll(ctx: Context<RequestVrfRoll>, user_seed: u64, bet_type: BetType, bet_size: u64, vrf_seed: [u8; 32]) -> Result<()> {
+        require!(ctx.accounts.reserve.vrf_program != Pubkey::default(), ErrorCode::VrfDisabled);
+        let (multiplier_bp, _) = bet_type.get_odds(ctx.accounts.reserve.edge_bp)?;
+        let referred = bind_referrer(&mut ctx.accounts.player_stats.referrer, ctx.accounts.player.key(), &mut ctx.accounts.referrer)?;
+        let bet_referrer = if referred { ctx.accounts.player_stats.referrer } else { Pubkey::default() };
+        let booked_payout = book_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.bumps.player_exposure, ctx.accounts.player.key(), bet_size, multiplier_bp)?;
+        let player = &ctx.accounts.player;
+        let reserve = &mut ctx.accounts.reserve;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let bet = &mut ctx.accounts.bet;
+
+        let rent = Rent::get()?;
+        let minimum_balance = rent.minimum_balance(0);
+        let balance: u64 = reserve_key.to_account_info().lamports();
+
+        validate_bet(reserve, multiplier_bp, bet_size)?;
+
+        let max_bet = get_max_bet(reserve.get_free_balance(balance, minimum_balance)?, multiplier_bp, reserve.ratio)?;
+        require!(max_bet >= bet_size, ErrorCode::BetTooBig);
+        // the win stays held back until the bet settles, so the reserve can always pay it
+        reserve.hold_pending_bet(bet_size, booked_payout)?;
+
+        let clock = Clock::get()?;
+        bet.player = player.key();
+        bet.kind = BetKind::Vrf;
+        bet.user_seed = user_seed;
+        bet.bet_size = bet_size;
+        bet.commitment = vrf_seed;
+        bet.commit_slot = clock.slot;
+        bet.bump = ctx.bumps.bet;
+        bet.reserve = reserve.key();
+        bet.referrer = bet_referrer;
+        bet.set_odds(reserve, bet_type)?;
+
+        let player_stats = &mut ctx.accounts.player_stats;
+        player_stats.player = player.key();
+        player_stats.bump = ctx.bumps.player_stats;
+
+        // escrow the bet in the bet account until the oracle fulfills
+        invoke(
+            &transfer(
+                player.key,
+                &bet.key(),
+                bet_size,
+            ),
+            &[
+                player.to_account_info(),
+                bet.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        arcade_vrf::request(
+            &ctx.accounts.vrf_program.to_account_info(),
+            &player.to_account_info(),
+            &ctx.accounts.randomness.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            vrf_seed,
+        )?;
+
+        msg!("Requested randomness {:?} for bet {:?}", ctx.accounts.randomness.key(), bet.key());
+        emit_bet_placed(bet);
+
+        Ok(())
+    }
+
+    pub fn settle_vrf_roll<'info>(ctx: Context<'_, '_, 'info, 'info, SettleVrfRoll<'info>>, bump: u8) -> Result<()> {
+        ctx.accounts.reserve.release_pending_bet(ctx.accounts.bet.bet_size, ctx.accounts.bet.payout()?)?;
+        let player = &ctx.accounts.player;
+        let reserve = &ctx.accounts.reserve;
+        let house = &ctx.accounts.house;
+        let reserve_key = &ctx.accounts.reserve_key;
+        let bet = &ctx.accounts.bet;
+        let referred = check_bet_referrer(bet.referrer, &ctx.accounts.referrer)?;
+        let randomness = Randomness::load(&ctx.accounts.randomness).ok_or(ErrorCode::MismatchedRandomness)?;
+
+        require!(randomness.seed == bet.commitment, ErrorCode::MismatchedRandomness);
+        require!(randomness.is_fulfilled(), ErrorCode::RandomnessNotFulfilled);
+
+        // release the escrowed bet into the reserve before paying out
+        **bet.to_account_info().try_borrow_mut_lamports()? -= bet.bet_size;
+        **reserve_key.to_account_info().try_borrow_mut_lamports()? += bet.bet_size;
+
+        let table_id = reserve.table_id.to_le_bytes();
+        let seeds = &[b"reserve-key-1".as_ref(), table_id.as_ref(), &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        let seed = derive_seed(DICE_DOMAIN, &[&randomness.randomness]);
+        let p = get_random_bp(seed)?;
+        let threshold_bp = bet.threshold_bp;
+
+        msg!("p: {:?} - threshold_bp: {:?} - edge_bp: {:?} - multiplier_bp: {:?}", p, threshold_bp, bet.edge_bp, bet.multiplier_bp);
+        msg!("Hash inputs: randomness {:?}", ctx.accounts.randomness.key());
+
+        let balance_before = reserve_key.lamports();
+        let payout = pay_out(
+            reserve,
+            bet.bet_type.get_outcome(p),
+            threshold_bp,
+            bet.bet_size,
+            bet.multiplier_bp,
+            player.to_account_info(),
+            house.to_account_info(),
+            ctx.accounts.referrer.as_ref().filter(|_| referred).map(|referrer| referrer.to_account_info()),
+            get_fee_accounts(reserve, ctx.remaining_accounts)?,
+            reserve_key.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            signer_seeds,
+        )?;
+        if referred && payout == 0 {
+            credit_referrer(&mut ctx.accounts.referrer, get_referral_fee(reserve, bet.bet_size)?)?;
+        }
+        let slot = Clock::get()?.slot;
+        ctx.accounts.player_stats.record_roll(bet.bet_size, bet.multiplier_bp, p, payout, slot)?;
+        emit_dice_rolled(bet, reserve, threshold_bp, seed, p, payout, slot);
+        check_circuit_breaker(&mut ctx.accounts.reserve, balance_before, reserve_key.lamports(), slot)?;
+        if payout == 0 {
+            release_bet_exposure(&mut ctx.accounts.reserve, &mut ctx.accounts.player_exposure, ctx.accounts.bet.commit_slot, ctx.accounts.bet.payout()?, 0)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn emit_bet_placed(bet: &Account<Bet>) {
+    emit!(BetPlaced {
+        bet: bet.key(),
+        player: bet.player,
+        kind: bet.kind,
+        user_seed: bet.user_seed,
+        multiplier_bp: bet.multiplier_bp,
+        bet_size: bet.bet_size,
+        commitment: bet.commitment,
+        target_slot: bet.target_slot,
+        nonce: bet.nonce,
+    });
+}
+
+fn emit_dice_rolled(bet: &Bet, reserve: &Reserve, threshold_bp: u64, randomness: [u8; 32], roll_bp: u64, payout: u64, slot: u64) {
+    emit!(DiceRolled {
+        player: bet.player,
+        table_id: reserve.table_id,
+        mint: reserve.mint,
+        kind: bet.kind,
+        bet_type: bet.bet_type,
+        user_seed: bet.user_seed,
+        multiplier_bp: bet.multiplier_bp,
+        bet_size: bet.bet_size,
+        edge_bp: bet.edge_bp,
+        threshold_bp,
+        randomness,
+        roll_bp,
+        payout,
+        slot,
+    });
+}
+
+fn get_random_bp(seed: [u8; 32]) -> Result<u64> {
+    Ok(uniform(seed, ROLL_OUTCOMES).ok_or(ErrorCode::HashConversionFailed)?) // 1m bp == 100
+}
+
+fn validate_config(edge_bp: u64, ratio: u64, house: Pubkey) -> Result<()> {
+    require!((MIN_EDGE_BP..=MAX_EDGE_BP).contains(&edge_bp), ErrorCode::InvalidEdge);
+    require!(ratio >= MIN_RATIO, ErrorCode::InvalidRatio);
+    require!(house != Pubkey::default(), ErrorCode::InvalidHouse);
+    Ok(())
+}
+
+fn validate_bet(reserve: &Reserve, multiplier_bp: u64, bet_size: u64) -> Result<()> {
+    require!(!reserve.paused, ErrorCode::Paused);
+    require!((reserve.min_multiplier_bp..=reserve.max_multiplier_bp).contains(&multiplier_bp), ErrorCode::MultiplierOutOfRange);
+    require!(bet_size >= reserve.min_bet, ErrorCode::BetTooSmall);
+    Ok(())
+}
+
+/// Pauses the reserve once a payout leaves it more than `breaker_drawdown_bp` below its balance
+/// at the start of the window. The roll that trips it still goes through.
+fn check_circuit_breaker(reserve: &mut Reserve, balance_before: u64, balance_after: u64, slot: u64) -> Result<()> {
+    if reserve.breaker_drawdown_bp == 0 {
+        return Ok(());
+    }
+
+    if slot >= reserve.breaker_window_start.saturating_add(reserve.breaker_window_slots) {
+        reserve.breaker_window_start = slot;
+        reserve.breaker_window_balance = balance_before;
+    }
+
+    let floor = reserve.breaker_window_balance - get_fee(reserve.breaker_window_balance, reserve.breaker_drawdown_bp)?;
+    if balance_after < floor && !reserve.paused {
+        reserve.paused = true;
+        msg!("Circuit breaker tripped: balance {:?} below {:?} since slot {:?}", balance_after, floor, reserve.breaker_window_start);
+        emit!(CircuitBreakerTripped {
+            mint: reserve.mint,
+            window_start: reserve.breaker_window_start,
+            window_balance: reserve.breaker_window_balance,
+            balance: balance_after,
+        });
+    }
+
+    Ok(())
+}
+
+/// Books the bet as a win against the reserve's window and the player's epoch budget.
+/// Instant rolls release the booking when they lose, committed bets keep it until the window ends.
+fn book_exposure(reserve: &mut Account<Reserve>, exposure: &mut PlayerExposure, exposure_bump: u8, player: Pubkey, bet_size: u64, multiplier_bp: u64) -> Result<u64> {
+    let clock = Clock::get()?;
+    let payout = get_payout(bet_size, multiplier_bp)?;
+    exposure.reserve = reserve.key();
+    exposure.player = player;
+    exposure.bump = exposure_bump;
+
+    if reserve.max_net_payout > 0 {
+        if clock.slot >= reserve.exposure_window_start.saturating_add(reserve.exposure_window_slots) {
+            reserve.exposure_window_start = clock.slot;
+            reserve.exposure_window_paid = 0;
+            reserve.exposure_window_taken = 0;
+        }
+        reserve.exposure_window_paid = reserve.exposure_window_paid.checked_add(payout).ok_or(ErrorCode::MathOverflow)?;
+        reserve.exposure_window_taken = reserve.exposure_window_taken.checked_add(bet_size).ok_or(ErrorCode::MathOverflow)?;
+        let budget = reserve.max_net_payout.saturating_add(reserve.exposure_window_taken);
+        require!(reserve.exposure_window_paid <= budget, ErrorCode::ExposureLimitExceeded);
+    }
+
+    if reserve.max_player_payout > 0 {
+        if exposure.epoch != clock.epoch {
+            exposure.epoch = clock.epoch;
+            exposure.epoch_payout = 0;
+        }
+        exposure.epoch_payout = exposure.epoch_payout.checked_add(payout).ok_or(ErrorCode::MathOverflow)?;
+        require!(exposure.epoch_payout <= reserve.max_player_payout, ErrorCode::PlayerPayoutLimitExceeded);
+    }
+
+    Ok(payout)
+}
+
+fn release_exposure(reserve: &mut Reserve, exposure: &mut PlayerExposure, payout: u64) {
+    if reserve.max_net_payout > 0 {
+        reserve.exposure_window_paid = reserve.exposure_window_paid.saturating_sub(payout);
+    }
+    if reserve.max_player_payout > 0 {
+        exposure.epoch_payout = exposure.epoch_payout.saturating_sub(payout);
+    }
+}
+
+/// Releases what a bet settled after placement booked in `book_exposure`, once it is known not to pay out.
+/// Nothing is released from a window or epoch that rolled over since the bet was placed.
+fn release_bet_exposure(reserve: &mut Reserve, exposure: &mut PlayerExposure, commit_slot: u64, payout: u64, taken: u64) -> Result<()> {
+    if reserve.max_net_payout > 0 && commit_slot >= reserve.exposure_window_start {
+        reserve.exposure_window_paid = reserve.exposure_window_paid.saturating_sub(payout);
+        reserve.exposure_window_taken = reserve.exposure_window_taken.saturating_sub(taken);
+    }
+    if reserve.max_player_payout > 0 && exposure.epoch == EpochSchedule::get()?.get_epoch(commit_slot) {
+        exposure.epoch_payout = exposure.epoch_payout.saturating_sub(payout);
+    }
+    Ok(())
+}
+
+fn validate_fees(house_fee_bp: u64, fee_recipients: &[FeeRecipient]) -> Result<()> {
+    let total_fee_bp = fee_recipients.iter().try_fold(house_fee_bp, |total, fee_recipient| total.checked_add(fee_recipient.fee_bp));
+    require!(total_fee_bp.is_some_and(|total| total <= MAX_TOTAL_FEE_BP), ErrorCode::FeeTooHigh);
+    Ok(())
+}
+
+fn get_referral_fee(reserve: &Reserve, bet_size: u64) -> Result<u64> {
+    get_fee(get_fee(bet_size, reserve.house_fee_bp)?, reserve.referral_fee_bp)
+}
+
+/// Binds the first referrer a player rolls with, afterwards only that referrer can be passed.
+/// Returns whether the roll pays a referral fee.
+fn bind_referrer(bound_referrer: &mut Pubkey, player: Pubkey, referrer: &mut Option<Account<Referrer>>) -> Result<bool> {
+    if let Some(referrer) = referrer.as_mut() {
+        if *bound_referrer == Pubkey::default() && referrer.referrer != player {
+            *bound_referrer = referrer.referrer;
+            referrer.referral_count = referrer.referral_count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+            emit!(ReferrerBound {
+                player,
+                referrer: referrer.referrer,
+            });
+        }
+    }
+    if *bound_referrer == Pubkey::default() {
+        return Ok(false);
+    }
+    let referrer = referrer.as_ref().ok_or(ErrorCode::MismatchedReferrer)?;
+    require_keys_eq!(referrer.referrer, *bound_referrer, ErrorCode::MismatchedReferrer);
+    Ok(true)
+}
+
+/// Checks a settle got the referrer its bet was placed with, `false` if the bet has none.
+fn check_bet_referrer(bet_referrer: Pubkey, referrer: &Option<Account<Referrer>>) -> Result<bool> {
+    if bet_referrer == Pubkey::default() {
+        return Ok(false);
+    }
+    require!(referrer.is_some(), ErrorCode::MismatchedReferrer);
+    Ok(true)
+}
+
+fn credit_referrer(referrer: &mut Option<Account<Referrer>>, referral_fee: u64) -> Result<()> {
+    let referrer = referrer.as_mut().ok_or(ErrorCode::MismatchedReferrer)?;
+    referrer.total_earned = referrer.total_earned.checked_add(referral_fee).ok_or(ErrorCode::MathOverflow)?;
+    Ok(())
+}
+
+/// Checks that the remaining accounts start with the reserve's fee recipients, in order.
+fn get_fee_accounts<'a, 'info>(reserve: &Reserve, remaining_accounts: &'a [AccountInfo<'info>]) -> Result<&'a [AccountInfo<'info>]> {
+    let count = reserve.fee_recipient_count as usize;
+    require!(remaining_accounts.len() >= count, ErrorCode::MismatchedFeeRecipient);
+    for (fee_recipient, account) in reserve.fee_recipients.iter().zip(&remaining_accounts[..count]) {
+        require_keys_eq!(account.key(), fee_recipient.recipient, ErrorCode::MismatchedFeeRecipient);
+    }
+    Ok(&remaining_accounts[..count])
+}
+
+#[allow(clippy::too_many_arguments)]
+fn pay_out<'info>(
+    reserve: &Reserve,
+    p: u64,
+    threshold_bp: u64,
+    bet_size: u64,
+    multiplier_bp: u64,
+    player: AccountInfo<'info>,
+    house: AccountInfo<'info>,
+    referrer: Option<AccountInfo<'info>>,
+    fee_accounts: &[AccountInfo<'info>],
+    reserve_key: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<u64> {
+    if p < threshold_bp {
+        // transfer sol from reserve to player
+        msg!("Win! p < threshold {:?} < {:?}", p, threshold_bp);
+        let payout = get_payout(bet_size, multiplier_bp)?;
+        invoke_signed(
+            &transfer(
+                reserve_key.key,
+                player.key,
+                payout,
+            ),
+            &[
+                player,
+                reserve_key,
+                system_program,
+            ],
+            signer_seeds,
+        )?;
+        Ok(payout)
+    } else {
+        // transfer the configured fee shares of the bet to the house and the fee recipients
+        msg!("Lose! p >= threshold {:?} >= {:?}", p, threshold_bp);
+        let fees = LossFees::new(reserve, bet_size, referrer.is_some())?;
+        send_fees(&fees, house, referrer, fee_accounts, reserve_key, system_program, signer_seeds)?;
+        Ok(0)
+    }
+}
+
+/// Fee shares taken out of lost bets, summed over every lost roll of a batch.
+#[derive(Default)]
+struct LossFees {
+    house: u64,
+    referral: u64,
+    recipients: [u64; MAX_FEE_RECIPIENTS],
+}
+
+impl LossFees {
+    fn new(reserve: &Reserve, bet_size: u64, referred: bool) -> Result<Self> {
+        // a bound referrer's cut comes out of the house fee
+        let referral = if referred { get_referral_fee(reserve, bet_size)? } else { 0 };
+        let house = get_fee(bet_size, reserve.house_fee_bp)?.checked_sub(referral).ok_or(ErrorCode::MathOverflow)?;
+        let mut recipients = [0; MAX_FEE_RECIPIENTS];
+        for (fee, fee_recipient) in recipients.iter_mut().zip(&reserve.fee_recipients[..reserve.fee_recipient_count as usize]) {
+            *fee = get_fee(bet_size, fee_recipient.fee_bp)?;
+        }
+        Ok(LossFees { house, referral, recipients })
+    }
+
+    fn add(&mut self, other: &LossFees) -> Result<()> {
+        self.house = self.house.checked_add(other.house).ok_or(ErrorCode::MathOverflow)?;
+        self.referral = self.referral.checked_add(other.referral).ok_or(ErrorCode::MathOverflow)?;
+        for (fee, other_fee) in self.recipients.iter_mut().zip(other.recipients) {
+            *fee = fee.checked_add(other_fee).ok_or(ErrorCode::MathOverflow)?;
+        }
+        Ok(())
+    }
+}
+
+fn send_fees<'info>(
+    fees: &LossFees,
+    house: AccountInfo<'info>,
+    referrer: Option<AccountInfo<'info>>,
+    fee_accounts: &[AccountInfo<'info>],
+    reserve_key: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let mut transfers = vec![(house, fees.house)];
+    transfers.extend(referrer.map(|referrer| (referrer, fees.referral)));
+    transfers.extend(fee_accounts.iter().cloned().zip(fees.recipients));
+    for (recipient, fee) in transfers {
+        invoke_signed(
+            &transfer(
+                reserve_key.key,
+                recipient.key,
+                fee,
+            ),
+            &[
+                reserve_key.clone(),
+                recipient,
+                system_program.clone(),
+            ],
+            signer_seeds,
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct ChangeConfig<'info> {
+    // works for the native reserve as well as every token reserve
+    #[account(mut)]
+    pub reserve: Account<'info, Reserve>,
+    #[account(constraint = reserve.is_authority(authority.key()))]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeTreasury<'info> {
+    #[account(mut)]
+    pub reserve: Account<'info, Reserve>,
+    #[account(constraint = reserve.is_treasury_admin(authority.key()))]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeRoles<'info> {
+    #[account(mut)]
+    pub reserve: Account<'info, Reserve>,
+    #[account(constraint = reserve.is_authority(authority.key()))]
+    pub authority: Signer<'info>,
+    #[account(constraint = reserve.is_treasury_admin(treasury_admin.key()))]
+    pub treasury_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PauseReserve<'info> {
+    #[account(mut)]
+    pub reserve: Account<'info, Reserve>,
+    #[account(constraint = reserve.is_pauser(authority.key()))]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(mut)]
+    pub reserve: Account<'info, Reserve>,
+    #[account(constraint = new_authority.key() == reserve.pending_authority @ ErrorCode::NotPendingAuthority)]
+    pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = Referrer::LEN,
+        seeds = [
+            b"referrer",
+            authority.key().as_ref()
+        ],
+        bump
+    )]
+    pub referrer: Account<'info, Referrer>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferral<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            authority.key().as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Account<'info, Referrer>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositReserve<'info> {
+    #[account(
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawReserve<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(mut, constraint = reserve.is_treasury_admin(authority.key()))]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitLpPool<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [
+            b"lp-mint",
+            reserve.key().as_ref()
+        ],
+        bump,
+        mint::decimals = LP_DECIMALS,
+        mint::authority = reserve,
+        mint::token_program = token_program
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = authority,
+        associated_token::mint = lp_mint,
+        associated_token::authority = authority,
+        associated_token::token_program = token_program
+    )]
+    pub authority_shares: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = reserve.is_treasury_admin(authority.key()))]
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositLiquidity<'info> {
+    #[account(
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(mut, address = reserve.lp_mint)]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = provider,
+        associated_token::mint = lp_mint,
+        associated_token::authority = provider,
+        associated_token::token_program = token_program
+    )]
+    pub provider_shares: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = provider,
+        space = LpPosition::LEN,
+        seeds = [
+            b"lp-position",
+            reserve.key().as_ref(),
+            provider.key().as_ref()
+        ],
+        bump
+    )]
+    pub lp_position: Account<'info, LpPosition>,
+    #[account(mut)]
+    pub provider: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RequestLpWithdrawal<'info> {
+    #[account(
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(address = reserve.lp_mint)]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        associated_token::mint = reserve.lp_mint,
+        associated_token::authority = provider,
+        associated_token::token_program = token_program
+    )]
+    pub provider_shares: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = provider,
+        space = LpPosition::LEN,
+        seeds = [
+            b"lp-position",
+            reserve.key().as_ref(),
+            provider.key().as_ref()
+        ],
+        bump
+    )]
+    pub lp_position: Account<'info, LpPosition>,
+    #[account(mut)]
+    pub provider: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawLiquidity<'info> {
+    #[account(
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(mut, address = reserve.lp_mint)]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = lp_mint,
+        associated_token::authority = provider,
+        associated_token::token_program = token_program
+    )]
+    pub provider_shares: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [
+            b"lp-position",
+            reserve.key().as_ref(),
+            provider.key().as_ref()
+        ],
+        bump = lp_position.bump
+    )]
+    pub lp_position: Account<'info, LpPosition>,
+    #[account(mut)]
+    pub provider: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(table_id: u16)]
+pub struct SetupDice<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = Reserve::LEN,
+        seeds = [
+            b"reserve-1",
+            table_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: this is ok
+    #[account(mut)]
+    pub reserve_key: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(table_id: u16)]
+pub struct MigrateLegacyReserve<'info> {
+    /// CHECK: parsed by `LegacyReserve::load`
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [
+            b"reserve-1"
+        ],
+        bump
+    )]
+    pub legacy_reserve: UncheckedAccount<'info>,
+    /// CHECK: we check the address matches the seeds
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-key-1"
+        ],
+        bump
+    )]
+    pub legacy_reserve_key: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = authority,
+        space = Reserve::LEN,
+        seeds = [
+            b"reserve-1",
+            table_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: we check the address matches the seeds
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-key-1",
+            table_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub reserve_key: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Layout of the reserve at `[b"reserve-1"]`, written before the reserve had any other field.
+#[derive(AnchorDeserialize)]
+struct LegacyReserve {
+    ratio: u64,
+    edge_bp: u64,
+    house: Pubkey,
+    _reserve_key: Pubkey,
+    update_authority: Pubkey,
+    _bump: u8,
+}
+
+impl LegacyReserve {
+    const LEN: usize = DISCRIMINATOR_LENGTH + (U64_LENGTH * 2) + (PUBLIC_KEY_LENGTH * 3) + U8_LENGTH;
+
+    fn load(account: &AccountInfo) -> Result<Self> {
+        let data = account.try_borrow_data()?;
+        require!(data.len() == Self::LEN && data[..DISCRIMINATOR_LENGTH] == <Reserve as anchor_lang::Discriminator>::DISCRIMINATOR, ErrorCode::NotLegacyReserve);
+        Ok(Self::deserialize(&mut &data[DISCRIMINATOR_LENGTH..])?)
+    }
+}
+
+#[account]
+pub struct Reserve {
+    pub ratio: u64,
+    pub edge_bp: u64,
+    pub house: Pubkey,
+    pub reserve_key: Pubkey,
+    pub update_authority: Pubkey,
+    pub bump: u8,
+    pub seed_chain_head: [u8; 32],
+    pub next_nonce: u64,
+    pub revealed_nonce: u64,
+    /// `Pubkey::default()` for the native reserve, `reserve_key` is then the token vault.
+    pub mint: Pubkey,
+    pub decimals: u8,
+    /// Lamports above rent that `withdraw_reserve` always leaves in `reserve_key`.
+    pub withdraw_floor: u64,
+    /// Seconds between `request_withdrawal` and `withdraw_reserve`, 0 withdraws immediately.
+    pub withdraw_timelock: i64,
+    pub pending_withdrawal: u64,
+    pub withdrawal_unlock_time: i64,
+    /// Share mint of the house pool, `Pubkey::default()` until `init_lp_pool`.
+    pub lp_mint: Pubkey,
+    /// Seconds between `request_lp_withdrawal` and `withdraw_liquidity`.
+    pub lp_cooldown: i64,
+    /// Share of a lost bet paid to `house`.
+    pub house_fee_bp: u64,
+    pub fee_recipients: [FeeRecipient; MAX_FEE_RECIPIENTS],
+    pub fee_recipient_count: u8,
+    /// Share of the house fee paid to a bound referrer.
+    pub referral_fee_bp: u64,
+    pub min_multiplier_bp: u64,
+    pub max_multiplier_bp: u64,
+    pub min_bet: u64,
+    /// Set by `propose_authority`, becomes `update_authority` once it signs `accept_authority`.
+    pub pending_authority: Pubkey,
+    /// Stops `UPDATE_AUTHORITY` from acting on the reserve, can't be undone.
+    pub backdoor_revoked: bool,
+    /// Moves money out of the reserve: `request_withdrawal`, `withdraw_reserve` and `init_lp_pool`,
+    /// and sets where fees go: `set_house`, `set_fee_recipients` and `set_referral_fee`.
+    pub treasury_admin: Pubkey,
+    /// Sets `paused` through `set_paused`.
+    pub pauser: Pubkey,
+    pub paused: bool,
+    /// Drop below the balance at the start of the window, in bp, that pauses the reserve. 0 disables it.
+    pub breaker_drawdown_bp: u64,
+    pub breaker_window_slots: u64,
+    pub breaker_window_start: u64,
+    pub breaker_window_balance: u64,
+    /// Seeds the reserve and its `reserve_key` or vault, each table is an independent reserve.
+    pub table_id: u16,
+    /// Most the reserve can pay out net of bets taken per `exposure_window_slots`, 0 disables it.
+    pub max_net_payout: u64,
+    pub exposure_window_slots: u64,
+    pub exposure_window_start: u64,
+    pub exposure_window_paid: u64,
+    pub exposure_window_taken: u64,
+    /// Most a single player can be paid per epoch, 0 disables it.
+    pub max_player_payout: u64,
+    /// Oracle that `request_vrf_roll` asks for randomness, `Pubkey::default()` disables vrf rolls.
+    pub vrf_program: Pubkey,
+    /// Allows `roll_dice`, `roll_dice_batch` and `roll_dice_token`. They settle in the transaction that
+    /// places the bet, so a player can simulate them or revert a loss, off until the authority opts in.
+    pub instant_rolls: bool,
+    /// What the reserve owes if every placed but unsettled bet wins, held back from new bets and withdrawals.
+    pub pending_payout: u64,
+    /// Chain positions of fair rolls claimed unrevealed since the last reveal.
+    pub skipped_reveals: u64,
+    /// Stakes of placed but unsettled bets, escrowed outside the reserve until they settle.
+    pub pending_stake: u64
+}
+
+impl Reserve {
+    const LEN: usize = DISCRIMINATOR_LENGTH + (U64_LENGTH * 2) + (PUBLIC_KEY_LENGTH * 3) + U8_LENGTH + 32 + (U64_LENGTH * 2) + PUBLIC_KEY_LENGTH + U8_LENGTH + (U64_LENGTH * 4) + PUBLIC_KEY_LENGTH + U64_LENGTH + U64_LENGTH + (FeeRecipient::LEN * MAX_FEE_RECIPIENTS) + U8_LENGTH + (U64_LENGTH * 4) + PUBLIC_KEY_LENGTH + U8_LENGTH + (PUBLIC_KEY_LENGTH * 2) + U8_LENGTH + (U64_LENGTH * 4) + U16_LENGTH + (U64_LENGTH * 6) + PUBLIC_KEY_LENGTH + U8_LENGTH + (U64_LENGTH * 3);
+
+    fn has_role(&self, role: Pubkey, key: Pubkey) -> bool {
+        key == role || (!self.backdoor_revoked && key == UPDATE_AUTHORITY)
+    }
+
+    /// With a timelock only the pending request can be withdrawn, once it has unlocked.
+    fn take_pending_withdrawal(&mut self, amount: u64) -> Result<()> {
+        if self.withdraw_timelock > 0 {
+            let clock = Clock::get()?;
+            require!(self.pending_withdrawal > 0 && self.pending_withdrawal == amount, ErrorCode::NoPendingWithdrawal);
+            require!(clock.unix_timestamp >= self.withdrawal_unlock_time, ErrorCode::WithdrawalLocked);
+            self.pending_withdrawal = 0;
+        }
+        Ok(())
+    }
+
+    /// Balance above rent that isn't held back for the payouts of unsettled bets.
+    fn get_free_balance(&self, balance: u64, minimum_balance: u64) -> Result<u64> {
+        get_available_balance(balance, minimum_balance.checked_add(self.pending_payout).ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    /// Lp value if every unsettled bet wins, withdrawals are priced at it so they can't dodge known wins.
+    fn get_lp_value_floor(&self, balance: u64, minimum_balance: u64) -> Result<u64> {
+        let balance = balance.checked_add(self.pending_stake).ok_or(ErrorCode::MathOverflow)?;
+        self.get_free_balance(balance, minimum_balance)
+    }
+
+    /// Lp value if every unsettled bet loses, deposits are priced at it so they can't buy into known losses.
+    fn get_lp_value_ceiling(&self, balance: u64, minimum_balance: u64) -> Result<u64> {
+        get_available_balance(balance.checked_add(self.pending_stake).ok_or(ErrorCode::MathOverflow)?, minimum_balance)
+    }
+
+    fn hold_pending_bet(&mut self, stake: u64, payout: u64) -> Result<()> {
+        self.pending_stake = self.pending_stake.checked_add(stake).ok_or(ErrorCode::MathOverflow)?;
+        self.pending_payout = self.pending_payout.checked_add(payout).ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    fn release_pending_bet(&mut self, stake: u64, payout: u64) -> Result<()> {
+        self.pending_stake = self.pending_stake.checked_sub(stake).ok_or(ErrorCode::MathOverflow)?;
+        self.pending_payout = self.pending_payout.checked_sub(payout).ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    fn is_authority(&self, key: Pubkey) -> bool {
+        self.has_role(self.update_authority, key)
+    }
+
+    fn is_treasury_admin(&self, key: Pubkey) -> bool {
+        self.has_role(self.treasury_admin, key)
+    }
+
+    fn is_pauser(&self, key: Pubkey) -> bool {
+        self.has_role(self.pauser, key)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct FeeRecipient {
+    pub recipient: Pubkey,
+    pub fee_bp: u64
+}
+
+impl FeeRecipient {
+    const LEN: usize = PUBLIC_KEY_LENGTH + U64_LENGTH;
+}
+
+/// Payouts a player took from one reserve in the current epoch.
+#[account]
+pub struct PlayerExposure {
+    pub reserve: Pubkey,
+    pub player: Pubkey,
+    pub epoch: u64,
+    pub epoch_payout: u64,
+    pub bump: u8
+}
+
+impl PlayerExposure {
+    const LEN: usize = DISCRIMINATOR_LENGTH + (PUBLIC_KEY_LENGTH * 2) + (U64_LENGTH * 2) + U8_LENGTH;
+}
+
+#[account]
+pub struct LpPosition {
+    pub provider: Pubkey,
+    pub pending_shares: u64,
+    pub unlock_time: i64,
+    pub bump: u8,
+    /// Value of `pending_shares` when they were requested, the withdrawal pays at most this.
+    pub pending_amount: u64,
+    /// Last deposit plus `lp_cooldown`, withdrawals can't be requested before it.
+    pub deposit_unlock_time: i64
+}
+
+impl LpPosition {
+    const LEN: usize = DISCRIMINATOR_LENGTH + PUBLIC_KEY_LENGTH + (U64_LENGTH * 2) + U8_LENGTH + (U64_LENGTH * 2);
+}
+
+#[derive(Accounts)]
+#[instruction(table_id: u16)]
+pub struct SetupTokenDice<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = Reserve::LEN,
+        seeds = [
+            b"reserve-1",
+            table_id.to_le_bytes().as_ref(),
+            mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [
+            b"reserve-vault",
+            table_id.to_le_bytes().as_ref(),
+            mint.key().as_ref()
+        ],
+        bump,
+        token::mint = mint,
+        token::authority = reserve,
+        token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = creator,
+        token::token_program = token_program
+    )]
+    pub creator_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositTokenReserve<'info> {
+    #[account(
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref(),
+            mint.key().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = reserve.reserve_key)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = depositor,
+        token::token_program = token_program
+    )]
+    pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub depositor: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTokenReserve<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref(),
+            mint.key().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = reserve.reserve_key)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = authority,
+        token::token_program = token_program
+    )]
+    pub authority_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = reserve.is_treasury_admin(authority.key()))]
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RollDiceToken<'info> {
+    #[account(mut)]
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref(),
+            mint.key().as_ref()
+        ],
+        bump = reserve.bump,
+        constraint = reserve.instant_rolls @ ErrorCode::InstantRollsDisabled
+    )]
+    pub reserve: Account<'info, Reserve>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = reserve.reserve_key)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = player,
+        token::token_program = token_program
+    )]
+    pub player_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = reserve.house,
+        token::token_program = token_program
+    )]
+    pub house_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Kept per mint so the totals stay in one currency.
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerStats::LEN,
+        seeds = [
+            b"player-stats",
+            player.key().as_ref(),
+            mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerExposure::LEN,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: the address is constrained
+    #[account(address = slot_hashes::id())]
+    slot_hashes: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RollDice<'info> {
+    #[account(mut)]
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump,
+        constraint = reserve.instant_rolls @ ErrorCode::InstantRollsDisabled
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: this is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.house)]
+    pub house: AccountInfo<'info>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerStats::LEN,
+        seeds = [
+            b"player-stats",
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// Binds to the player on their first referred roll, required on every roll after that.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            referrer.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerExposure::LEN,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: the address is constrained
+    #[account(address = slot_hashes::id())]
+    slot_hashes: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(user_seed: u64)]
+pub struct CommitRoll<'info> {
+    #[account(mut)]
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = player,
+        space = Bet::LEN,
+        seeds = [
+            b"bet",
+            player.key().as_ref(),
+            &user_seed.to_le_bytes()
+        ],
+        bump
+    )]
+    pub bet: Account<'info, Bet>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerStats::LEN,
+        seeds = [
+            b"player-stats",
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// Binds to the player on their first referred roll, required on every roll after that.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            referrer.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerExposure::LEN,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRoll<'info> {
+    pub settler: Signer<'info>,
+    /// CHECK: this is checked against the player in the bet struct
+    #[account(mut, address = bet.player)]
+    pub player: AccountInfo<'info>,
+    #[account(
+        mut,
+        close = player,
+        seeds = [
+            b"bet",
+            bet.player.as_ref(),
+            &bet.user_seed.to_le_bytes()
+        ],
+        bump = bet.bump,
+        constraint = bet.kind == BetKind::SlotHash @ ErrorCode::WrongBetKind
+    )]
+    pub bet: Account<'info, Bet>,
+    #[account(
+        mut,
+        seeds = [
+            b"player-stats",
+            bet.player.as_ref()
+        ],
+        bump = player_stats.bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// The bet's referrer, required when it was placed with one.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            bet.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump,
+        constraint = reserve.key() == bet.reserve @ ErrorCode::WrongTable
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: this is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.house)]
+    pub house: AccountInfo<'info>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            bet.player.as_ref()
+        ],
+        bump = player_exposure.bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: the address is constrained
+    #[account(address = slot_hashes::id())]
+    slot_hashes: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(user_seed: u64)]
+pub struct CommitRollBatch<'info> {
+    #[account(mut)]
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = player,
+        space = BatchCommit::LEN,
+        seeds = [
+            b"batch",
+            player.key().as_ref(),
+            &user_seed.to_le_bytes()
+        ],
+        bump
+    )]
+    pub batch: Account<'info, BatchCommit>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerStats::LEN,
+        seeds = [
+            b"player-stats",
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// Binds to the player on their first referred roll, required on every roll after that.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            referrer.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerExposure::LEN,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRollBatch<'info> {
+    pub settler: Signer<'info>,
+    /// CHECK: this is checked against the player in the batch struct
+    #[account(mut, address = batch.player)]
+    pub player: AccountInfo<'info>,
+    #[account(
+        mut,
+        close = player,
+        seeds = [
+            b"batch",
+            batch.player.as_ref(),
+            &batch.user_seed.to_le_bytes()
+        ],
+        bump = batch.bump
+    )]
+    pub batch: Account<'info, BatchCommit>,
+    #[account(
+        mut,
+        seeds = [
+            b"player-stats",
+            batch.player.as_ref()
+        ],
+        bump = player_stats.bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// The batch's referrer, required when it was placed with one.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            batch.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump,
+        constraint = reserve.key() == batch.reserve @ ErrorCode::WrongTable
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: this is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.house)]
+    pub house: AccountInfo<'info>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            batch.player.as_ref()
+        ],
+        bump = player_exposure.bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: the address is constrained
+    #[account(address = slot_hashes::id())]
+    slot_hashes: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(user_seed: u64)]
+pub struct RollDiceFair<'info> {
+    #[account(mut)]
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump
+    )]
+    pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = player,
+        space = Bet::LEN,
+        seeds = [
+            b"bet",
+            player.key().as_ref(),
+            &user_seed.to_le_bytes()
+        ],
+        bump
+    )]
+    pub bet: Account<'info, Bet>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerStats::LEN,
+        seeds = [
+            b"player-stats",
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// Binds to the player on their first referred roll, required on every roll after that.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            referrer.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerExposure::LEN,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ChangeConfig<'info> {
+pub struct RevealRoll<'info> {
+    #[account(constraint = reserve.is_authority(authority.key()))]
+    pub authority: Signer<'info>,
+    /// CHECK: this is checked against the player in the bet struct
+    #[account(mut, address = bet.player)]
+    pub player: AccountInfo<'info>,
     #[account(
         mut,
+        close = player,
         seeds = [
-            b"reserve-1"
+            b"bet",
+            bet.player.as_ref(),
+            &bet.user_seed.to_le_bytes()
         ],
-        bump = reserve.bump
+        bump = bet.bump,
+        constraint = bet.kind == BetKind::HouseSeed @ ErrorCode::WrongBetKind
+    )]
+    pub bet: Account<'info, Bet>,
+    #[account(
+        mut,
+        seeds = [
+            b"player-stats",
+            bet.player.as_ref()
+        ],
+        bump = player_stats.bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// The bet's referrer, required when it was placed with one.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            bet.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump,
+        constraint = reserve.key() == bet.reserve @ ErrorCode::WrongTable
     )]
     pub reserve: Account<'info, Reserve>,
-    #[account(constraint = authority.key() == reserve.update_authority || authority.key() == UPDATE_AUTHORITY)]
-    pub authority: Signer<'info>,
+    /// CHECK: this is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.house)]
+    pub house: AccountInfo<'info>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            bet.player.as_ref()
+        ],
+        bump = player_exposure.bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct SetupDice<'info> {
+pub struct ClaimUnrevealedBet<'info> {
+    pub caller: Signer<'info>,
+    /// CHECK: this is checked against the player in the bet struct
+    #[account(mut, address = bet.player)]
+    pub player: AccountInfo<'info>,
     #[account(
-        init,
-        payer = creator,
-        space = Reserve::LEN,
+        mut,
+        close = player,
         seeds = [
-            b"reserve-1"
+            b"bet",
+            bet.player.as_ref(),
+            &bet.user_seed.to_le_bytes()
         ],
-        bump
+        bump = bet.bump,
+        constraint = bet.kind == BetKind::HouseSeed @ ErrorCode::WrongBetKind
+    )]
+    pub bet: Account<'info, Bet>,
+    #[account(
+        mut,
+        seeds = [
+            b"player-stats",
+            bet.player.as_ref()
+        ],
+        bump = player_stats.bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump,
+        constraint = reserve.key() == bet.reserve @ ErrorCode::WrongTable
     )]
     pub reserve: Account<'info, Reserve>,
-    /// CHECK: this is ok
-    #[account(mut)]
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(mut, address = reserve.reserve_key)]
     pub reserve_key: AccountInfo<'info>,
     pub system_program: Program<'info, System>,
-    #[account(mut)]
-    pub creator: Signer<'info>,
-}
-
-#[account]
-pub struct Reserve {
-    pub ratio: u64,
-    pub edge_bp: u64,
-    pub house: Pubkey,
-    pub reserve_key: Pubkey,
-    pub update_authority: Pubkey,
-    pub bump: u8
-}
-
-impl Reserve {
-    const LEN: usize = DISCRIMINATOR_LENGTH + (U64_LENGTH * 2) + (PUBLIC_KEY_LENGTH * 3) + U8_LENGTH;
 }
 
 #[derive(Accounts)]
-pub struct RollDice<'info> {
+#[instruction(user_seed: u64)]
+pub struct RequestVrfRoll<'info> {
     #[account(mut)]
     pub player: Signer<'info>,
     #[account(
         mut,
         seeds = [
-            b"reserve-1"
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
         ],
         bump = reserve.bump
     )]
     pub reserve: Account<'info, Reserve>,
+    /// CHECK: This is checked against the pubkey in the reserve struct
+    #[account(address = reserve.reserve_key)]
+    pub reserve_key: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = player,
+        space = Bet::LEN,
+        seeds = [
+            b"bet",
+            player.key().as_ref(),
+            &user_seed.to_le_bytes()
+        ],
+        bump
+    )]
+    pub bet: Account<'info, Bet>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerStats::LEN,
+        seeds = [
+            b"player-stats",
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// Binds to the player on their first referred roll, required on every roll after that.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            referrer.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    /// CHECK: initialized by the vrf program, which checks the address against the seed
+    #[account(mut)]
+    pub randomness: UncheckedAccount<'info>,
+    /// CHECK: the oracle configured on the reserve
+    #[account(executable, address = reserve.vrf_program @ ErrorCode::MismatchedVrfProgram)]
+    pub vrf_program: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = player,
+        space = PlayerExposure::LEN,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            player.key().as_ref()
+        ],
+        bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleVrfRoll<'info> {
+    pub settler: Signer<'info>,
+    /// CHECK: this is checked against the player in the bet struct
+    #[account(mut, address = bet.player)]
+    pub player: AccountInfo<'info>,
+    #[account(
+        mut,
+        close = player,
+        seeds = [
+            b"bet",
+            bet.player.as_ref(),
+            &bet.user_seed.to_le_bytes()
+        ],
+        bump = bet.bump,
+        constraint = bet.kind == BetKind::Vrf @ ErrorCode::WrongBetKind
+    )]
+    pub bet: Account<'info, Bet>,
+    #[account(
+        mut,
+        seeds = [
+            b"player-stats",
+            bet.player.as_ref()
+        ],
+        bump = player_stats.bump
+    )]
+    pub player_stats: Account<'info, PlayerStats>,
+    /// The bet's referrer, required when it was placed with one.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            bet.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    /// CHECK: owned by the reserve's oracle, the seed is checked against the bet in the handler
+    #[account(owner = reserve.vrf_program @ ErrorCode::MismatchedVrfProgram)]
+    pub randomness: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"reserve-1",
+            reserve.table_id.to_le_bytes().as_ref()
+        ],
+        bump = reserve.bump,
+        constraint = reserve.key() == bet.reserve @ ErrorCode::WrongTable
+    )]
+    pub reserve: Account<'info, Reserve>,
     /// CHECK: this is checked against the pubkey in the reserve struct
     #[account(mut, address = reserve.house)]
     pub house: AccountInfo<'info>,
     /// CHECK: This is checked against the pubkey in the reserve struct
     #[account(mut, address = reserve.reserve_key)]
     pub reserve_key: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"exposure",
+            reserve.key().as_ref(),
+            bet.player.as_ref()
+        ],
+        bump = player_exposure.bump
+    )]
+    pub player_exposure: Account<'info, PlayerExposure>,
     pub system_program: Program<'info, System>,
-    /// CHECK: the address is constrained
-    #[account(address = slot_hashes::id())]
-    slot_hashes: UncheckedAccount<'info>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct RollRecord {
+    pub bet_size: u64,
+    pub multiplier_bp: u64,
+    pub roll_bp: u64,
+    pub payout: u64,
+    pub slot: u64
+}
+
+impl RollRecord {
+    const LEN: usize = U64_LENGTH * 5;
+}
+
+#[account]
+pub struct PlayerStats {
+    pub player: Pubkey,
+    pub total_wagered: u64,
+    pub total_won: u64,
+    pub roll_count: u64,
+    pub biggest_win: u64,
+    pub history: [RollRecord; HISTORY_LENGTH],
+    pub bump: u8,
+    /// First referrer the player rolled with, `Pubkey::default()` if none.
+    pub referrer: Pubkey
+}
+
+impl PlayerStats {
+    const LEN: usize = DISCRIMINATOR_LENGTH + PUBLIC_KEY_LENGTH + (U64_LENGTH * 4) + (RollRecord::LEN * HISTORY_LENGTH) + U8_LENGTH + PUBLIC_KEY_LENGTH;
+
+    /// Adds a settled roll to the totals, `history` is a ring buffer indexed by `roll_count`.
+    fn record_roll(&mut self, bet_size: u64, multiplier_bp: u64, roll_bp: u64, payout: u64, slot: u64) -> Result<()> {
+        self.history[(self.roll_count % HISTORY_LENGTH as u64) as usize] = RollRecord {
+            bet_size,
+            multiplier_bp,
+            roll_bp,
+            payout,
+            slot,
+        };
+        self.total_wagered = self.total_wagered.checked_add(bet_size).ok_or(ErrorCode::MathOverflow)?;
+        self.total_won = self.total_won.checked_add(payout).ok_or(ErrorCode::MathOverflow)?;
+        self.roll_count = self.roll_count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        self.biggest_win = self.biggest_win.max(payout);
+        Ok(())
+    }
+}
+
+#[account]
+pub struct Referrer {
+    pub referrer: Pubkey,
+    pub total_earned: u64,
+    pub referral_count: u64,
+    pub bump: u8
+}
+
+impl Referrer {
+    const LEN: usize = DISCRIMINATOR_LENGTH + PUBLIC_KEY_LENGTH + (U64_LENGTH * 2) + U8_LENGTH;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BetKind {
+    SlotHash,
+    HouseSeed,
+    Vrf,
+    Instant,
+}
+
+/// What a roll has to land on to win.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BetType {
+    /// Roll under the threshold that pays `multiplier_bp`.
+    Multiplier { multiplier_bp: u64 },
+    /// Roll under `target`.
+    Under { target: u64 },
+    /// Roll over `target`.
+    Over { target: u64 },
+    /// Roll from `low` to `high`, inclusive.
+    InRange { low: u64, high: u64 },
+    /// Roll under `low` or over `high`.
+    OutOfRange { low: u64, high: u64 },
+}
+
+/// One bet of a `roll_dice_batch` or `commit_roll_batch`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BatchBet {
+    pub bet_type: BetType,
+    pub bet_size: u64,
+}
+
+/// Result of one roll of a `roll_dice_batch`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BatchRoll {
+    pub bet_type: BetType,
+    pub multiplier_bp: u64,
+    pub bet_size: u64,
+    /// Number of winning rolls out of `ROLL_OUTCOMES`.
+    pub threshold_bp: u64,
+    pub roll_bp: u64,
+    pub payout: u64,
+}
+
+/// One roll of a `commit_roll_batch`, with the odds locked in when it was committed.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CommittedRoll {
+    pub bet_type: BetType,
+    pub bet_size: u64,
+    pub multiplier_bp: u64,
+    pub threshold_bp: u64,
+}
+
+impl CommittedRoll {
+    const LEN: usize = BetType::LEN + (U64_LENGTH * 3);
+}
+
+impl BetType {
+    const LEN: usize = U8_LENGTH + (U64_LENGTH * 2);
+
+    /// Multiplier and number of winning rolls, targets that always or never win are rejected.
+    fn get_odds(&self, edge_bp: u64) -> Result<(u64, u64)> {
+        let winning_rolls = match *self {
+            BetType::Multiplier { multiplier_bp } => return Ok((multiplier_bp, get_threshold_bp(multiplier_bp, edge_bp)?)),
+            BetType::Under { target } => target,
+            BetType::Over { target } => (ROLL_OUTCOMES - 1).checked_sub(target).ok_or(ErrorCode::InvalidBetTarget)?,
+            BetType::InRange { low, high } | BetType::OutOfRange { low, high } => {
+                require!(low <= high && high < ROLL_OUTCOMES, ErrorCode::InvalidBetTarget);
+                let in_range = high - low + 1;
+                if let BetType::InRange { .. } = self { in_range } else { ROLL_OUTCOMES - in_range }
+            }
+        };
+        require!(0 < winning_rolls && winning_rolls < ROLL_OUTCOMES, ErrorCode::InvalidBetTarget);
+        Ok((get_multiplier_bp(winning_rolls, edge_bp)?, winning_rolls))
+    }
+
+    /// Reorders the rolls so the winning ones come first, a roll wins when its outcome is below the number of winning rolls.
+    fn get_outcome(&self, roll_bp: u64) -> u64 {
+        match *self {
+            BetType::Multiplier { .. } | BetType::Under { .. } => roll_bp,
+            BetType::Over { .. } => ROLL_OUTCOMES - 1 - roll_bp,
+            BetType::InRange { low, .. } => (roll_bp + ROLL_OUTCOMES - low) % ROLL_OUTCOMES,
+            BetType::OutOfRange { high, .. } => (roll_bp + ROLL_OUTCOMES - high - 1) % ROLL_OUTCOMES,
+        }
+    }
+}
+
+#[account]
+pub struct Bet {
+    pub player: Pubkey,
+    pub kind: BetKind,
+    pub user_seed: u64,
+    pub multiplier_bp: u64,
+    pub bet_size: u64,
+    pub commitment: [u8; 32],
+    pub commit_slot: u64,
+    pub target_slot: u64,
+    pub nonce: u64,
+    pub bump: u8,
+    /// Table the bet was placed at, only its reserve can settle it.
+    pub reserve: Pubkey,
+    /// Odds when the bet was placed, config changes before settling don't reprice it.
+    pub edge_bp: u64,
+    pub threshold_bp: u64,
+    pub bet_type: BetType,
+    /// Referrer bound to the player when the bet was placed, `Pubkey::default()` if none.
+    pub referrer: Pubkey
+}
+
+impl Bet {
+    const LEN: usize = DISCRIMINATOR_LENGTH + PUBLIC_KEY_LENGTH + U8_LENGTH + (U64_LENGTH * 6) + 32 + U8_LENGTH + PUBLIC_KEY_LENGTH + (U64_LENGTH * 2) + BetType::LEN + PUBLIC_KEY_LENGTH;
+
+    /// Locks in the reserve's current odds for `bet_type`.
+    fn set_odds(&mut self, reserve: &Reserve, bet_type: BetType) -> Result<()> {
+        let (multiplier_bp, threshold_bp) = bet_type.get_odds(reserve.edge_bp)?;
+        self.bet_type = bet_type;
+        self.multiplier_bp = multiplier_bp;
+        self.edge_bp = reserve.edge_bp;
+        self.threshold_bp = threshold_bp;
+        Ok(())
+    }
+
+    /// What the bet pays if it wins.
+    fn payout(&self) -> Result<u64> {
+        get_payout(self.bet_size, self.multiplier_bp)
+    }
+}
+
+/// Escrows the stakes of a `commit_roll_batch` until `settle_roll_batch`.
+#[account]
+pub struct BatchCommit {
+    pub player: Pubkey,
+    /// Table the batch was placed at, only its reserve can settle it.
+    pub reserve: Pubkey,
+    pub user_seed: u64,
+    pub commitment: [u8; 32],
+    pub commit_slot: u64,
+    pub target_slot: u64,
+    pub edge_bp: u64,
+    pub total_bet: u64,
+    pub bump: u8,
+    pub rolls: Vec<CommittedRoll>,
+    /// Referrer bound to the player when the batch was placed, `Pubkey::default()` if none.
+    pub referrer: Pubkey
+}
+
+impl BatchCommit {
+    const LEN: usize = DISCRIMINATOR_LENGTH + (PUBLIC_KEY_LENGTH * 2) + U64_LENGTH + 32 + (U64_LENGTH * 4) + U8_LENGTH + 4 + (CommittedRoll::LEN * MAX_BATCH_ROLLS) + PUBLIC_KEY_LENGTH;
+
+    /// What the batch pays if every roll wins.
+    fn max_payout(&self) -> Result<u64> {
+        self.rolls.iter().try_fold(0u64, |total, roll| total.checked_add(get_payout(roll.bet_size, roll.multiplier_bp)?).ok_or(error!(ErrorCode::MathOverflow)))
+    }
+}
+
+#[event]
+pub struct ConfigChanged {
+    pub authority: Pubkey,
+    pub edge_bp: u64,
+    pub ratio: u64,
+    pub house: Pubkey,
+    pub house_fee_bp: u64,
+}
+
+#[event]
+pub struct AuthorityProposed {
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+}
+
+#[event]
+pub struct AuthorityChanged {
+    pub old_authority: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+#[event]
+pub struct BackdoorAuthorityRevoked {
+    pub authority: Pubkey,
+}
+
+#[event]
+pub struct RolesChanged {
+    pub config_admin: Pubkey,
+    pub treasury_admin: Pubkey,
+    pub pauser: Pubkey,
+}
+
+#[event]
+pub struct PausedChanged {
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct CircuitBreakerTripped {
+    pub mint: Pubkey,
+    pub window_start: u64,
+    pub window_balance: u64,
+    pub balance: u64,
+}
+
+#[event]
+pub struct InstantRollsChanged {
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub instant_rolls: bool,
+}
+
+#[event]
+pub struct CircuitBreakerChanged {
+    pub authority: Pubkey,
+    pub drawdown_bp: u64,
+    pub window_slots: u64,
+}
+
+#[event]
+pub struct ReferralFeeChanged {
+    pub authority: Pubkey,
+    pub referral_fee_bp: u64,
+}
+
+#[event]
+pub struct BetLimitsChanged {
+    pub authority: Pubkey,
+    pub min_multiplier_bp: u64,
+    pub max_multiplier_bp: u64,
+    pub min_bet: u64,
+}
+
+#[event]
+pub struct VrfProgramChanged {
+    pub authority: Pubkey,
+    pub vrf_program: Pubkey,
+}
+
+#[event]
+pub struct ExposureLimitsChanged {
+    pub authority: Pubkey,
+    pub max_net_payout: u64,
+    pub exposure_window_slots: u64,
+    pub max_player_payout: u64,
+}
+
+#[event]
+pub struct FeeRecipientsChanged {
+    pub authority: Pubkey,
+    pub house_fee_bp: u64,
+    pub fee_recipients: Vec<FeeRecipient>,
+}
+
+#[event]
+pub struct ReserveFunded {
+    pub funder: Pubkey,
+    pub reserve_key: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+}
+
+#[event]
+pub struct LegacyReserveMigrated {
+    pub authority: Pubkey,
+    pub table_id: u16,
+    pub reserve_key: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct WithdrawalRequested {
+    pub amount: u64,
+    pub unlock_time: i64,
+}
+
+#[event]
+pub struct ReserveWithdrawn {
+    pub authority: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+}
+
+#[event]
+pub struct WithdrawPolicyChanged {
+    pub authority: Pubkey,
+    pub withdraw_floor: u64,
+    pub withdraw_timelock: i64,
+}
+
+#[event]
+pub struct LiquidityDeposited {
+    pub provider: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+}
+
+#[event]
+pub struct LpWithdrawalRequested {
+    pub provider: Pubkey,
+    pub shares: u64,
+    pub unlock_time: i64,
+}
+
+#[event]
+pub struct LiquidityWithdrawn {
+    pub provider: Pubkey,
+    pub shares: u64,
+    pub amount: u64,
+    pub balance: u64,
+}
+
+#[event]
+pub struct ReferrerBound {
+    pub player: Pubkey,
+    pub referrer: Pubkey,
+}
+
+#[event]
+pub struct ReferralClaimed {
+    pub referrer: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct SeedChainRotated {
+    pub old_head: [u8; 32],
+    pub new_head: [u8; 32],
+    pub nonce: u64,
+}
+
+#[event]
+pub struct BetPlaced {
+    pub bet: Pubkey,
+    pub player: Pubkey,
+    pub kind: BetKind,
+    pub user_seed: u64,
+    pub multiplier_bp: u64,
+    pub bet_size: u64,
+    pub commitment: [u8; 32],
+    pub target_slot: u64,
+    pub nonce: u64,
+}
+
+#[event]
+pub struct BatchPlaced {
+    pub batch: Pubkey,
+    pub player: Pubkey,
+    pub user_seed: u64,
+    pub total_bet: u64,
+    pub rolls: Vec<CommittedRoll>,
+    pub commitment: [u8; 32],
+    pub target_slot: u64,
+}
+
+/// Emitted for every `roll_dice_batch` and `settle_roll_batch`, each roll's seed is derived from `randomness` and its index.
+#[event]
+pub struct DiceBatchRolled {
+    pub player: Pubkey,
+    pub table_id: u16,
+    pub mint: Pubkey,
+    pub user_seed: u64,
+    pub edge_bp: u64,
+    pub randomness: [u8; 32],
+    pub total_bet: u64,
+    pub total_payout: u64,
+    pub rolls: Vec<BatchRoll>,
+    pub slot: u64,
+}
+
+/// Emitted for every settled roll, `roll_bp` is `EXPIRED_ROLL_BP` when a committed roll expired
+/// or a fair roll was claimed unrevealed.
+#[event]
+pub struct DiceRolled {
+    pub player: Pubkey,
+    pub table_id: u16,
+    pub mint: Pubkey,
+    pub kind: BetKind,
+    pub bet_type: BetType,
+    pub user_seed: u64,
+    pub multiplier_bp: u64,
+    pub bet_size: u64,
+    pub edge_bp: u64,
+    /// Number of winning rolls out of `ROLL_OUTCOMES`.
+    pub threshold_bp: u64,
+    pub randomness: [u8; 32],
+    pub roll_bp: u64,
+    pub payout: u64,
+    pub slot: u64,
 }
 
 #[error_code]
@@ -267,5 +3987,101 @@ pub enum ErrorCode {
     #[msg("Mismatched reserve-key address")]
     MismatchedReserveKey,
     #[msg("Mismatched slot-hashes key")]
-    MismatchedSlotHashes
+    MismatchedSlotHashes,
+    #[msg("Target slot has not passed yet")]
+    RollNotReady,
+    #[msg("Bet cannot be settled this way")]
+    WrongBetKind,
+    #[msg("No seed chain published")]
+    NoSeedChain,
+    #[msg("Server seed does not hash to the chain head")]
+    InvalidServerSeed,
+    #[msg("Fair rolls must be revealed in nonce order")]
+    WrongRevealOrder,
+    #[msg("Fair rolls are still waiting for a reveal")]
+    PendingFairRolls,
+    #[msg("Randomness has not been fulfilled yet")]
+    RandomnessNotFulfilled,
+    #[msg("Mismatched randomness account")]
+    MismatchedRandomness,
+    #[msg("Invalid slot-hashes data")]
+    InvalidSlotHashes,
+    #[msg("Withdrawal would take the reserve below its floor")]
+    BelowWithdrawFloor,
+    #[msg("Withdrawal is still timelocked")]
+    WithdrawalLocked,
+    #[msg("Withdrawal does not match the pending request")]
+    NoPendingWithdrawal,
+    #[msg("Withdraw timelock can only be increased")]
+    TimelockDecrease,
+    #[msg("Reserve is a house pool, withdraw through lp shares")]
+    LpPoolActive,
+    #[msg("Deposit too small to mint a share")]
+    DepositTooSmall,
+    #[msg("Not enough lp shares")]
+    InsufficientShares,
+    #[msg("Fees exceed the maximum share of a bet")]
+    FeeTooHigh,
+    #[msg("Too many fee recipients")]
+    TooManyFeeRecipients,
+    #[msg("Mismatched fee recipient")]
+    MismatchedFeeRecipient,
+    #[msg("Referral fee can't exceed the house fee")]
+    BadReferralFee,
+    #[msg("Player is bound to a different referrer")]
+    MismatchedReferrer,
+    #[msg("Edge is out of bounds")]
+    InvalidEdge,
+    #[msg("Ratio is too small")]
+    InvalidRatio,
+    #[msg("House can't be the default address")]
+    InvalidHouse,
+    #[msg("Invalid multiplier range")]
+    InvalidMultiplierRange,
+    #[msg("Minimum bet must be positive")]
+    InvalidMinBet,
+    #[msg("Multiplier is out of range")]
+    MultiplierOutOfRange,
+    #[msg("Bet too small")]
+    BetTooSmall,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Signer is not the pending authority")]
+    NotPendingAuthority,
+    #[msg("Reserve is paused")]
+    Paused,
+    #[msg("Circuit breaker drawdown must be at most 10000 bp with a non-empty window")]
+    InvalidCircuitBreaker,
+    #[msg("Bet was placed at a different table")]
+    WrongTable,
+    #[msg("Exposure window needs a length when the net payout limit is set")]
+    InvalidExposureLimits,
+    #[msg("Bet exceeds the reserve's remaining payout budget for this window")]
+    ExposureLimitExceeded,
+    #[msg("Bet exceeds the player's remaining payout budget for this epoch")]
+    PlayerPayoutLimitExceeded,
+    #[msg("Bet target leaves no winning or no losing rolls")]
+    InvalidBetTarget,
+    #[msg("Batch must hold between 1 and MAX_BATCH_ROLLS bets")]
+    InvalidBatchSize,
+    #[msg("No vrf oracle is configured for this reserve")]
+    VrfDisabled,
+    #[msg("Account doesn't belong to the reserve's vrf oracle")]
+    MismatchedVrfProgram,
+    #[msg("Instant rolls are disabled for this reserve")]
+    InstantRollsDisabled,
+    #[msg("Bet can't be claimed before the reveal timeout")]
+    RevealNotTimedOut,
+    #[msg("Lp withdrawal expired, request it again")]
+    WithdrawalExpired,
+    #[msg("Cooldown can't be negative")]
+    InvalidCooldown,
+    #[msg("Account is not a legacy reserve")]
+    NotLegacyReserve,
+    #[msg("Signer is not the legacy reserve's authority")]
+    NotLegacyAuthority,
+    #[msg("Liquidity can't be withdrawn within the cooldown of a deposit")]
+    DepositLocked,
+    #[msg("Mints that withhold a fee on transfer aren't supported")]
+    TransferFeeMint
 }
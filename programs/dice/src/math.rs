@@ -0,0 +1,179 @@
+//! Payout math for the dice reserves.
+//!
+//! Products are widened to u128 and narrowed back with a check, so an out of range input
+//! fails the roll with `ErrorCode::MathOverflow` instead of panicking under overflow-checks.
+
+use anchor_lang::prelude::*;
+
+use crate::ErrorCode;
+
+const BP: u128 = 10_000;
+
+/// `a * b / divisor` without intermediate overflow.
+fn mul_div(a: u64, b: u64, divisor: u128) -> Result<u64> {
+    let result = (a as u128 * b as u128)
+        .checked_div(divisor)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(u64::try_from(result).map_err(|_| ErrorCode::MathOverflow)?)
+}
+
+/// Part of `balance` a roll can use, the rest keeps the reserve rent exempt.
+pub fn get_available_balance(balance: u64, minimum_balance: u64) -> Result<u64> {
+    Ok(balance.checked_sub(minimum_balance).ok_or(ErrorCode::MathOverflow)?)
+}
+
+/// Largest bet that keeps a win below `1 / ratio` of the available balance.
+pub fn get_max_bet(available_balance: u64, multiplier_bp: u64, ratio: u64) -> Result<u64> {
+    mul_div(available_balance, BP as u64, multiplier_bp as u128 * ratio as u128)
+}
+
+/// Largest total a batch of rolls may win, the batch counterpart of `get_max_bet`.
+pub fn get_max_payout(available_balance: u64, ratio: u64) -> Result<u64> {
+    Ok(available_balance.checked_div(ratio).ok_or(ErrorCode::MathOverflow)?)
+}
+
+/// Default smallest bet of a token reserve, a millionth of a whole token and at least one base unit.
+pub fn get_min_bet(decimals: u8) -> Result<u64> {
+    Ok(10u64.checked_pow(decimals.saturating_sub(6) as u32).ok_or(ErrorCode::MathOverflow)?)
+}
+
+pub fn get_payout(bet_size: u64, multiplier_bp: u64) -> Result<u64> {
+    mul_div(bet_size, multiplier_bp, BP)
+}
+
+pub fn get_fee(amount: u64, fee_bp: u64) -> Result<u64> {
+    mul_div(amount, fee_bp, BP)
+}
+
+/// Rolls below the threshold win, the edge pushes it below the fair `10_000 / multiplier`.
+pub fn get_threshold_bp(multiplier_bp: u64, edge_bp: u64) -> Result<u64> {
+    let odds_bp = multiplier_bp.checked_add(edge_bp).ok_or(ErrorCode::MathOverflow)?;
+    mul_div(BP as u64, BP as u64, odds_bp as u128)
+}
+
+/// Inverse of `get_threshold_bp`, the multiplier paying out on `winning_rolls` of every 10_000.
+pub fn get_multiplier_bp(winning_rolls: u64, edge_bp: u64) -> Result<u64> {
+    Ok(mul_div(BP as u64, BP as u64, winning_rolls as u128)?.checked_sub(edge_bp).ok_or(ErrorCode::MathOverflow)?)
+}
+
+/// Shares minted for `amount`, 1:1 with lamports while the pool is empty.
+pub fn get_shares_for_deposit(amount: u64, supply: u64, pool_value: u64) -> Result<u64> {
+    if supply == 0 || pool_value == 0 {
+        return Ok(amount);
+    }
+    mul_div(amount, supply, pool_value as u128)
+}
+
+pub fn get_withdrawal_for_shares(shares: u64, supply: u64, pool_value: u64) -> Result<u64> {
+    mul_div(shares, pool_value, supply as u128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn overflow() -> Error {
+        ErrorCode::MathOverflow.into()
+    }
+
+    #[test]
+    fn zero_divisors_are_errors() {
+        assert_eq!(get_max_bet(1_000, 0, 5).unwrap_err(), overflow());
+        assert_eq!(get_max_bet(1_000, 20_000, 0).unwrap_err(), overflow());
+        assert_eq!(get_max_payout(1_000, 0).unwrap_err(), overflow());
+        assert_eq!(get_threshold_bp(0, 0).unwrap_err(), overflow());
+        assert_eq!(get_withdrawal_for_shares(1, 0, 1_000).unwrap_err(), overflow());
+    }
+
+    #[test]
+    fn min_bets_scale_with_decimals() {
+        assert_eq!(get_min_bet(0).unwrap(), 1);
+        assert_eq!(get_min_bet(6).unwrap(), 1);
+        assert_eq!(get_min_bet(9).unwrap(), 1_000);
+        assert_eq!(get_min_bet(26).unwrap_err(), overflow());
+    }
+
+    #[test]
+    fn drained_reserve_is_an_error() {
+        assert_eq!(get_available_balance(890_879, 890_880).unwrap_err(), overflow());
+        assert_eq!(get_available_balance(890_880, 890_880).unwrap(), 0);
+    }
+
+    #[test]
+    fn results_above_u64_are_errors() {
+        assert_eq!(get_payout(u64::MAX, 20_000).unwrap_err(), overflow());
+        assert_eq!(get_max_bet(u64::MAX, 10_000, 1).unwrap(), u64::MAX);
+        assert_eq!(get_max_bet(u64::MAX, 5_000, 1).unwrap_err(), overflow());
+        assert_eq!(get_threshold_bp(u64::MAX, 1).unwrap_err(), overflow());
+    }
+
+    #[test]
+    fn thresholds_match_the_old_formula() {
+        for multiplier_bp in 10_000..=30_000 {
+            for edge_bp in (1..=10_000).step_by(97) {
+                assert_eq!(get_threshold_bp(multiplier_bp, edge_bp).unwrap(), (10_000 * 10_000) / (multiplier_bp + edge_bp));
+            }
+        }
+    }
+
+    #[test]
+    fn small_payouts_match_the_old_formula() {
+        for bet_size in 0..=1_000 {
+            for multiplier_bp in (10_000..=1_000_000).step_by(997) {
+                assert_eq!(get_payout(bet_size, multiplier_bp).unwrap(), (bet_size * multiplier_bp) / 10_000);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn payouts_never_panic(bet_size in any::<u64>(), multiplier_bp in any::<u64>()) {
+            let exact = bet_size as u128 * multiplier_bp as u128 / 10_000;
+            match get_payout(bet_size, multiplier_bp) {
+                Ok(payout) => prop_assert_eq!(payout as u128, exact),
+                Err(_) => prop_assert!(exact > u64::MAX as u128),
+            }
+        }
+
+        #[test]
+        fn fees_never_exceed_the_amount(amount in any::<u64>(), fee_bp in 0u64..=10_000) {
+            prop_assert!(get_fee(amount, fee_bp).unwrap() <= amount);
+        }
+
+        #[test]
+        fn max_bet_wins_fit_the_reserve(balance in any::<u64>(), multiplier_bp in 10_000u64..=1_000_000, ratio in 1u64..1_000) {
+            let max_bet = get_max_bet(balance, multiplier_bp, ratio).unwrap();
+            let payout = get_payout(max_bet, multiplier_bp).unwrap();
+            prop_assert!(payout as u128 * ratio as u128 <= balance as u128);
+        }
+
+        #[test]
+        fn max_bets_fit_the_max_payout(balance in any::<u64>(), multiplier_bp in 10_000u64..=1_000_000, ratio in 1u64..1_000) {
+            let max_bet = get_max_bet(balance, multiplier_bp, ratio).unwrap();
+            prop_assert!(get_payout(max_bet, multiplier_bp).unwrap() <= get_max_payout(balance, ratio).unwrap());
+        }
+
+        #[test]
+        fn thresholds_stay_in_range(multiplier_bp in 10_000u64..=1_000_000, edge_bp in 1u64..=10_000) {
+            prop_assert!(get_threshold_bp(multiplier_bp, edge_bp).unwrap() < 10_000);
+        }
+
+        #[test]
+        fn multipliers_never_pay_more_than_the_odds(winning_rolls in 1u64..10_000, edge_bp in 1u64..=10_000) {
+            let multiplier_bp = get_multiplier_bp(winning_rolls, edge_bp).unwrap();
+            prop_assert!(get_threshold_bp(multiplier_bp, edge_bp).unwrap() >= winning_rolls);
+            prop_assert!((multiplier_bp as u128) * (winning_rolls as u128) < 10_000 * 10_000);
+        }
+
+        #[test]
+        fn shares_round_trip_down(amount in 1u64..u64::MAX / 4, supply in 1u64..u64::MAX / 4, pool_value in 1u64..u64::MAX / 4) {
+            // a deposit can never be redeemed for more than was put in
+            if let Ok(shares) = get_shares_for_deposit(amount, supply, pool_value) {
+                prop_assume!(supply.checked_add(shares).is_some());
+                let redeemed = get_withdrawal_for_shares(shares, supply + shares, pool_value + amount).unwrap();
+                prop_assert!(redeemed <= amount);
+            }
+        }
+    }
+}
@@ -1,24 +1,28 @@
 use anchor_lang::prelude::*;
-// use anchor_spl::token::{self, TokenAccount, Transfer, Token};
-use sha3::{Digest, Sha3_256};
+use anchor_spl::token_interface::{self, Burn, Mint, TokenAccount, TokenInterface, TransferChecked};
+use arcade_rng::{derive_seed, uniform, SlotHashesData, LOTTERY_DOMAIN};
+use solana_program::native_token::LAMPORTS_PER_SOL;
 use solana_program::pubkey::Pubkey;
-use solana_program::sysvar::slot_hashes::{SlotHashes};
 use solana_program::sysvar::slot_hashes;
-use arrayref::array_ref;
+use arcade_vrf::Randomness;
 
 
 declare_id!("GPQoxR32g2heKCf5DeKWnR4CyGC1qnBVXzW8kiCR3pBz");
 
 const DISCRIMINATOR_LENGTH: usize = 8;
 const PUBLIC_KEY_LENGTH: usize = 32;
-const TIMESTAMP_LENGTH: usize = 8;
+/// System account that holds the burn share for the length of the swap into `burn_token`.
+pub const SWAP_AUTHORITY_SEED: &[u8] = b"swap-authority";
+/// Programs whose swap `burn_round` can call, they take `mock_amm`'s swap accounts.
+pub const ALLOWED_AMM_PROGRAMS: [Pubkey; 1] = [mock_amm::ID];
+/// Widest `burn_slippage_bp` the config admin can set.
+pub const MAX_BURN_SLIPPAGE_BP: u64 = 500;
 
 #[program]
 pub mod lottery {
-    use std::hash::Hash;
-    use solana_program::slot_hashes::SlotHash;
     use super::*;
 
+    #[allow(clippy::too_many_arguments)]
     pub fn setup_lottery(ctx: Context<SetupLottery>,
                       lottery_num: u8,
                       initial_duration: u64,
@@ -31,6 +35,8 @@ pub mod lottery {
                       burn_address: Pubkey,
                       burn_token: Pubkey,
                       team_address: Pubkey,
+                      keeper_bounty_bp: u64,
+                      referral_fee_bp: u64,
     ) -> Result<()> {
         let config = &mut ctx.accounts.config;
 
@@ -50,23 +56,30 @@ pub mod lottery {
         // msg!("Bump seed: {}", bump_seed);
 
 
-        config.lottery_num = lottery_num;
-        config.initial_duration = initial_duration;
-        config.duration_extension = duration_extension;
-        config.min_deposit = min_deposit;
-        config.burn_pct = burn_pct;
-        config.last_depositor_pct = last_depositor_pct;
-        config.team_pct = team_pct;
-        config.winner_pct = winner_pct;
-        config.burn_address = burn_address;
-        config.burn_token = burn_token;
-        config.team_address = team_address;
-        require!(burn_pct + team_pct + winner_pct + last_depositor_pct == 90, ErrorCode::BadPercentages);
-        require!(burn_pct < 100 && team_pct < 100 && winner_pct < 100 && last_depositor_pct < 100, ErrorCode::BadPercentages);
+        configure_lottery(
+            config,
+            lottery_num,
+            initial_duration,
+            duration_extension,
+            min_deposit,
+            burn_pct,
+            last_depositor_pct,
+            team_pct,
+            winner_pct,
+            burn_address,
+            burn_token,
+            team_address,
+            keeper_bounty_bp,
+            referral_fee_bp,
+            Pubkey::default(),
+        )?;
+        set_roles(config, ctx.accounts.creator.key());
 
         let state = &mut ctx.accounts.state;
+        state.bump = ctx.bumps.state;
         state.num_rounds = 1;
         state.tickets_bought = 0;
+        state.total_burned = 0;
 
         let first_round = &mut ctx.accounts.first_round;
         first_round.bump = ctx.bumps.first_round;
@@ -75,90 +88,440 @@ pub mod lottery {
         Ok(())
     }
 
-    pub fn close_round(ctx: Context<CloseRound>, user_seed: u64) -> Result<()> {
-        let previous_round = &mut ctx.accounts.previous_round;
-        let next_round = &mut ctx.accounts.next_round;
-        let state = &mut ctx.accounts.state;
+    /// Same as `setup_lottery` but tickets are bought with `burn_token`, which is burned instead of sent to `burn_address`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn setup_token_lottery(ctx: Context<SetupTokenLottery>,
+                      lottery_num: u8,
+                      initial_duration: u64,
+                      duration_extension: u64,
+                      min_deposit: u64,
+                      burn_pct: u8,
+                      last_depositor_pct: u8,
+                      team_pct: u8,
+                      winner_pct: u8,
+                      burn_address: Pubkey,
+                      burn_token: Pubkey,
+                      team_address: Pubkey,
+                      keeper_bounty_bp: u64,
+                      referral_fee_bp: u64,
+    ) -> Result<()> {
         let config = &mut ctx.accounts.config;
+        require_keys_eq!(burn_token, ctx.accounts.mint.key(), ErrorCode::WrongLotteryKind);
+
+        configure_lottery(
+            config,
+            lottery_num,
+            initial_duration,
+            duration_extension,
+            min_deposit,
+            burn_pct,
+            last_depositor_pct,
+            team_pct,
+            winner_pct,
+            burn_address,
+            burn_token,
+            team_address,
+            keeper_bounty_bp,
+            referral_fee_bp,
+            burn_token,
+        )?;
+        set_roles(config, ctx.accounts.creator.key());
+
+        let state = &mut ctx.accounts.state;
+        state.bump = ctx.bumps.state;
+        state.num_rounds = 1;
+        state.tickets_bought = 0;
+        state.total_burned = 0;
+
+        let first_round = &mut ctx.accounts.first_round;
+        first_round.bump = ctx.bumps.first_round;
+        initialize_round(lottery_num, 1, 0, config.initial_duration, 0, first_round);
+
+        Ok(())
+    }
+
+    /// Moves a lottery set up before the config had roles and the state lived at a keypair.
+    /// The legacy config has no admin, so the program's upgrade authority takes every role.
+    /// The lottery comes out paused, its rounds go through `migrate_legacy_round` before the
+    /// pauser lifts it.
+    pub fn migrate_legacy_lottery(ctx: Context<MigrateLegacyLottery>, lottery_num: u8) -> Result<()> {
+        let config_info = ctx.accounts.config.to_account_info();
+        let legacy_state = ctx.accounts.legacy_state.to_account_info();
+        let authority = &ctx.accounts.authority;
+
+        let legacy = LegacyLotteryConfig::load(&config_info)?;
+        require!(legacy.lottery_num == lottery_num, ErrorCode::NotLegacyAccount);
+        let legacy_totals = LegacyLotteryState::load(&legacy_state)?;
+
+        let mut config = LotteryConfig {
+            lottery_num,
+            initial_duration: legacy.initial_duration,
+            duration_extension: legacy.duration_extension,
+            min_deposit: legacy.min_deposit,
+            burn_pct: legacy.burn_pct,
+            last_depositor_pct: legacy.last_depositor_pct,
+            team_pct: legacy.team_pct,
+            winner_pct: legacy.winner_pct,
+            burn_address: legacy.burn_address,
+            burn_token: legacy.burn_token,
+            team_address: legacy.team_address,
+            keeper_bounty_bp: 0,
+            mint: Pubkey::default(),
+            referral_fee_bp: 0,
+            config_admin: Pubkey::default(),
+            treasury_admin: Pubkey::default(),
+            pauser: Pubkey::default(),
+            paused: true,
+            vrf_program: Pubkey::default(),
+            amm_program: Pubkey::default(),
+            burn_slippage_bp: 0,
+            burn_price: 0,
+        };
+        set_roles(&mut config, authority.key());
+        grow_legacy_account(&config_info, LotteryConfig::LEN, authority, &ctx.accounts.system_program)?;
+        config.try_serialize(&mut &mut config_info.try_borrow_mut_data()?[..])?;
 
+        let state = &mut ctx.accounts.state;
+        state.bump = ctx.bumps.state;
+        state.num_rounds = legacy_totals.num_rounds;
+        state.tickets_bought = legacy_totals.tickets_bought;
+        state.total_burned = 0;
+
+        let legacy_rent = legacy_state.lamports();
+        legacy_state.sub_lamports(legacy_rent)?;
+        authority.add_lamports(legacy_rent)?;
+        legacy_state.assign(&System::id());
+        legacy_state.realloc(0, false)?;
+
+        emit!(LegacyLotteryMigrated {
+            lottery_num,
+            authority: authority.key(),
+            num_rounds: state.num_rounds,
+            tickets_bought: state.tickets_bought,
+        });
+
+        Ok(())
+    }
+
+    /// Moves one round of a migrated lottery, in order from the first. Legacy closes never moved
+    /// the carry-over, so it comes out of the previous round here. Legacy draws picked a ticket
+    /// index rather than a point of the pot and legacy claims paid nothing, so closed rounds are
+    /// drawn again the way `close_round` draws them.
+    pub fn migrate_legacy_round(ctx: Context<MigrateLegacyRound>, round_num: u32, user_seed: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.paused, ErrorCode::NotPaused);
+        require!(ctx.accounts.previous_round.is_some() == (round_num > 1), ErrorCode::MismatchedRound);
+
+        let round_info = ctx.accounts.round.to_account_info();
+        let legacy = LegacyLotteryRound::load(&round_info)?;
+        require!(legacy.lottery_num == config.lottery_num && legacy.round_num == round_num, ErrorCode::NotLegacyAccount);
+
+        let closed = round_num < ctx.accounts.state.num_rounds;
+        let winning_number = if closed {
+            let data = ctx.accounts.slot_hashes.data.borrow();
+            let (_, recent_hash) = SlotHashesData::new(&data)
+                .and_then(|slot_hashes| slot_hashes.most_recent())
+                .ok_or(ErrorCode::NoBlockhash)?;
+            let clock = Clock::get()?;
+            let seed = derive_seed(LOTTERY_DOMAIN, &[&user_seed.to_le_bytes(), &clock.unix_timestamp.to_le_bytes(), &recent_hash]);
+            uniform(seed, legacy.pot_size).unwrap_or(0)
+        } else {
+            0
+        };
+
+        let round = LotteryRound {
+            lottery_num: legacy.lottery_num,
+            round_num,
+            tickets_bought: legacy.tickets_bought,
+            start_time: legacy.start_time,
+            end_time: legacy.end_time,
+            winning_number,
+            previous_pot_size: legacy.previous_pot_size,
+            pot_size: legacy.pot_size,
+            bump: ctx.bumps.round,
+            last_depositor: legacy.last_depositor,
+            winning_depositor: Pubkey::default(),
+            vrf_seed: [0u8; 32],
+            closed,
+            close_bounty: 0,
+            referral_paid: 0,
+            keeper_bounty: 0,
+            burn_pending: 0,
+        };
+        grow_legacy_account(&round_info, LotteryRound::LEN, &ctx.accounts.authority, &ctx.accounts.system_program)?;
+        round.try_serialize(&mut &mut round_info.try_borrow_mut_data()?[..])?;
+
+        if let Some(previous_round) = &ctx.accounts.previous_round {
+            move_lamports(&previous_round.to_account_info(), &round_info, previous_round.pot_size / 10)?;
+        }
+
+        emit!(LegacyRoundMigrated {
+            lottery_num: round.lottery_num,
+            round_num,
+            pot_size: round.pot_size,
+            closed,
+            winning_number,
+        });
+
+        Ok(())
+    }
+
+    pub fn close_round(ctx: Context<CloseRound>, user_seed: u64) -> Result<()> {
+        // once randomness is requested, or an oracle is configured, only close_round_vrf can draw the winner
+        require!(ctx.accounts.previous_round.vrf_seed == [0u8; 32], ErrorCode::RandomnessAlreadyRequested);
+        require!(ctx.accounts.config.vrf_program == Pubkey::default(), ErrorCode::VrfEnabled);
         let recent_slot_hashes = &ctx.accounts.slot_hashes;
         let data = recent_slot_hashes.data.borrow();
-        let most_recent = array_ref![data, 12, 8];
+        let (_, recent_hash) = SlotHashesData::new(&data)
+            .and_then(|slot_hashes| slot_hashes.most_recent())
+            .ok_or(ErrorCode::NoBlockhash)?;
         let clock = Clock::get()?;
 
-        // generate random number
-        let mut hasher = Sha3_256::new();
-        hasher.update(user_seed.to_le_bytes());
-        hasher.update(clock.unix_timestamp.to_le_bytes());
-        hasher.update(*most_recent);
-        let hashed_data = hasher.finalize();
-        let hash_bytes = hashed_data[..8].try_into().map_err(|_| ErrorCode::HashConversionFailed)?;
-        let winning_number = u64::from_le_bytes(hash_bytes);
-        previous_round.winning_number = winning_number % state.tickets_bought;
-
-        // initialize next round
-        state.num_rounds = state.num_rounds.checked_add(1).unwrap();
-        initialize_round(
-            config.lottery_num,
-            state.num_rounds,
-            state.tickets_bought,
-            config.initial_duration,
-            previous_round.pot_size / 10,
-            next_round
-        );
-        // next_round.bump = ctx.bumps.next_round;
+        // generate random seed
+        let seed = derive_seed(LOTTERY_DOMAIN, &[&user_seed.to_le_bytes(), &clock.unix_timestamp.to_le_bytes(), &recent_hash]);
+
+        finish_round(
+            &mut ctx.accounts.previous_round,
+            &mut ctx.accounts.next_round,
+            &mut ctx.accounts.state,
+            &ctx.accounts.config,
+            ctx.accounts.closer.key(),
+            seed,
+        )?;
+        ctx.accounts.next_round.bump = ctx.bumps.next_round;
+        carry_lamports(&ctx.accounts.previous_round, &ctx.accounts.next_round, &ctx.accounts.closer.to_account_info())?;
 
         Ok(())
     }
 
-    pub fn claim_winnings(ctx: Context<ClaimWinnings>, round_num: u64) -> Result<()> {
-        let ticket : &mut Account<LotteryTicket> = &mut ctx.accounts.ticket;
-        let round: &mut Account<LotteryRound> = &mut ctx.accounts.round;
+    pub fn request_round_randomness(ctx: Context<RequestRoundRandomness>, vrf_seed: [u8; 32]) -> Result<()> {
+        let round = &mut ctx.accounts.round;
+        require!(!ctx.accounts.config.paused, ErrorCode::Paused);
+        require!(ctx.accounts.config.vrf_program != Pubkey::default(), ErrorCode::VrfDisabled);
+
+        let clock: Clock = Clock::get()?;
+        require!((clock.unix_timestamp as u64) >= round.end_time, ErrorCode::LotteryNotEnded);
+        require!(!round.closed, ErrorCode::RoundAlreadyClosed);
+        require!(round.vrf_seed == [0u8; 32], ErrorCode::RandomnessAlreadyRequested);
+        require!(vrf_seed != [0u8; 32], ErrorCode::InvalidVrfSeed);
+        round.vrf_seed = vrf_seed;
+
+        arcade_vrf::request(
+            &ctx.accounts.vrf_program.to_account_info(),
+            &ctx.accounts.requester.to_account_info(),
+            &ctx.accounts.randomness.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            round.vrf_seed,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn close_round_vrf(ctx: Context<CloseRoundVrf>) -> Result<()> {
+        let randomness = load_randomness(&ctx.accounts.randomness, &ctx.accounts.previous_round)?;
+
+        let seed = derive_seed(LOTTERY_DOMAIN, &[&randomness.randomness]);
+
+        finish_round(
+            &mut ctx.accounts.previous_round,
+            &mut ctx.accounts.next_round,
+            &mut ctx.accounts.state,
+            &ctx.accounts.config,
+            ctx.accounts.closer.key(),
+            seed,
+        )?;
+        ctx.accounts.next_round.bump = ctx.bumps.next_round;
+        carry_lamports(&ctx.accounts.previous_round, &ctx.accounts.next_round, &ctx.accounts.closer.to_account_info())?;
+
+        Ok(())
+    }
+
+    pub fn close_token_round(ctx: Context<CloseTokenRound>, user_seed: u64) -> Result<()> {
+        require!(ctx.accounts.previous_round.vrf_seed == [0u8; 32], ErrorCode::RandomnessAlreadyRequested);
+        require!(ctx.accounts.config.vrf_program == Pubkey::default(), ErrorCode::VrfEnabled);
+        let recent_slot_hashes = &ctx.accounts.slot_hashes;
+        let data = recent_slot_hashes.data.borrow();
+        let (_, recent_hash) = SlotHashesData::new(&data)
+            .and_then(|slot_hashes| slot_hashes.most_recent())
+            .ok_or(ErrorCode::NoBlockhash)?;
+        let clock = Clock::get()?;
+
+        // generate random seed
+        let seed = derive_seed(LOTTERY_DOMAIN, &[&user_seed.to_le_bytes(), &clock.unix_timestamp.to_le_bytes(), &recent_hash]);
+
+        finish_round(
+            &mut ctx.accounts.previous_round,
+            &mut ctx.accounts.next_round,
+            &mut ctx.accounts.state,
+            &ctx.accounts.config,
+            ctx.accounts.closer.key(),
+            seed,
+        )?;
+        ctx.accounts.next_round.bump = ctx.bumps.next_round;
+
+        let previous_round = &ctx.accounts.previous_round;
+        let previous_vault = &ctx.accounts.previous_vault;
+        let mint = &ctx.accounts.mint;
+        let token_program = &ctx.accounts.token_program;
+        transfer_from_round(previous_round, previous_vault, ctx.accounts.next_vault.to_account_info(), mint, token_program, ctx.accounts.next_round.pot_size)?;
+        transfer_from_round(previous_round, previous_vault, ctx.accounts.closer_token_account.to_account_info(), mint, token_program, previous_round.close_bounty)?;
+
+        Ok(())
+    }
+
+    pub fn close_token_round_vrf(ctx: Context<CloseTokenRoundVrf>) -> Result<()> {
+        let randomness = load_randomness(&ctx.accounts.randomness, &ctx.accounts.previous_round)?;
+
+        let seed = derive_seed(LOTTERY_DOMAIN, &[&randomness.randomness]);
+
+        finish_round(
+            &mut ctx.accounts.previous_round,
+            &mut ctx.accounts.next_round,
+            &mut ctx.accounts.state,
+            &ctx.accounts.config,
+            ctx.accounts.closer.key(),
+            seed,
+        )?;
+        ctx.accounts.next_round.bump = ctx.bumps.next_round;
+
+        let previous_round = &ctx.accounts.previous_round;
+        let previous_vault = &ctx.accounts.previous_vault;
+        let mint = &ctx.accounts.mint;
+        let token_program = &ctx.accounts.token_program;
+        transfer_from_round(previous_round, previous_vault, ctx.accounts.next_vault.to_account_info(), mint, token_program, ctx.accounts.next_round.pot_size)?;
+        transfer_from_round(previous_round, previous_vault, ctx.accounts.closer_token_account.to_account_info(), mint, token_program, previous_round.close_bounty)?;
+
+        Ok(())
+    }
+
+    /// Without a `burn_token` the burn share goes to `burn_address`, otherwise it stays in the
+    /// round until `burn_round` swaps it, so the payouts never depend on the swap.
+    pub fn claim_winnings(ctx: Context<ClaimWinnings>, _round_num: u64) -> Result<()> {
+        let payouts = claim_payouts(&ctx.accounts.ticket, &mut ctx.accounts.round, &ctx.accounts.config, ctx.accounts.cranker.key())?;
+
+        let round_info = ctx.accounts.round.to_account_info();
+        move_lamports(&round_info, &ctx.accounts.owner.to_account_info(), payouts.winner_payout)?;
+        move_lamports(&round_info, &ctx.accounts.team.to_account_info(), payouts.team_payout)?;
+        move_lamports(&round_info, &ctx.accounts.last_depositor.to_account_info(), payouts.last_depositor_payout)?;
+        move_lamports(&round_info, &ctx.accounts.cranker.to_account_info(), payouts.keeper_bounty)?;
+
+        if ctx.accounts.config.burn_token == Pubkey::default() {
+            move_lamports(&round_info, &ctx.accounts.burn.to_account_info(), payouts.burn_amount)?;
+        } else {
+            ctx.accounts.round.burn_pending = payouts.burn_amount;
+        }
+
+        Ok(())
+    }
+
+    /// Permissionless crank that swaps a claimed round's burn share for `burn_token` through
+    /// the config's amm and burns the output. The swap has to return at least `burn_price`,
+    /// less `burn_slippage_bp`, so it can't be filled against a pool pushed off its price
+    /// in the same transaction. While the pool trades below that price the share waits in
+    /// the round for the treasury admin to update it.
+    pub fn burn_round(ctx: Context<BurnRound>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(!config.paused, ErrorCode::Paused);
+        let amount = ctx.accounts.round.burn_pending;
+        require!(amount > 0, ErrorCode::NothingToBurn);
+
+        let min_amount_out = (amount as u128)
+            .checked_mul(config.burn_price as u128)
+            .and_then(|out| out.checked_mul((10_000 - config.burn_slippage_bp) as u128))
+            .map(|out| out / (LAMPORTS_PER_SOL as u128 * 10_000))
+            .and_then(|out| u64::try_from(out).ok())
+            .ok_or(ErrorCode::BadBurnPrice)?;
+        require!(min_amount_out > 0, ErrorCode::BadBurnPrice);
+
+        ctx.accounts.round.burn_pending = 0;
+        let burned = ctx.accounts.swap_and_burn(amount, min_amount_out, ctx.bumps.swap_authority)?;
         let state = &mut ctx.accounts.state;
-        require!(check_ticket(ticket, round.winning_number, round.round_num - ticket.round_num), ErrorCode::NotWinningTicket);
-        require!(round.winning_depositor == Pubkey::default(), ErrorCode::RoundAlreadyClaimed);
+        state.total_burned = state.total_burned.checked_add(burned).ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(())
+    }
 
-        // TODO: transfer loot
+    pub fn claim_token_winnings(ctx: Context<ClaimTokenWinnings>) -> Result<()> {
+        let payouts = claim_payouts(&ctx.accounts.ticket, &mut ctx.accounts.round, &ctx.accounts.config, ctx.accounts.cranker.key())?;
 
-        round.winning_depositor = ticket.owner;
+        let round = &ctx.accounts.round;
+        let vault = &ctx.accounts.vault;
+        let mint = &ctx.accounts.mint;
+        let token_program = &ctx.accounts.token_program;
+        transfer_from_round(round, vault, ctx.accounts.owner_token_account.to_account_info(), mint, token_program, payouts.winner_payout)?;
+        transfer_from_round(round, vault, ctx.accounts.team_token_account.to_account_info(), mint, token_program, payouts.team_payout)?;
+        transfer_from_round(round, vault, ctx.accounts.last_depositor_token_account.to_account_info(), mint, token_program, payouts.last_depositor_payout)?;
+        transfer_from_round(round, vault, ctx.accounts.cranker_token_account.to_account_info(), mint, token_program, payouts.keeper_bounty)?;
+
+        // burn_token is the deposit token, so the burn share is burned straight from the vault
+        let lottery_seed = (round.lottery_num as u32).to_le_bytes();
+        let round_seed = round.round_num.to_le_bytes();
+        let seeds = &[b"round".as_ref(), &lottery_seed[..4], &round_seed[..4], &[round.bump]];
+        let signer_seeds = &[&seeds[..]];
+        token_interface::burn(
+            CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                Burn {
+                    mint: mint.to_account_info(),
+                    from: vault.to_account_info(),
+                    authority: round.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            payouts.burn_amount,
+        )?;
+
+        let state = &mut ctx.accounts.state;
+        state.total_burned = state.total_burned.checked_add(payouts.burn_amount).ok_or(ErrorCode::MathOverflow)?;
+        emit!(TokensBurned {
+            lottery_num: round.lottery_num,
+            round_num: round.round_num,
+            mint: mint.key(),
+            lamports_in: 0,
+            amount: payouts.burn_amount,
+        });
 
         Ok(())
     }
 
+    /// A bound referrer receives `referral_fee_bp` of the ticket's team share, the rest goes into the pot.
     pub fn buy_ticket(ctx: Context<BuyTicket>, amount: u64, user_ticket_num: u8) -> Result<()> {
-        let ticket: &mut Account<LotteryTicket> = &mut ctx.accounts.lottery_ticket;
+        let player = &mut ctx.accounts.player;
+        player.player = ctx.accounts.owner.key();
+        player.bump = ctx.bumps.player;
+        let referred = bind_referrer(&mut player.referrer, ctx.accounts.owner.key(), &mut ctx.accounts.referrer)?;
+
         let round: &mut Account<LotteryRound> = &mut ctx.accounts.round;
-        let config: &mut Account<LotteryConfig> = &mut ctx.accounts.config;
-        let state: &mut Account<LotteryState> = &mut ctx.accounts.state;
         let owner: &Signer = &ctx.accounts.owner;
 
-        let clock: Clock = Clock::get().unwrap();
-        require!((clock.unix_timestamp as u64) < round.end_time, ErrorCode::RoundAlreadyEnded);
-        require!(config.min_deposit <= amount, ErrorCode::DepositTooSmall);
-
-        ticket.owner = *owner.key;
-        ticket.user_ticket_num = user_ticket_num;
-        ticket.round_num = round.round_num;
-        ticket.window_start = round.pot_size;
-        ticket.window_end = ticket.window_start.checked_add(amount).unwrap();
-        // ticket.bump = ctx.bumps.lottery_ticket;
-
-        state.tickets_bought = state.tickets_bought.checked_add(1).unwrap();
-        // track pot size
-        round.pot_size = round.pot_size.checked_add(amount).unwrap();
-        // increment tickets bought
-        round.tickets_bought = round.tickets_bought.checked_add(1).unwrap();
-        // set last depositor
-        round.last_depositor = *owner.key;
-        // extend round end time
-        round.end_time = round.end_time.checked_add(config.duration_extension).unwrap();
+        ctx.accounts.lottery_ticket.bump = ctx.bumps.lottery_ticket;
+        record_ticket(&mut ctx.accounts.lottery_ticket, round, &ctx.accounts.config, &mut ctx.accounts.state, owner.key(), amount, user_ticket_num)?;
+
+        let referral_fee = if referred { get_bounty(get_share(amount, ctx.accounts.config.team_pct), ctx.accounts.config.referral_fee_bp)? } else { 0 };
+        if let Some(referrer) = ctx.accounts.referrer.as_mut().filter(|_| referred) {
+            solana_program::program::invoke(
+                &solana_program::system_instruction::transfer(
+                    owner.to_account_info().key,
+                    referrer.to_account_info().key,
+                    referral_fee,
+                ),
+                &[
+                    owner.to_account_info(),
+                    referrer.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                ],
+            )?;
+            referrer.total_earned = referrer.total_earned.checked_add(referral_fee).ok_or(ErrorCode::MathOverflow)?;
+            // taken out of the team share when the winnings are claimed
+            round.referral_paid = round.referral_paid.checked_add(referral_fee).ok_or(ErrorCode::MathOverflow)?;
+        }
 
         // Invoke the transfer
         solana_program::program::invoke(
             &solana_program::system_instruction::transfer(
                 owner.to_account_info().key,
                 round.to_account_info().key,
-                amount,
+                amount.checked_sub(referral_fee).ok_or(ErrorCode::MathOverflow)?,
             ),
             &[
                 owner.to_account_info(),
@@ -169,6 +532,435 @@ pub mod lottery {
 
         Ok(())
     }
+
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referrer = &mut ctx.accounts.referrer;
+        referrer.referrer = ctx.accounts.authority.key();
+        referrer.bump = ctx.bumps.referrer;
+
+        Ok(())
+    }
+
+    /// Referral fees accumulate as lamports in the referrer account, everything above rent is claimable.
+    pub fn claim_referral(ctx: Context<ClaimReferral>) -> Result<()> {
+        let referrer = &ctx.accounts.referrer.to_account_info();
+        let authority = &ctx.accounts.authority.to_account_info();
+
+        let rent = Rent::get()?;
+        let amount = referrer.lamports().checked_sub(rent.minimum_balance(Referrer::LEN)).ok_or(ErrorCode::MathOverflow)?;
+        move_lamports(referrer, authority, amount)?;
+
+        emit!(ReferralClaimed {
+            referrer: authority.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    pub fn buy_token_ticket(ctx: Context<BuyTokenTicket>, amount: u64, user_ticket_num: u8) -> Result<()> {
+        let owner: &Signer = &ctx.accounts.owner;
+        let mint = &ctx.accounts.mint;
+
+        ctx.accounts.lottery_ticket.bump = ctx.bumps.lottery_ticket;
+        record_ticket(&mut ctx.accounts.lottery_ticket, &mut ctx.accounts.round, &ctx.accounts.config, &mut ctx.accounts.state, owner.key(), amount, user_ticket_num)?;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.owner_token_account.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: owner.to_account_info(),
+                },
+            ),
+            amount,
+            mint.decimals,
+        )?;
+
+        Ok(())
+    }
+
+    /// The payout percentages are fixed at setup, changing them would reprice tickets already bought.
+    pub fn update_lottery_config(ctx: Context<ChangeLotteryConfig>,
+                      initial_duration: u64,
+                      duration_extension: u64,
+                      min_deposit: u64,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let current = LotteryConfig::clone(config);
+
+        configure_lottery(
+            config,
+            current.lottery_num,
+            initial_duration,
+            duration_extension,
+            min_deposit,
+            current.burn_pct,
+            current.last_depositor_pct,
+            current.team_pct,
+            current.winner_pct,
+            current.burn_address,
+            current.burn_token,
+            current.team_address,
+            current.keeper_bounty_bp,
+            current.referral_fee_bp,
+            current.mint,
+        )
+    }
+
+    /// Keeper bounties and referral fees come out of the team share, so the treasury admin sets them.
+    pub fn set_lottery_fees(ctx: Context<ChangeLotteryTreasury>, keeper_bounty_bp: u64, referral_fee_bp: u64) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let current = LotteryConfig::clone(config);
+
+        configure_lottery(
+            config,
+            current.lottery_num,
+            current.initial_duration,
+            current.duration_extension,
+            current.min_deposit,
+            current.burn_pct,
+            current.last_depositor_pct,
+            current.team_pct,
+            current.winner_pct,
+            current.burn_address,
+            current.burn_token,
+            current.team_address,
+            keeper_bounty_bp,
+            referral_fee_bp,
+            current.mint,
+        )
+    }
+
+    /// Both the config admin and the current treasury admin have to sign, so neither can take
+    /// the other's role alone.
+    pub fn set_lottery_roles(ctx: Context<ChangeLotteryRoles>, config_admin: Pubkey, treasury_admin: Pubkey, pauser: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.config_admin = config_admin;
+        config.treasury_admin = treasury_admin;
+        config.pauser = pauser;
+
+        emit!(RolesChanged {
+            lottery_num: config.lottery_num,
+            config_admin,
+            treasury_admin,
+            pauser,
+        });
+
+        Ok(())
+    }
+
+    /// Halts ticket sales, round closing and claims until unpaused.
+    pub fn set_lottery_paused(ctx: Context<PauseLottery>, paused: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.paused = paused;
+
+        emit!(PausedChanged {
+            lottery_num: config.lottery_num,
+            authority: ctx.accounts.authority.key(),
+            paused,
+        });
+
+        Ok(())
+    }
+
+    /// The oracle has to serve the `arcade_vrf` interface and derive its randomness from
+    /// something the requester can't predict, `mock_vrf` derives it from the seed alone and
+    /// is only fit for tests. Once set, rounds can only close through the oracle.
+    pub fn set_vrf_program(ctx: Context<ChangeLotteryConfig>, vrf_program: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.vrf_program = vrf_program;
+
+        emit!(VrfProgramChanged {
+            lottery_num: config.lottery_num,
+            authority: ctx.accounts.authority.key(),
+            vrf_program,
+        });
+
+        Ok(())
+    }
+
+    /// `amm_program` has to be one of `ALLOWED_AMM_PROGRAMS`, `burn_price` is the `burn_token`
+    /// base units one SOL has to buy and `burn_slippage_bp` how far below it the swap may fill.
+    pub fn set_burn_swap(ctx: Context<ChangeLotteryTreasury>, amm_program: Pubkey, burn_price: u64, burn_slippage_bp: u64) -> Result<()> {
+        require!(ALLOWED_AMM_PROGRAMS.contains(&amm_program), ErrorCode::MismatchedAmmProgram);
+        require!(burn_price > 0, ErrorCode::BadBurnPrice);
+        require!(burn_slippage_bp <= MAX_BURN_SLIPPAGE_BP, ErrorCode::BadBurnSlippage);
+        let config = &mut ctx.accounts.config;
+        config.amm_program = amm_program;
+        config.burn_price = burn_price;
+        config.burn_slippage_bp = burn_slippage_bp;
+
+        emit!(BurnSwapChanged {
+            lottery_num: config.lottery_num,
+            authority: ctx.accounts.authority.key(),
+            amm_program,
+            burn_price,
+            burn_slippage_bp,
+        });
+
+        Ok(())
+    }
+
+    /// Only affects payouts claimed afterwards, including those of rounds that already closed.
+    pub fn set_team_address(ctx: Context<ChangeLotteryTreasury>, team_address: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.team_address = team_address;
+
+        emit!(TeamAddressChanged {
+            lottery_num: config.lottery_num,
+            authority: ctx.accounts.authority.key(),
+            team_address,
+        });
+
+        Ok(())
+    }
+}
+
+/// Reads the randomness the round requested, the owner is checked against the config's oracle.
+fn load_randomness(randomness: &AccountInfo, round: &LotteryRound) -> Result<Randomness> {
+    require!(round.vrf_seed != [0u8; 32], ErrorCode::RandomnessNotRequested);
+    let randomness = Randomness::load(randomness).ok_or(ErrorCode::MismatchedRandomness)?;
+    require!(randomness.seed == round.vrf_seed, ErrorCode::MismatchedRandomness);
+    require!(randomness.is_fulfilled(), ErrorCode::RandomnessNotFulfilled);
+    Ok(randomness)
+}
+
+/// Every role starts with the creator.
+fn set_roles(config: &mut LotteryConfig, creator: Pubkey) {
+    config.config_admin = creator;
+    config.treasury_admin = creator;
+    config.pauser = creator;
+}
+
+/// Reallocs a legacy account to `len`, `payer` covers the extra rent so a round's pot stays whole.
+fn grow_legacy_account<'info>(account: &AccountInfo<'info>, len: usize, payer: &Signer<'info>, system_program: &Program<'info, System>) -> Result<()> {
+    let rent = Rent::get()?;
+    let extra_rent = rent.minimum_balance(len).saturating_sub(rent.minimum_balance(account.data_len()));
+    solana_program::program::invoke(
+        &solana_program::system_instruction::transfer(payer.key, account.key, extra_rent),
+        &[
+            payer.to_account_info(),
+            account.clone(),
+            system_program.to_account_info(),
+        ],
+    )?;
+    account.realloc(len, false)?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn configure_lottery(
+    config: &mut LotteryConfig,
+    lottery_num: u8,
+    initial_duration: u64,
+    duration_extension: u64,
+    min_deposit: u64,
+    burn_pct: u8,
+    last_depositor_pct: u8,
+    team_pct: u8,
+    winner_pct: u8,
+    burn_address: Pubkey,
+    burn_token: Pubkey,
+    team_address: Pubkey,
+    keeper_bounty_bp: u64,
+    referral_fee_bp: u64,
+    mint: Pubkey,
+) -> Result<()> {
+    config.lottery_num = lottery_num;
+    config.initial_duration = initial_duration;
+    config.duration_extension = duration_extension;
+    config.min_deposit = min_deposit;
+    config.burn_pct = burn_pct;
+    config.last_depositor_pct = last_depositor_pct;
+    config.team_pct = team_pct;
+    config.winner_pct = winner_pct;
+    config.burn_address = burn_address;
+    config.burn_token = burn_token;
+    config.team_address = team_address;
+    config.keeper_bounty_bp = keeper_bounty_bp;
+    config.referral_fee_bp = referral_fee_bp;
+    config.mint = mint;
+    require!(burn_pct + team_pct + winner_pct + last_depositor_pct == 90, ErrorCode::BadPercentages);
+    require!(burn_pct < 100 && team_pct < 100 && winner_pct < 100 && last_depositor_pct < 100, ErrorCode::BadPercentages);
+    // both keeper bounties and the referral fees are paid out of the team share
+    require!(referral_fee_bp <= 10_000, ErrorCode::BadReferralFee);
+    require!(
+        keeper_bounty_bp.checked_mul(2 * 100).ok_or(ErrorCode::BadKeeperBounty)? + (team_pct as u64) * referral_fee_bp <= (team_pct as u64) * 10_000,
+        ErrorCode::BadKeeperBounty
+    );
+
+    emit!(ConfigChanged {
+        lottery_num,
+        initial_duration,
+        duration_extension,
+        min_deposit,
+        burn_pct,
+        last_depositor_pct,
+        team_pct,
+        winner_pct,
+        burn_address,
+        burn_token,
+        team_address,
+        keeper_bounty_bp,
+        referral_fee_bp,
+        mint,
+    });
+
+    Ok(())
+}
+
+/// Binds the first referrer a player buys a ticket with, afterwards only that referrer can be passed.
+/// Returns whether the ticket pays a referral fee.
+fn bind_referrer(bound_referrer: &mut Pubkey, player: Pubkey, referrer: &mut Option<Account<Referrer>>) -> Result<bool> {
+    if let Some(referrer) = referrer.as_mut() {
+        if *bound_referrer == Pubkey::default() && referrer.referrer != player {
+            *bound_referrer = referrer.referrer;
+            referrer.referral_count = referrer.referral_count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+            emit!(ReferrerBound {
+                player,
+                referrer: referrer.referrer,
+            });
+        }
+    }
+    if *bound_referrer == Pubkey::default() {
+        return Ok(false);
+    }
+    let referrer = referrer.as_ref().ok_or(ErrorCode::MismatchedReferrer)?;
+    require_keys_eq!(referrer.referrer, *bound_referrer, ErrorCode::MismatchedReferrer);
+    Ok(true)
+}
+
+fn record_ticket(ticket: &mut Account<LotteryTicket>, round: &mut LotteryRound, config: &LotteryConfig, state: &mut LotteryState, owner: Pubkey, amount: u64, user_ticket_num: u8) -> Result<()> {
+    require!(!config.paused, ErrorCode::Paused);
+    let clock: Clock = Clock::get().unwrap();
+    require!((clock.unix_timestamp as u64) < round.end_time, ErrorCode::RoundAlreadyEnded);
+    require!(config.min_deposit <= amount, ErrorCode::DepositTooSmall);
+
+    ticket.owner = owner;
+    ticket.user_ticket_num = user_ticket_num;
+    ticket.round_num = round.round_num;
+    ticket.window_start = round.pot_size;
+    ticket.window_end = ticket.window_start.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+
+    state.tickets_bought = state.tickets_bought.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+    // track pot size
+    round.pot_size = round.pot_size.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+    // increment tickets bought
+    round.tickets_bought = round.tickets_bought.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+    // set last depositor
+    round.last_depositor = owner;
+    // extend round end time
+    round.end_time = round.end_time.checked_add(config.duration_extension).ok_or(ErrorCode::MathOverflow)?;
+
+    emit!(TicketBought {
+        lottery_num: round.lottery_num,
+        round_num: round.round_num,
+        ticket: ticket.key(),
+        owner,
+        user_ticket_num,
+        amount,
+        window_start: ticket.window_start,
+        window_end: ticket.window_end,
+        pot_size: round.pot_size,
+        end_time: round.end_time,
+    });
+
+    Ok(())
+}
+
+struct Payouts {
+    winner_payout: u64,
+    team_payout: u64,
+    last_depositor_payout: u64,
+    burn_amount: u64,
+    keeper_bounty: u64,
+}
+
+/// Checks the ticket won the round, marks it claimed and splits the pot.
+fn claim_payouts(ticket: &Account<LotteryTicket>, round: &mut LotteryRound, config: &LotteryConfig, cranker: Pubkey) -> Result<Payouts> {
+    require!(!config.paused, ErrorCode::Paused);
+    require!(round.closed, ErrorCode::RoundNotClosed);
+    let rounds_past = round.round_num.checked_sub(ticket.round_num).ok_or(ErrorCode::NotWinningTicket)?;
+    require!(check_ticket(ticket, round.winning_number, rounds_past), ErrorCode::NotWinningTicket);
+    require!(round.winning_depositor == Pubkey::default(), ErrorCode::RoundAlreadyClaimed);
+
+    round.winning_depositor = ticket.owner;
+
+    // the remaining 10% was already carried over into the next round when this one closed
+    let keeper_bounty = round.keeper_bounty;
+    let payouts = Payouts {
+        winner_payout: get_share(round.pot_size, config.winner_pct),
+        team_payout: get_share(round.pot_size, config.team_pct)
+            .checked_sub(round.close_bounty + keeper_bounty + round.referral_paid)
+            .ok_or(ErrorCode::BadKeeperBounty)?,
+        last_depositor_payout: get_share(round.pot_size, config.last_depositor_pct),
+        burn_amount: get_share(round.pot_size, config.burn_pct),
+        keeper_bounty,
+    };
+
+    emit!(WinningsClaimed {
+        lottery_num: round.lottery_num,
+        round_num: round.round_num,
+        ticket: ticket.key(),
+        owner: ticket.owner,
+        winning_number: round.winning_number,
+        winner_payout: payouts.winner_payout,
+        team_payout: payouts.team_payout,
+        last_depositor_payout: payouts.last_depositor_payout,
+        burn_amount: payouts.burn_amount,
+        cranker,
+        keeper_bounty,
+    });
+
+    Ok(payouts)
+}
+
+/// Draws the winner and opens the next round, the caller moves the carry-over and the close bounty.
+fn finish_round(previous_round: &mut LotteryRound, next_round: &mut LotteryRound, state: &mut LotteryState, config: &LotteryConfig, closer: Pubkey, seed: [u8; 32]) -> Result<()> {
+    require!(!config.paused, ErrorCode::Paused);
+    let clock: Clock = Clock::get()?;
+    require!((clock.unix_timestamp as u64) >= previous_round.end_time, ErrorCode::LotteryNotEnded);
+    require!(!previous_round.closed, ErrorCode::RoundAlreadyClosed);
+
+    // ticket windows partition [0, pot_size), an empty pot has no winner
+    previous_round.winning_number = uniform(seed, previous_round.pot_size).unwrap_or(0);
+    previous_round.closed = true;
+
+    // initialize next round, numbered like the pda it lives at
+    let next_round_num = previous_round.round_num + 1;
+    state.num_rounds = next_round_num;
+    initialize_round(
+        config.lottery_num,
+        next_round_num,
+        state.tickets_bought,
+        config.initial_duration,
+        previous_round.pot_size / 10,
+        next_round
+    );
+    // reward whoever closed the round and whoever cranks the claim, both taken from the team share
+    // when the winnings are claimed, so they are capped by what the referral fees left of it
+    let team_share_left = get_share(previous_round.pot_size, config.team_pct).saturating_sub(previous_round.referral_paid);
+    let bounty = get_bounty(previous_round.pot_size, config.keeper_bounty_bp)?.min(team_share_left / 2);
+    previous_round.close_bounty = bounty;
+    previous_round.keeper_bounty = bounty;
+
+    emit!(RoundClosed {
+        lottery_num: previous_round.lottery_num,
+        round_num: previous_round.round_num,
+        randomness: seed,
+        winning_number: previous_round.winning_number,
+        tickets_bought: previous_round.tickets_bought,
+        pot_size: previous_round.pot_size,
+        next_round_num: next_round.round_num,
+        next_pot_size: next_round.pot_size,
+        closer,
+        close_bounty: previous_round.close_bounty,
+    });
+
+    Ok(())
 }
 
 fn initialize_round(lottery_num: u8, round_num: u32, previous_pot_size: u64, initial_duration: u64, pot_size: u64, round: &mut LotteryRound) {
@@ -181,39 +973,817 @@ fn initialize_round(lottery_num: u8, round_num: u32, previous_pot_size: u64, ini
     round.winning_number = 0;
     round.previous_pot_size = previous_pot_size;
     round.pot_size = pot_size;
+    round.closed = false;
+    round.close_bounty = 0;
+    round.referral_paid = 0;
+    round.keeper_bounty = 0;
     // round.last_depositor
 }
 
+fn get_share(pot_size: u64, pct: u8) -> u64 {
+    pot_size.checked_mul(pct as u64).unwrap() / 100
+}
+
+fn get_bounty(pot_size: u64, keeper_bounty_bp: u64) -> Result<u64> {
+    Ok(pot_size.checked_mul(keeper_bounty_bp).ok_or(ErrorCode::BadKeeperBounty)? / 10_000)
+}
+
+/// Carries the 10% that claim_winnings leaves behind into the next pot and pays the closer.
+fn carry_lamports<'info>(previous_round: &Account<'info, LotteryRound>, next_round: &Account<'info, LotteryRound>, closer: &AccountInfo<'info>) -> Result<()> {
+    move_lamports(&previous_round.to_account_info(), &next_round.to_account_info(), next_round.pot_size)?;
+    move_lamports(&previous_round.to_account_info(), closer, previous_round.close_bounty)
+}
+
+fn transfer_from_round<'info>(
+    round: &Account<'info, LotteryRound>,
+    vault: &InterfaceAccount<'info, TokenAccount>,
+    to: AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let lottery_seed = (round.lottery_num as u32).to_le_bytes();
+    let round_seed = round.round_num.to_le_bytes();
+    let seeds = &[b"round".as_ref(), &lottery_seed[..4], &round_seed[..4], &[round.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            TransferChecked {
+                from: vault.to_account_info(),
+                mint: mint.to_account_info(),
+                to,
+                authority: round.to_account_info(),
+            },
+            signer_seeds,
+        ),
+        amount,
+        mint.decimals,
+    )
+}
+
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
+    **from.try_borrow_mut_lamports()? = from.lamports().checked_sub(amount).ok_or(ErrorCode::InsufficientPot)?;
+    **to.try_borrow_mut_lamports()? = to.lamports().checked_add(amount).unwrap();
+    Ok(())
+}
+
 pub fn check_ticket(ticket: &LotteryTicket, winning_number: u64, rounds_past: u32) -> bool {
     let start = ticket.window_start / 10u64.pow(rounds_past);
     let end = ticket.window_end / 10u64.pow(rounds_past);
-    return start <= winning_number && winning_number < end;
+    start <= winning_number && winning_number < end
+}
+
+#[derive(Accounts)]
+pub struct ClaimWinnings<'info> {
+    #[account(
+        seeds = [
+            b"lottery",
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump,
+        constraint = config.mint == Pubkey::default() @ ErrorCode::WrongLotteryKind
+    )]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    #[account(
+        mut,
+        seeds = [
+            b"buy-ticket",
+            ticket.owner.key().as_ref(),
+            ticket.round_num.to_le_bytes().as_ref(),
+            &[ticket.user_ticket_num, 0, 0, 0],
+            &[config.lottery_num, 0, 0, 0]
+        ],
+        // tickets bought before the bump was stored hold 0
+        bump
+    )]
+    pub ticket: Account<'info, LotteryTicket>,
+    #[account(
+        mut,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(round.round_num).to_le_bytes()[..4]
+        ],
+        bump = round.bump
+    )]
+    pub round: Account<'info, LotteryRound>,
+    /// CHECK: we check the address matches the ticket owner
+    #[account(mut, address = ticket.owner)]
+    pub owner: UncheckedAccount<'info>,
+    /// CHECK: we check the address matches the round's last depositor
+    #[account(mut, address = round.last_depositor)]
+    pub last_depositor: UncheckedAccount<'info>,
+    /// CHECK: we check the address matches the config
+    #[account(mut, address = config.team_address)]
+    pub team: UncheckedAccount<'info>,
+    /// CHECK: we check the address matches the config
+    #[account(mut, address = config.burn_address)]
+    pub burn: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BurnRound<'info> {
+    #[account(
+        seeds = [
+            b"lottery",
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump,
+        constraint = config.mint == Pubkey::default() @ ErrorCode::WrongLotteryKind
+    )]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    #[account(
+        mut,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(round.round_num).to_le_bytes()[..4]
+        ],
+        bump = round.bump
+    )]
+    pub round: Account<'info, LotteryRound>,
+    /// CHECK: we check the address matches the config
+    #[account(executable, address = config.amm_program @ ErrorCode::MismatchedAmmProgram)]
+    pub amm_program: UncheckedAccount<'info>,
+    /// CHECK: validated by the amm program
+    #[account(mut)]
+    pub amm_pool: UncheckedAccount<'info>,
+    /// CHECK: validated by the amm program
+    #[account(mut)]
+    pub amm_vault: UncheckedAccount<'info>,
+    #[account(mut, address = config.burn_token)]
+    pub burn_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: we check the address matches the seeds
+    #[account(
+        mut,
+        seeds = [
+            SWAP_AUTHORITY_SEED
+        ],
+        bump
+    )]
+    pub swap_authority: UncheckedAccount<'info>,
+    /// swap output, a token account of `burn_mint` owned by `swap_authority`
+    #[account(
+        mut,
+        token::mint = burn_mint,
+        token::authority = swap_authority,
+        token::token_program = token_program
+    )]
+    pub burn_vault: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> BurnRound<'info> {
+    /// Swaps `amount` lamports from the round for `burn_token` and burns the output.
+    fn swap_and_burn(&mut self, amount: u64, min_amount_out: u64, swap_authority_bump: u8) -> Result<u64> {
+        let amm_program = &self.amm_program;
+        let amm_pool = &self.amm_pool;
+        let amm_vault = &self.amm_vault;
+        let burn_mint = &self.burn_mint;
+        let swap_authority = &self.swap_authority;
+        let burn_vault = &mut self.burn_vault;
+        let token_program = &self.token_program;
+
+        let seeds = &[SWAP_AUTHORITY_SEED, &[swap_authority_bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        // the swap authority is a plain system account so it can pay the amm with a system transfer
+        move_lamports(&self.round.to_account_info(), &swap_authority.to_account_info(), amount)?;
+        let balance_before = burn_vault.amount;
+        mock_amm::cpi::swap_sol_for_token(
+            CpiContext::new_with_signer(
+                amm_program.to_account_info(),
+                mock_amm::cpi::accounts::SwapSolForToken {
+                    payer: swap_authority.to_account_info(),
+                    pool: amm_pool.to_account_info(),
+                    mint: burn_mint.to_account_info(),
+                    vault: amm_vault.to_account_info(),
+                    destination: burn_vault.to_account_info(),
+                    token_program: token_program.to_account_info(),
+                    system_program: self.system_program.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+            min_amount_out,
+        )?;
+        burn_vault.reload()?;
+        let burned = burn_vault.amount.checked_sub(balance_before).ok_or(ErrorCode::MathOverflow)?;
+
+        token_interface::burn(
+            CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                Burn {
+                    mint: burn_mint.to_account_info(),
+                    from: burn_vault.to_account_info(),
+                    authority: swap_authority.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            burned,
+        )?;
+
+        emit!(TokensBurned {
+            lottery_num: self.round.lottery_num,
+            round_num: self.round.round_num,
+            mint: burn_mint.key(),
+            lamports_in: amount,
+            amount: burned,
+        });
+
+        Ok(burned)
+    }
+}
+
+#[derive(Accounts)]
+pub struct CloseRound<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(previous_round.round_num).to_le_bytes()[..4]
+        ],
+        bump = previous_round.bump
+    )]
+    pub previous_round: Account<'info, LotteryRound>,
+    #[account(
+        init,
+        payer = closer,
+        space = LotteryRound::LEN,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(previous_round.round_num + 1u32).to_le_bytes()[..4]
+        ],
+        bump
+    )]
+    pub next_round: Account<'info, LotteryRound>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    #[account(constraint = config.mint == Pubkey::default() @ ErrorCode::WrongLotteryKind)]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(mut)]
+    pub closer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: we check the address matches in the constraint below
+    #[account(address = slot_hashes::id())]
+    slot_hashes: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RequestRoundRandomness<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(round.round_num).to_le_bytes()[..4]
+        ],
+        bump = round.bump
+    )]
+    pub round: Account<'info, LotteryRound>,
+    #[account()]
+    pub config: Account<'info, LotteryConfig>,
+    /// CHECK: initialized by the vrf program, which checks the address against the seed
+    #[account(mut)]
+    pub randomness: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub requester: Signer<'info>,
+    /// CHECK: the oracle configured on the lottery
+    #[account(executable, address = config.vrf_program @ ErrorCode::MismatchedVrfProgram)]
+    pub vrf_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRoundVrf<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(previous_round.round_num).to_le_bytes()[..4]
+        ],
+        bump = previous_round.bump
+    )]
+    pub previous_round: Account<'info, LotteryRound>,
+    #[account(
+        init,
+        payer = closer,
+        space = LotteryRound::LEN,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(previous_round.round_num + 1u32).to_le_bytes()[..4]
+        ],
+        bump
+    )]
+    pub next_round: Account<'info, LotteryRound>,
+    /// CHECK: owned by the config's oracle, the seed is checked against the round in the handler
+    #[account(owner = config.vrf_program @ ErrorCode::MismatchedVrfProgram)]
+    pub randomness: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    #[account(constraint = config.mint == Pubkey::default() @ ErrorCode::WrongLotteryKind)]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(mut)]
+    pub closer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, user_ticket_num: u8)]
+pub struct BuyTicket<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = LotteryTicket::LEN,
+        seeds = [
+            b"buy-ticket",
+            owner.key().as_ref(),
+            round.round_num.to_le_bytes().as_ref(),
+            &[user_ticket_num, 0, 0, 0],
+            &[config.lottery_num, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub lottery_ticket: Account<'info, LotteryTicket>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    #[account(mut, constraint = config.mint == Pubkey::default() @ ErrorCode::WrongLotteryKind)]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(
+        mut,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(round.round_num).to_le_bytes()[..4]
+        ],
+        bump = round.bump
+    )]
+    pub round: Account<'info, LotteryRound>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = Player::LEN,
+        seeds = [
+            b"player",
+            owner.key().as_ref()
+        ],
+        bump
+    )]
+    pub player: Account<'info, Player>,
+    /// Binds to the player on their first referred ticket, required on every ticket after that.
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            referrer.referrer.as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = Referrer::LEN,
+        seeds = [
+            b"referrer",
+            authority.key().as_ref()
+        ],
+        bump
+    )]
+    pub referrer: Account<'info, Referrer>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferral<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"referrer",
+            authority.key().as_ref()
+        ],
+        bump = referrer.bump
+    )]
+    pub referrer: Account<'info, Referrer>,
+    #[account(mut)]
+    pub authority: Signer<'info>
+}
+
+#[derive(Accounts)]
+pub struct ChangeLotteryConfig<'info> {
+    #[account(mut)]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(constraint = authority.key() == config.config_admin @ ErrorCode::Unauthorized)]
+    pub authority: Signer<'info>
+}
+
+#[derive(Accounts)]
+pub struct PauseLottery<'info> {
+    #[account(mut)]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(constraint = authority.key() == config.pauser @ ErrorCode::Unauthorized)]
+    pub authority: Signer<'info>
+}
+
+#[derive(Accounts)]
+pub struct ChangeLotteryTreasury<'info> {
+    #[account(mut)]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(constraint = authority.key() == config.treasury_admin @ ErrorCode::Unauthorized)]
+    pub authority: Signer<'info>
+}
+
+#[derive(Accounts)]
+pub struct ChangeLotteryRoles<'info> {
+    #[account(mut)]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(constraint = authority.key() == config.config_admin @ ErrorCode::Unauthorized)]
+    pub authority: Signer<'info>,
+    #[account(constraint = treasury_admin.key() == config.treasury_admin @ ErrorCode::Unauthorized)]
+    pub treasury_admin: Signer<'info>
+}
+
+#[derive(Accounts)]
+#[instruction(lottery_num: u8)]
+pub struct SetupLottery<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = LotteryConfig::LEN,
+        seeds = [
+            b"lottery",
+            &[lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(
+        init,
+        payer = creator,
+        space = LotteryState::LEN,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    #[account(
+        init,
+        payer = creator,
+        space = LotteryRound::LEN,
+        seeds = [
+            b"round",
+            &(lottery_num as u32).to_le_bytes()[..4],
+            &(1u32.to_le_bytes())[..4]
+        ],
+        bump
+    )]
+    pub first_round: Account<'info, LotteryRound>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+#[instruction(lottery_num: u8)]
+pub struct SetupTokenLottery<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = LotteryConfig::LEN,
+        seeds = [
+            b"lottery",
+            &[lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(
+        init,
+        payer = creator,
+        space = LotteryState::LEN,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    #[account(
+        init,
+        payer = creator,
+        space = LotteryRound::LEN,
+        seeds = [
+            b"round",
+            &(lottery_num as u32).to_le_bytes()[..4],
+            &(1u32.to_le_bytes())[..4]
+        ],
+        bump
+    )]
+    pub first_round: Account<'info, LotteryRound>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [
+            b"round-vault",
+            first_round.key().as_ref()
+        ],
+        bump,
+        token::mint = mint,
+        token::authority = first_round,
+        token::token_program = token_program
+    )]
+    pub first_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+#[instruction(lottery_num: u8)]
+pub struct MigrateLegacyLottery<'info> {
+    /// CHECK: parsed by `LegacyLotteryConfig::load`
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [
+            b"lottery",
+            &[lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub config: UncheckedAccount<'info>,
+    /// CHECK: parsed by `LegacyLotteryState::load`, legacy states aren't tied to their config
+    /// so the upgrade authority picks the lottery's
+    #[account(mut, owner = crate::ID)]
+    pub legacy_state: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = authority,
+        space = LotteryState::LEN,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::Lottery>,
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()) @ ErrorCode::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+#[instruction(round_num: u32)]
+pub struct MigrateLegacyRound<'info> {
+    #[account(
+        seeds = [
+            b"lottery",
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub config: Account<'info, LotteryConfig>,
+    #[account(
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
+    pub state: Account<'info, LotteryState>,
+    /// CHECK: parsed by `LegacyLotteryRound::load`
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &round_num.to_le_bytes()[..4]
+        ],
+        bump
+    )]
+    pub round: UncheckedAccount<'info>,
+    /// Already migrated, required for every round after the first.
+    #[account(
+        mut,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &round_num.wrapping_sub(1).to_le_bytes()[..4]
+        ],
+        bump = previous_round.bump
+    )]
+    pub previous_round: Option<Account<'info, LotteryRound>>,
+    #[account(mut, constraint = authority.key() == config.config_admin @ ErrorCode::Unauthorized)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: we check the address matches in the constraint below
+    #[account(address = slot_hashes::id())]
+    slot_hashes: UncheckedAccount<'info>,
+}
+
+/// Layout of the config before it had fees or roles.
+#[derive(AnchorDeserialize)]
+struct LegacyLotteryConfig {
+    lottery_num: u8,
+    initial_duration: u64,
+    duration_extension: u64,
+    min_deposit: u64,
+    burn_pct: u8,
+    last_depositor_pct: u8,
+    team_pct: u8,
+    winner_pct: u8,
+    burn_address: Pubkey,
+    burn_token: Pubkey,
+    team_address: Pubkey,
+}
+
+impl LegacyLotteryConfig {
+    const LEN: usize = DISCRIMINATOR_LENGTH + 1 + (8 * 3) + 4 + (PUBLIC_KEY_LENGTH * 3);
+
+    fn load(account: &AccountInfo) -> Result<Self> {
+        load_legacy(account, Self::LEN, &<LotteryConfig as anchor_lang::Discriminator>::DISCRIMINATOR)
+    }
+}
+
+/// Layout of the state when it lived at a keypair.
+#[derive(AnchorDeserialize)]
+struct LegacyLotteryState {
+    num_rounds: u32,
+    tickets_bought: u64,
+}
+
+impl LegacyLotteryState {
+    const LEN: usize = DISCRIMINATOR_LENGTH + 4 + 8;
+
+    fn load(account: &AccountInfo) -> Result<Self> {
+        load_legacy(account, Self::LEN, &<LotteryState as anchor_lang::Discriminator>::DISCRIMINATOR)
+    }
+}
+
+/// Layout of a round before closes were tracked.
+#[derive(AnchorDeserialize)]
+struct LegacyLotteryRound {
+    lottery_num: u8,
+    round_num: u32,
+    tickets_bought: u64,
+    start_time: u64,
+    end_time: u64,
+    _winning_number: u64,
+    previous_pot_size: u64,
+    pot_size: u64,
+    _bump: u8,
+    last_depositor: Pubkey,
+    _winning_depositor: Pubkey,
+}
+
+impl LegacyLotteryRound {
+    const LEN: usize = DISCRIMINATOR_LENGTH + 1 + 4 + (8 * 8) + 1 + (2 * PUBLIC_KEY_LENGTH);
+
+    fn load(account: &AccountInfo) -> Result<Self> {
+        load_legacy(account, Self::LEN, &<LotteryRound as anchor_lang::Discriminator>::DISCRIMINATOR)
+    }
+}
+
+fn load_legacy<T: AnchorDeserialize>(account: &AccountInfo, len: usize, discriminator: &[u8; 8]) -> Result<T> {
+    let data = account.try_borrow_data()?;
+    require!(data.len() == len && data[..DISCRIMINATOR_LENGTH] == *discriminator, ErrorCode::NotLegacyAccount);
+    Ok(T::deserialize(&mut &data[DISCRIMINATOR_LENGTH..])?)
 }
 
 #[derive(Accounts)]
-pub struct ClaimWinnings<'info> {
+#[instruction(amount: u64, user_ticket_num: u8)]
+pub struct BuyTokenTicket<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = LotteryTicket::LEN,
+        seeds = [
+            b"buy-ticket",
+            owner.key().as_ref(),
+            round.round_num.to_le_bytes().as_ref(),
+            &[user_ticket_num, 0, 0, 0],
+            &[config.lottery_num, 0, 0, 0]
+        ],
+        bump
+    )]
+    pub lottery_ticket: Account<'info, LotteryTicket>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
+    pub state: Account<'info, LotteryState>,
     #[account()]
     pub config: Account<'info, LotteryConfig>,
-    #[account(mut)]
-    pub state: Account<'info, LotteryState>,
     #[account(
         mut,
         seeds = [
-            b"buy-ticket",
-            ticket.owner.key().as_ref(),
-            &round.round_num.to_le_bytes(),
-            &round.tickets_bought.to_le_bytes()
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(round.round_num).to_le_bytes()[..4]
         ],
-        bump = ticket.bump
+        bump = round.bump
     )]
-    pub ticket: Account<'info, LotteryTicket>,
+    pub round: Account<'info, LotteryRound>,
+    #[account(address = config.mint @ ErrorCode::WrongLotteryKind, mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [
+            b"round-vault",
+            round.key().as_ref()
+        ],
+        bump
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = owner,
+        token::token_program = token_program
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
     #[account(mut)]
-    pub round: Account<'info, LotteryRound>
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>
 }
 
 #[derive(Accounts)]
-pub struct CloseRound<'info> {
+pub struct CloseTokenRound<'info> {
     #[account(
+        mut,
         seeds = [
             b"round",
             &(config.lottery_num as u32).to_le_bytes()[..4],
@@ -234,12 +1804,51 @@ pub struct CloseRound<'info> {
         bump
     )]
     pub next_round: Account<'info, LotteryRound>,
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [
+            b"round-vault",
+            previous_round.key().as_ref()
+        ],
+        bump
+    )]
+    pub previous_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = closer,
+        seeds = [
+            b"round-vault",
+            next_round.key().as_ref()
+        ],
+        bump,
+        token::mint = mint,
+        token::authority = next_round,
+        token::token_program = token_program
+    )]
+    pub next_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = config.mint @ ErrorCode::WrongLotteryKind, mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
     pub state: Account<'info, LotteryState>,
     #[account()]
     pub config: Account<'info, LotteryConfig>,
     #[account(mut)]
     pub closer: Signer<'info>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = closer,
+        token::token_program = token_program
+    )]
+    pub closer_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
     /// CHECK: we check the address matches in the constraint below
     #[account(address = slot_hashes::id())]
@@ -247,64 +1856,164 @@ pub struct CloseRound<'info> {
 }
 
 #[derive(Accounts)]
-#[instruction(amount: u64, user_ticket_num: u8)]
-pub struct BuyTicket<'info> {
+pub struct CloseTokenRoundVrf<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(previous_round.round_num).to_le_bytes()[..4]
+        ],
+        bump = previous_round.bump
+    )]
+    pub previous_round: Account<'info, LotteryRound>,
     #[account(
         init,
-        payer = owner,
-        space = LotteryTicket::LEN,
+        payer = closer,
+        space = LotteryRound::LEN,
         seeds = [
-            b"buy-ticket",
-            owner.key().as_ref(),
-            round.round_num.to_le_bytes().as_ref(),
-            &[user_ticket_num, 0, 0, 0],
-            &[config.lottery_num, 0, 0, 0]
+            b"round",
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(previous_round.round_num + 1u32).to_le_bytes()[..4]
         ],
         bump
     )]
-    pub lottery_ticket: Account<'info, LotteryTicket>,
-    #[account(mut)]
+    pub next_round: Account<'info, LotteryRound>,
+    #[account(
+        mut,
+        seeds = [
+            b"round-vault",
+            previous_round.key().as_ref()
+        ],
+        bump
+    )]
+    pub previous_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = closer,
+        seeds = [
+            b"round-vault",
+            next_round.key().as_ref()
+        ],
+        bump,
+        token::mint = mint,
+        token::authority = next_round,
+        token::token_program = token_program
+    )]
+    pub next_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = config.mint @ ErrorCode::WrongLotteryKind, mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: owned by the config's oracle, the seed is checked against the round in the handler
+    #[account(owner = config.vrf_program @ ErrorCode::MismatchedVrfProgram)]
+    pub randomness: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
     pub state: Account<'info, LotteryState>,
-    #[account(mut)]
+    #[account()]
     pub config: Account<'info, LotteryConfig>,
     #[account(mut)]
-    pub round: Account<'info, LotteryRound>,
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub system_program: Program<'info, System>
+    pub closer: Signer<'info>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = closer,
+        token::token_program = token_program
+    )]
+    pub closer_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(lottery_num: u8)]
-pub struct SetupLottery<'info> {
+pub struct ClaimTokenWinnings<'info> {
     #[account(
-        init,
-        payer = creator,
-        space = LotteryConfig::LEN,
         seeds = [
             b"lottery",
-            &[lottery_num, 0, 0, 0, 0, 0, 0]
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
         ],
         bump
     )]
     pub config: Account<'info, LotteryConfig>,
-    #[account(init, payer = creator, space = LotteryState::LEN)]
+    #[account(
+        mut,
+        seeds = [
+            b"buy-ticket",
+            ticket.owner.key().as_ref(),
+            ticket.round_num.to_le_bytes().as_ref(),
+            &[ticket.user_ticket_num, 0, 0, 0],
+            &[config.lottery_num, 0, 0, 0]
+        ],
+        // tickets bought before the bump was stored hold 0
+        bump
+    )]
+    pub ticket: Account<'info, LotteryTicket>,
+    #[account(
+        mut,
+        seeds = [
+            b"lottery-state".as_ref(),
+            &[config.lottery_num, 0, 0, 0, 0, 0, 0]
+        ],
+        bump = state.bump
+    )]
     pub state: Account<'info, LotteryState>,
     #[account(
-        init,
-        payer = creator,
-        space = LotteryRound::LEN,
+        mut,
         seeds = [
             b"round",
-            &(lottery_num as u32).to_le_bytes()[..4],
-            &(1u32.to_le_bytes())[..4]
+            &(config.lottery_num as u32).to_le_bytes()[..4],
+            &(round.round_num).to_le_bytes()[..4]
+        ],
+        bump = round.bump
+    )]
+    pub round: Account<'info, LotteryRound>,
+    #[account(
+        mut,
+        seeds = [
+            b"round-vault",
+            round.key().as_ref()
         ],
         bump
     )]
-    pub first_round: Account<'info, LotteryRound>,
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = config.mint @ ErrorCode::WrongLotteryKind, mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = ticket.owner,
+        token::token_program = token_program
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = round.last_depositor,
+        token::token_program = token_program
+    )]
+    pub last_depositor_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = config.team_address,
+        token::token_program = token_program
+    )]
+    pub team_token_account: InterfaceAccount<'info, TokenAccount>,
     #[account(mut)]
-    pub creator: Signer<'info>,
-    pub system_program: Program<'info, System>
+    pub cranker: Signer<'info>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = cranker,
+        token::token_program = token_program
+    )]
+    pub cranker_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[account]
@@ -320,10 +2029,30 @@ pub struct LotteryConfig {
     pub burn_address: Pubkey,
     pub burn_token: Pubkey,
     pub team_address: Pubkey,
+    pub keeper_bounty_bp: u64,
+    /// `Pubkey::default()` for lotteries played with lamports.
+    pub mint: Pubkey,
+    /// Share of a ticket's team share, in bp, paid to the buyer's referrer.
+    pub referral_fee_bp: u64,
+    /// Changes the config, and the roles together with the treasury admin. Can be a multisig vault like the other roles.
+    pub config_admin: Pubkey,
+    /// Changes where the team share and the burn share go, and what keepers and referrers take of them.
+    pub treasury_admin: Pubkey,
+    /// Sets `paused` through `set_lottery_paused`.
+    pub pauser: Pubkey,
+    pub paused: bool,
+    /// Oracle that `request_round_randomness` asks for randomness, `Pubkey::default()` disables vrf closes.
+    pub vrf_program: Pubkey,
+    /// Swaps the burn share into `burn_token`, `Pubkey::default()` until set.
+    pub amm_program: Pubkey,
+    /// How far below `burn_price`, in bp, the burn swap may fill.
+    pub burn_slippage_bp: u64,
+    /// `burn_token` base units the burn swap has to buy per SOL, fixed by the treasury admin.
+    pub burn_price: u64
 }
 
 impl LotteryConfig {
-    const LEN: usize = DISCRIMINATOR_LENGTH + 1 + (8 * 3) + 4 + (PUBLIC_KEY_LENGTH * 3);
+    const LEN: usize = DISCRIMINATOR_LENGTH + 1 + (8 * 3) + 4 + (PUBLIC_KEY_LENGTH * 3) + 8 + PUBLIC_KEY_LENGTH + 8 + (PUBLIC_KEY_LENGTH * 3) + 1 + (PUBLIC_KEY_LENGTH * 2) + (8 * 2);
 }
 
 #[account]
@@ -338,21 +2067,31 @@ pub struct LotteryRound {
     pub pot_size: u64,
     pub bump: u8,
     pub last_depositor: Pubkey,
-    pub winning_depositor: Pubkey
+    pub winning_depositor: Pubkey,
+    pub vrf_seed: [u8; 32],
+    pub closed: bool,
+    pub close_bounty: u64,
+    pub referral_paid: u64,
+    /// Paid to whoever cranks `claim_winnings`, fixed when the round closes.
+    pub keeper_bounty: u64,
+    /// Claimed burn share waiting for `burn_round` to swap it into `burn_token`.
+    pub burn_pending: u64
 }
 
 impl LotteryRound {
-    const LEN: usize = DISCRIMINATOR_LENGTH + 1 + 4 + (8 * 8) + 1 + (2 * PUBLIC_KEY_LENGTH);
+    const LEN: usize = DISCRIMINATOR_LENGTH + 1 + 4 + (8 * 8) + 1 + (2 * PUBLIC_KEY_LENGTH) + 32 + 1 + 8 + 8 + 8 + 8;
 }
 
 #[account]
 pub struct LotteryState {
     pub num_rounds: u32,
-    pub tickets_bought: u64
+    pub tickets_bought: u64,
+    pub total_burned: u64,
+    pub bump: u8
 }
 
 impl LotteryState {
-    const LEN: usize = DISCRIMINATOR_LENGTH + 4 + 8;
+    const LEN: usize = DISCRIMINATOR_LENGTH + 4 + 8 + 8 + 1;
 }
 
 #[account]
@@ -369,6 +2108,166 @@ impl LotteryTicket {
     const LEN: usize = DISCRIMINATOR_LENGTH + 4 + 1 + (8 * 2) + PUBLIC_KEY_LENGTH + 1;
 }
 
+#[account]
+pub struct Player {
+    pub player: Pubkey,
+    /// First referrer the player bought a ticket with, `Pubkey::default()` if none.
+    pub referrer: Pubkey,
+    pub bump: u8
+}
+
+impl Player {
+    const LEN: usize = DISCRIMINATOR_LENGTH + (PUBLIC_KEY_LENGTH * 2) + 1;
+}
+
+#[account]
+pub struct Referrer {
+    pub referrer: Pubkey,
+    pub total_earned: u64,
+    pub referral_count: u64,
+    pub bump: u8
+}
+
+impl Referrer {
+    const LEN: usize = DISCRIMINATOR_LENGTH + PUBLIC_KEY_LENGTH + (8 * 2) + 1;
+}
+
+#[event]
+pub struct ConfigChanged {
+    pub lottery_num: u8,
+    pub initial_duration: u64,
+    pub duration_extension: u64,
+    pub min_deposit: u64,
+    pub burn_pct: u8,
+    pub last_depositor_pct: u8,
+    pub team_pct: u8,
+    pub winner_pct: u8,
+    pub burn_address: Pubkey,
+    pub burn_token: Pubkey,
+    pub team_address: Pubkey,
+    pub keeper_bounty_bp: u64,
+    pub referral_fee_bp: u64,
+    pub mint: Pubkey,
+}
+
+#[event]
+pub struct RolesChanged {
+    pub lottery_num: u8,
+    pub config_admin: Pubkey,
+    pub treasury_admin: Pubkey,
+    pub pauser: Pubkey,
+}
+
+#[event]
+pub struct PausedChanged {
+    pub lottery_num: u8,
+    pub authority: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct VrfProgramChanged {
+    pub lottery_num: u8,
+    pub authority: Pubkey,
+    pub vrf_program: Pubkey,
+}
+
+#[event]
+pub struct LegacyLotteryMigrated {
+    pub lottery_num: u8,
+    pub authority: Pubkey,
+    pub num_rounds: u32,
+    pub tickets_bought: u64,
+}
+
+#[event]
+pub struct LegacyRoundMigrated {
+    pub lottery_num: u8,
+    pub round_num: u32,
+    pub pot_size: u64,
+    pub closed: bool,
+    pub winning_number: u64,
+}
+
+#[event]
+pub struct BurnSwapChanged {
+    pub lottery_num: u8,
+    pub authority: Pubkey,
+    pub amm_program: Pubkey,
+    pub burn_price: u64,
+    pub burn_slippage_bp: u64,
+}
+
+#[event]
+pub struct TeamAddressChanged {
+    pub lottery_num: u8,
+    pub authority: Pubkey,
+    pub team_address: Pubkey,
+}
+
+#[event]
+pub struct TicketBought {
+    pub lottery_num: u8,
+    pub round_num: u32,
+    pub ticket: Pubkey,
+    pub owner: Pubkey,
+    pub user_ticket_num: u8,
+    pub amount: u64,
+    pub window_start: u64,
+    pub window_end: u64,
+    pub pot_size: u64,
+    pub end_time: u64,
+}
+
+#[event]
+pub struct RoundClosed {
+    pub lottery_num: u8,
+    pub round_num: u32,
+    pub randomness: [u8; 32],
+    pub winning_number: u64,
+    pub tickets_bought: u64,
+    pub pot_size: u64,
+    pub next_round_num: u32,
+    pub next_pot_size: u64,
+    pub closer: Pubkey,
+    pub close_bounty: u64,
+}
+
+#[event]
+pub struct WinningsClaimed {
+    pub lottery_num: u8,
+    pub round_num: u32,
+    pub ticket: Pubkey,
+    pub owner: Pubkey,
+    pub winning_number: u64,
+    pub winner_payout: u64,
+    pub team_payout: u64,
+    pub last_depositor_payout: u64,
+    pub burn_amount: u64,
+    pub cranker: Pubkey,
+    pub keeper_bounty: u64,
+}
+
+#[event]
+pub struct TokensBurned {
+    pub lottery_num: u8,
+    pub round_num: u32,
+    pub mint: Pubkey,
+    pub lamports_in: u64,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ReferrerBound {
+    pub player: Pubkey,
+    pub referrer: Pubkey,
+}
+
+#[event]
+pub struct ReferralClaimed {
+    pub referrer: Pubkey,
+    pub amount: u64,
+}
 
 #[error_code]
 pub enum ErrorCode {
@@ -387,5 +2286,59 @@ pub enum ErrorCode {
     #[msg("Not the winning ticket")]
     NotWinningTicket,
     #[msg("Round already claimed")]
-    RoundAlreadyClaimed
+    RoundAlreadyClaimed,
+    #[msg("No tickets bought")]
+    NoTicketsBought,
+    #[msg("Invalid vrf seed")]
+    InvalidVrfSeed,
+    #[msg("Randomness already requested for this round")]
+    RandomnessAlreadyRequested,
+    #[msg("Randomness has not been requested for this round")]
+    RandomnessNotRequested,
+    #[msg("Randomness has not been fulfilled yet")]
+    RandomnessNotFulfilled,
+    #[msg("Mismatched randomness account")]
+    MismatchedRandomness,
+    #[msg("Round does not hold enough lamports for the payout")]
+    InsufficientPot,
+    #[msg("Round already closed")]
+    RoundAlreadyClosed,
+    #[msg("Round has not been closed yet")]
+    RoundNotClosed,
+    #[msg("Keeper bounties can't exceed the team share")]
+    BadKeeperBounty,
+    #[msg("Instruction does not match the lottery's deposit token")]
+    WrongLotteryKind,
+    #[msg("Missing or invalid accounts for swapping into the burn token")]
+    MissingSwapAccounts,
+    #[msg("Referral fee can't exceed the team share")]
+    BadReferralFee,
+    #[msg("Player is bound to a different referrer")]
+    MismatchedReferrer,
+    #[msg("Signer doesn't hold the role for this instruction")]
+    Unauthorized,
+    #[msg("Lottery is paused")]
+    Paused,
+    #[msg("No vrf oracle is configured for this lottery")]
+    VrfDisabled,
+    #[msg("Account doesn't belong to the lottery's vrf oracle")]
+    MismatchedVrfProgram,
+    #[msg("Burn slippage can't exceed 5%")]
+    BadBurnSlippage,
+    #[msg("Program doesn't match the lottery's amm")]
+    MismatchedAmmProgram,
+    #[msg("Rounds close through the vrf oracle while one is configured")]
+    VrfEnabled,
+    #[msg("Round has no burn share waiting to be swapped")]
+    NothingToBurn,
+    #[msg("Burn price is unset or out of range")]
+    BadBurnPrice,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Account isn't in the legacy layout")]
+    NotLegacyAccount,
+    #[msg("Lottery must be paused to migrate its rounds")]
+    NotPaused,
+    #[msg("Rounds after the first migrate with their previous round")]
+    MismatchedRound
 }
@@ -0,0 +1,190 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+declare_id!("3p5YXs2dmazq8cEDqg6o4PReFJVTupRQgijc2zy4xSF4");
+
+const DISCRIMINATOR_LENGTH: usize = 8;
+const PUBLIC_KEY_LENGTH: usize = 32;
+const U8_LENGTH: usize = 1;
+pub const POOL_SEED: &[u8] = b"pool";
+pub const POOL_VAULT_SEED: &[u8] = b"pool-vault";
+
+/// Stand-in for a Jupiter/Orca style swap: one constant product SOL/token pool per mint.
+/// The SOL side is the pool account's balance above rent, the token side its vault.
+#[program]
+pub mod mock_amm {
+    use super::*;
+
+    pub fn init_pool(ctx: Context<InitPool>, initial_lamports: u64, initial_tokens: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let mint = &ctx.accounts.mint;
+
+        pool.mint = mint.key();
+        pool.bump = ctx.bumps.pool;
+
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.creator.to_account_info(),
+                    to: pool.to_account_info(),
+                },
+            ),
+            initial_lamports,
+        )?;
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.creator_token_account.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.creator.to_account_info(),
+                },
+            ),
+            initial_tokens,
+            mint.decimals,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn swap_sol_for_token(ctx: Context<SwapSolForToken>, amount_in: u64, min_amount_out: u64) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let mint = &ctx.accounts.mint;
+        let vault = &ctx.accounts.vault;
+
+        let rent = Rent::get()?;
+        let sol_reserve = pool.to_account_info().lamports() - rent.minimum_balance(Pool::LEN);
+        let amount_out = get_amount_out(sol_reserve, vault.amount, amount_in);
+        require!(amount_out >= min_amount_out, ErrorCode::SlippageExceeded);
+
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: pool.to_account_info(),
+                },
+            ),
+            amount_in,
+        )?;
+
+        let mint_key = mint.key();
+        let seeds = &[POOL_SEED, mint_key.as_ref(), &[pool.bump]];
+        let signer_seeds = &[&seeds[..]];
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: vault.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.destination.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount_out,
+            mint.decimals,
+        )?;
+
+        Ok(())
+    }
+}
+
+pub fn get_amount_out(sol_reserve: u64, token_reserve: u64, amount_in: u64) -> u64 {
+    ((token_reserve as u128 * amount_in as u128) / (sol_reserve as u128 + amount_in as u128)) as u64
+}
+
+#[derive(Accounts)]
+pub struct InitPool<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(
+        init,
+        payer = creator,
+        space = Pool::LEN,
+        seeds = [
+            POOL_SEED,
+            mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [
+            POOL_VAULT_SEED,
+            mint.key().as_ref()
+        ],
+        bump,
+        token::mint = mint,
+        token::authority = pool,
+        token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = creator,
+        token::token_program = token_program
+    )]
+    pub creator_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SwapSolForToken<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            POOL_SEED,
+            mint.key().as_ref()
+        ],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [
+            POOL_VAULT_SEED,
+            mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[account]
+pub struct Pool {
+    pub mint: Pubkey,
+    pub bump: u8
+}
+
+impl Pool {
+    const LEN: usize = DISCRIMINATOR_LENGTH + PUBLIC_KEY_LENGTH + U8_LENGTH;
+}
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Swap output below the minimum")]
+    SlippageExceeded
+}
@@ -0,0 +1,102 @@
+use anchor_lang::prelude::*;
+use sha3::{Digest, Sha3_256};
+
+declare_id!("ZCNGxNS5ZrDyrSk1XH9U1Ch2v3T8P62ckBi4UCUu8Z6");
+
+const DISCRIMINATOR_LENGTH: usize = 8;
+const PUBLIC_KEY_LENGTH: usize = 32;
+const U8_LENGTH: usize = 1;
+pub const RANDOMNESS_SEED: &[u8] = b"randomness";
+
+/// Stand-in for an ORAO/Switchboard style oracle serving the `arcade_vrf` interface:
+/// consumers request randomness for a seed and read the fulfilled randomness account back. Fulfillment is permissionless
+/// and derived from the seed alone so tests can predict it.
+#[program]
+pub mod mock_vrf {
+    use super::*;
+
+    pub fn request(ctx: Context<Request>, seed: [u8; 32]) -> Result<()> {
+        let randomness = &mut ctx.accounts.randomness;
+
+        randomness.seed = seed;
+        randomness.requester = ctx.accounts.payer.key();
+        randomness.randomness = [0u8; 64];
+        randomness.bump = ctx.bumps.randomness;
+
+        Ok(())
+    }
+
+    pub fn fulfill(ctx: Context<Fulfill>) -> Result<()> {
+        let randomness = &mut ctx.accounts.randomness;
+        require!(!randomness.is_fulfilled(), ErrorCode::AlreadyFulfilled);
+
+        randomness.randomness = derive_randomness(&randomness.seed);
+
+        Ok(())
+    }
+}
+
+pub fn derive_randomness(seed: &[u8; 32]) -> [u8; 64] {
+    let mut randomness = [0u8; 64];
+    for (i, chunk) in randomness.chunks_mut(32).enumerate() {
+        let mut hasher = Sha3_256::new();
+        hasher.update(seed);
+        hasher.update([i as u8]);
+        chunk.copy_from_slice(&hasher.finalize());
+    }
+    randomness
+}
+
+#[derive(Accounts)]
+#[instruction(seed: [u8; 32])]
+pub struct Request<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        space = RandomnessAccount::LEN,
+        seeds = [
+            RANDOMNESS_SEED,
+            seed.as_ref()
+        ],
+        bump
+    )]
+    pub randomness: Account<'info, RandomnessAccount>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Fulfill<'info> {
+    #[account(
+        mut,
+        seeds = [
+            RANDOMNESS_SEED,
+            randomness.seed.as_ref()
+        ],
+        bump = randomness.bump
+    )]
+    pub randomness: Account<'info, RandomnessAccount>,
+}
+
+#[account]
+pub struct RandomnessAccount {
+    pub seed: [u8; 32],
+    pub requester: Pubkey,
+    pub randomness: [u8; 64],
+    pub bump: u8
+}
+
+impl RandomnessAccount {
+    const LEN: usize = DISCRIMINATOR_LENGTH + 32 + PUBLIC_KEY_LENGTH + 64 + U8_LENGTH;
+
+    pub fn is_fulfilled(&self) -> bool {
+        self.randomness != [0u8; 64]
+    }
+}
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Randomness already fulfilled")]
+    AlreadyFulfilled
+}
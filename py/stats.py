@@ -2,7 +2,10 @@ import numpy as np
 import random
 
 # returns (user, house, reserve) diffs
-def simulate(multiplier, bet_size, edge, house_p):
+# fee_bp is the reserve's house_fee_bp plus the fee_bp of every extra fee recipient,
+# all of it counts as "house" here
+def simulate(multiplier, bet_size, edge, fee_bp):
+    house_p = fee_bp / 10_000
     reserve_p = 1 - house_p
     p = 1 / (multiplier + edge)
     if random.random() < p:
@@ -14,7 +17,7 @@ def simulate(multiplier, bet_size, edge, house_p):
     return out
 
 
-def run_simulations(n = 1000, edge = 0.2, house_p = 0.05):
+def run_simulations(n = 1000, edge = 0.2, fee_bp = 500):
     user_total = 0
     house_total = 0
     reserve_total = 0
@@ -29,7 +32,7 @@ def run_simulations(n = 1000, edge = 0.2, house_p = 0.05):
             np.random.randint(2, 10),
             np.random.randint(1, 1000),
             edge,
-            house_p
+            fee_bp
         )
         user_total += u
         house_total += h
@@ -47,7 +50,7 @@ def run_simulations(n = 1000, edge = 0.2, house_p = 0.05):
         if reserve_total > reserve_max:
             reserve_max = reserve_total
     print('edge', edge)
-    print('house_p', house_p)
+    print('fee_bp', fee_bp)
     print('user  ', int(user_total), int(user_min), int(user_max))
     print('house  ', int(house_total), int(house_min), int(house_max))
     print('reserve', int(reserve_total), int(reserve_min), int(reserve_max))
@@ -56,8 +59,8 @@ def run_simulations(n = 1000, edge = 0.2, house_p = 0.05):
 #     run_simulations(np.random.randint(10, 200))
 
 for i in range(1, 20):
-    run_simulations(100000, edge=i / 10, house_p=0.1)
+    run_simulations(100000, edge=i / 10, fee_bp=1000)
 
-# for house_p in range(1, 6):
+# for fee_bp in range(100, 2501, 400):
 #     for edge in range(20, 51, 5):
-#         run_simulations(1000000, edge=edge / 100, house_p=house_p / 100)
\ No newline at end of file
+#         run_simulations(1000000, edge=edge / 100, fee_bp=fee_bp)
\ No newline at end of file
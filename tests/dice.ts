@@ -1,25 +1,35 @@
 import * as anchor from "@coral-xyz/anchor";
 import {getProvider, Program} from "@coral-xyz/anchor";
 import { Dice } from "../target/types/dice";
+import { MockVrf } from "../target/types/mock_vrf";
 import * as assert from "assert";
 import { Keypair, PublicKey } from "@solana/web3.js";
+import { createHash, randomBytes } from "crypto";
+import { TOKEN_PROGRAM_ID, createMint, getAccount, getAssociatedTokenAddressSync, getOrCreateAssociatedTokenAccount, mintTo } from "@solana/spl-token";
 
 const SLOT_HASHES_SYSVAR = new PublicKey("SysvarS1otHashes111111111111111111111111111");
+const house_fee_bp = new anchor.BN(1000) // 10% of a lost bet
 
 describe("dice", () => {
     // Configure the client to use the local cluster.
     anchor.setProvider(anchor.AnchorProvider.env());
 
     let program = anchor.workspace.Dice as Program<Dice>;
+    const vrfProgram = anchor.workspace.MockVrf as Program<MockVrf>;
 
     function randomInteger(min, max) {
         return Math.floor(Math.random() * (max - min + 1)) + min;
     }
 
-    function getReserveKeyPDA() {
+    function getTableSeed(tableId) {
+        return new anchor.BN(tableId).toArrayLike(Buffer, "le", 2)
+    }
+
+    function getReserveKeyPDA(tableId = 0) {
         const [reserveKeyPDA, reserveKeyBump] = PublicKey.findProgramAddressSync(
             [
-                anchor.utils.bytes.utf8.encode("reserve-key-1")
+                anchor.utils.bytes.utf8.encode("reserve-key-1"),
+                getTableSeed(tableId)
             ],
             program.programId
         );
@@ -28,10 +38,11 @@ describe("dice", () => {
         return {reserveKeyPDA, reserveKeyBump}
     }
 
-    function getReservePDA() {
+    function getReservePDA(tableId = 0) {
         const [reservePDA, reserveBump] = PublicKey.findProgramAddressSync(
             [
-                anchor.utils.bytes.utf8.encode("reserve-1")
+                anchor.utils.bytes.utf8.encode("reserve-1"),
+                getTableSeed(tableId)
             ],
             program.programId
         );
@@ -40,6 +51,94 @@ describe("dice", () => {
         return {reservePDA, reserveBump}
     }
 
+    function getTokenReservePDAs(mint, tableId = 0) {
+        const [tokenReservePDA, _] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("reserve-1"),
+                getTableSeed(tableId),
+                mint.toBuffer()
+            ],
+            program.programId
+        );
+        const [vaultPDA, __] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("reserve-vault"),
+                getTableSeed(tableId),
+                mint.toBuffer()
+            ],
+            program.programId
+        );
+        return {tokenReservePDA, vaultPDA}
+    }
+
+    function getPlayerStatsPDA(player) {
+        const [playerStatsPDA, _] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("player-stats"),
+                player.toBuffer()
+            ],
+            program.programId
+        );
+        return playerStatsPDA
+    }
+
+    function getBetPDA(player, userSeed) {
+        const [betPDA, betBump] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("bet"),
+                player.toBuffer(),
+                userSeed.toArrayLike(Buffer, "le", 8)
+            ],
+            program.programId
+        );
+
+        return {betPDA, betBump}
+    }
+
+    function getRandomnessPDA(seed) {
+        const [randomnessPDA, _] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("randomness"),
+                seed
+            ],
+            vrfProgram.programId
+        );
+        return randomnessPDA
+    }
+
+    async function waitForSlot(slot) {
+        while (await getProvider().connection.getSlot('confirmed') <= slot) {
+            await new Promise(resolve => setTimeout(resolve, 400));
+        }
+    }
+
+    function sha3(...inputs) {
+        const hasher = createHash("sha3-256")
+        inputs.forEach(input => hasher.update(input))
+        return hasher.digest()
+    }
+
+    function deriveSeed(domain, ...inputs) {
+        const hasher = createHash("sha3-256")
+        for (const input of [Buffer.from(domain), ...inputs]) {
+            hasher.update(new anchor.BN(input.length).toArrayLike(Buffer, "le", 8))
+            hasher.update(input)
+        }
+        return hasher.digest()
+    }
+
+    function uniform(seed, bound) {
+        // mirrors arcade_rng::uniform's rejection sampling
+        const max = new anchor.BN(2).pow(new anchor.BN(64)).subn(1)
+        const zone = max.sub(new anchor.BN((max.modn(bound) + 1) % bound))
+        for (let counter = 0; ; counter++) {
+            const value = new anchor.BN(sha3(seed, new anchor.BN(counter).toArrayLike(Buffer, "le", 8)).subarray(0, 8), "le")
+            if (value.lte(zone)) {
+                return value.modn(bound)
+            }
+        }
+    }
+
     async function getRentExemption(space) {
         const lamports = await getProvider().connection.getMinimumBalanceForRentExemption(space);
         return lamports;
@@ -99,9 +198,11 @@ describe("dice", () => {
 
         // console.log({reservePDA, reserveKeyPDA, reserveKeyBalanceBefore})
         const txSignature = await program.methods.setupDice(
+            0,
             edge_bp,
             ratio,
             house.publicKey,
+            house_fee_bp,
             wallet.publicKey,
             initial_funding,
             reserveKeyBump
@@ -131,6 +232,26 @@ describe("dice", () => {
         assert.ok(reserveAccount.bump == reserveBump)
         assert.ok(reserveKeyBalanceBefore + initial_funding.toNumber() + reserveKeyRent == reserveKeyBalanceAfter)
         assert.equal(walletBalanceBefore, initial_funding.toNumber() + transactionFee + reserveKeyRent + reserveRent + walletBalanceAfter)
+
+        // instant rolls can be simulated by the player, the authority has to opt in
+        assert.equal(reserveAccount.instantRolls, false)
+        try {
+            await program.methods.rollDice(new anchor.BN(1), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(1000), reserveKeyBump).accounts({
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                reserve: reservePDA,
+                reserveKey: reserveKeyPDA,
+                house: house.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+                slotHashes: SLOT_HASHES_SYSVAR
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Instant rolls are disabled for this reserve");
+        }
+        await program.methods.setInstantRolls(true).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
     });
 
     it("Cannot double initialize", async () => {
@@ -147,7 +268,7 @@ describe("dice", () => {
 
         try {
             // console.log({reservePDA, reserveKeyPDA, reserveKeyBalanceBefore})
-            const txSignature = await program.methods.setupDice(edge_bp, ratio, house.publicKey, wallet.publicKey, initial_funding, reserveKeyBump).accounts({
+            const txSignature = await program.methods.setupDice(0, edge_bp, ratio, house.publicKey, house_fee_bp, wallet.publicKey, initial_funding, reserveKeyBump).accounts({
                 reserve: reservePDA,
                 reserveKey: reserveKeyPDA,
                 creator: wallet.publicKey,
@@ -159,6 +280,78 @@ describe("dice", () => {
         }
     });
 
+    it("Hosts independent tables", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA} = getReserveKeyPDA();
+        const highRoller = getReservePDA(1).reservePDA;
+        const {reserveKeyPDA: highRollerKeyPDA, reserveKeyBump: highRollerKeyBump} = getReserveKeyPDA(1);
+        const wallet = program.provider.wallet
+        const initial_funding = new anchor.BN(2 * anchor.web3.LAMPORTS_PER_SOL)
+
+        await program.methods.setupDice(1, new anchor.BN(100), new anchor.BN(2), house.publicKey, house_fee_bp, wallet.publicKey, initial_funding, highRollerKeyBump).accounts({
+            reserve: highRoller,
+            reserveKey: highRollerKeyPDA,
+            creator: wallet.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }).signers([]).rpc()
+
+        const reserveAccount = await program.account.reserve.fetch(highRoller)
+        assert.equal(reserveAccount.tableId, 1)
+        assert.ok(reserveAccount.edgeBp.eqn(100))
+        assert.ok(reserveAccount.reserveKey.equals(highRollerKeyPDA))
+        await program.methods.setInstantRolls(true).accounts({
+            reserve: highRoller,
+        }).signers([]).rpc()
+
+        const bet_size = 100_000_000
+        const balanceBefore = await getBalance(reserveKeyPDA)
+        const highRollerBalanceBefore = await getBalance(highRollerKeyPDA)
+        await program.methods.rollDice(new anchor.BN(randomInteger(1, 10000)), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(bet_size), highRollerKeyBump).accounts({
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            reserve: highRoller,
+            reserveKey: highRollerKeyPDA,
+            house: house.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }).signers([]).rpc()
+
+        assert.equal(await getBalance(reserveKeyPDA), balanceBefore)
+        assert.notEqual(await getBalance(highRollerKeyPDA), highRollerBalanceBefore)
+
+        try {
+            await program.methods.rollDice(new anchor.BN(1), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(bet_size), highRollerKeyBump).accounts({
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                reserve: reservePDA,
+                reserveKey: highRollerKeyPDA,
+                house: house.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+                slotHashes: SLOT_HASHES_SYSVAR
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "An address constraint was violated");
+        }
+
+        // this deployment never had a reserve at the unseeded legacy address
+        const [legacyReservePDA] = PublicKey.findProgramAddressSync([anchor.utils.bytes.utf8.encode("reserve-1")], program.programId)
+        const [legacyReserveKeyPDA] = PublicKey.findProgramAddressSync([anchor.utils.bytes.utf8.encode("reserve-key-1")], program.programId)
+        try {
+            await program.methods.migrateLegacyReserve(2).accounts({
+                legacyReserve: legacyReservePDA,
+                legacyReserveKey: legacyReserveKeyPDA,
+                reserve: getReservePDA(2).reservePDA,
+                reserveKey: getReserveKeyPDA(2).reserveKeyPDA,
+                authority: wallet.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "An owner constraint was violated");
+        }
+    });
+
 
     it("Lets you play!", async () => {
         const {reservePDA, reserveBump} = getReservePDA();
@@ -179,8 +372,9 @@ describe("dice", () => {
 
         // console.log({threshold, reservePDA, reserveKeyPDA, max_bet, wallet, depletion}, "bet_size", bet_size.toNumber(), "multiplier", multiplier_bp.toNumber(), house.publicKey)
 
-        const tx = await program.methods.rollDice(seed, multiplier_bp, bet_size, reserveKeyBump).accounts({
+        const tx = await program.methods.rollDice(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, reserveKeyBump).accounts({
             player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
             reserve: reservePDA,
             reserveKey: reserveKeyPDA,
             house: house.publicKey,
@@ -225,89 +419,227 @@ describe("dice", () => {
         }
     });
 
-    it("Prevents incorrect house", async () => {
-        const {reservePDA, reserveBump} = getReservePDA();
+    it("Emits a DiceRolled event", async () => {
+        const {reservePDA} = getReservePDA();
         const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
         const wallet = program.provider.wallet
-        const edge_bp = new anchor.BN(5000)
-        const ratio = new anchor.BN(5)
-        const fakeHouseKey = wallet
 
         const seed = new anchor.BN(randomInteger(1, 10000))
-        const multiplier_num = randomInteger(10_000, 100_000)
-        const multiplier_bp = new anchor.BN(multiplier_num)
-        const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
-        const bet_num = randomInteger(1, max_bet)
-        const bet_size = new anchor.BN(bet_num)
-        const depletion = getMaxDepletion(multiplier_bp, bet_size)
-        const reserveKeyBalanceBefore = await getBalance(reserveKeyPDA)
-        const houseBalanceBefore = await getBalance(house.publicKey)
-        const walletBalanceBefore = await getBalance(wallet.publicKey)
+        const multiplier_bp = new anchor.BN(20_000)
+        const bet_size = new anchor.BN(1000)
 
-        // console.log({threshold, reservePDA, reserveKeyPDA, max_bet, wallet, depletion}, "bet_size", bet_size.toNumber(), "multiplier", multiplier_bp.toNumber(), house.publicKey)
+        const tx = await program.methods.rollDice(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, reserveKeyBump).accounts({
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            house: house.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }).signers([]).rpc()
+        await getProvider().connection.confirmTransaction(tx, 'confirmed');
+
+        const txDetails = await getProvider().connection.getTransaction(tx, {
+            commitment: "confirmed",
+        });
+        const parser = new anchor.EventParser(program.programId, program.coder);
+        const events = [...parser.parseLogs(txDetails.meta.logMessages)];
+        const rolled = events.find(event => event.name === "diceRolled");
+
+        assert.ok(rolled);
+        assert.ok(rolled.data.player.equals(wallet.publicKey));
+        assert.ok(rolled.data.userSeed.eq(seed));
+        assert.ok(rolled.data.multiplierBp.eq(multiplier_bp));
+        assert.ok(rolled.data.betSize.eq(bet_size));
+        if (rolled.data.rollBp.lt(rolled.data.thresholdBp)) {
+            assert.ok(rolled.data.payout.eq(bet_size.mul(multiplier_bp).divn(10_000)));
+        } else {
+            assert.ok(rolled.data.payout.eqn(0));
+        }
+    });
+
+    it("Supports over, under and range bets", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const edge_bp = (await program.account.reserve.fetch(reservePDA)).edgeBp.toNumber()
+        const parser = new anchor.EventParser(program.programId, program.coder);
+        const bet_size = new anchor.BN(1000)
+        const bets = [
+            {betType: {under: {target: new anchor.BN(5000)}}, wins: roll => roll < 5000, winningRolls: 5000},
+            {betType: {over: {target: new anchor.BN(7499)}}, wins: roll => roll > 7499, winningRolls: 2500},
+            {betType: {inRange: {low: new anchor.BN(2500), high: new anchor.BN(7499)}}, wins: roll => roll >= 2500 && roll <= 7499, winningRolls: 5000},
+            {betType: {outOfRange: {low: new anchor.BN(2000), high: new anchor.BN(7999)}}, wins: roll => roll < 2000 || roll > 7999, winningRolls: 4000},
+        ]
+
+        for (const {betType, wins, winningRolls} of bets) {
+            const tx = await program.methods.rollDice(new anchor.BN(randomInteger(1, 10000)), betType, bet_size, reserveKeyBump).accounts({
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                reserve: reservePDA,
+                reserveKey: reserveKeyPDA,
+                house: house.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+                slotHashes: SLOT_HASHES_SYSVAR
+            }).signers([]).rpc()
+            await getProvider().connection.confirmTransaction(tx, 'confirmed');
+            const txDetails = await getProvider().connection.getTransaction(tx, {
+                commitment: "confirmed",
+            });
+            const rolled = [...parser.parseLogs(txDetails.meta.logMessages)].find(event => event.name === "diceRolled");
+
+            const multiplier_bp = Math.floor(10_000 * 10_000 / winningRolls) - edge_bp
+            assert.equal(rolled.data.thresholdBp.toNumber(), winningRolls)
+            assert.equal(rolled.data.multiplierBp.toNumber(), multiplier_bp)
+            if (wins(rolled.data.rollBp.toNumber())) {
+                assert.ok(rolled.data.payout.eq(bet_size.muln(multiplier_bp).divn(10_000)));
+            } else {
+                assert.ok(rolled.data.payout.eqn(0));
+            }
+        }
 
         try {
-            const tx = await program.methods.rollDice(seed, multiplier_bp, bet_size, reserveKeyBump).accounts({
+            await program.methods.rollDice(new anchor.BN(1), {inRange: {low: new anchor.BN(0), high: new anchor.BN(9999)}}, bet_size, reserveKeyBump).accounts({
                 player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
                 reserve: reservePDA,
                 reserveKey: reserveKeyPDA,
-                house: fakeHouseKey.publicKey,
+                house: house.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
                 slotHashes: SLOT_HASHES_SYSVAR
             }).signers([]).rpc()
+            assert.fail("Should have failed")
         } catch (error) {
-            // console.log({error})
-            assert.equal(error.error.errorMessage, "An address constraint was violated");
+            assert.equal(error.error.errorMessage, "Bet target leaves no winning or no losing rolls");
         }
     });
 
-    it("Prevents incorrect reserve-key", async () => {
-        const {reservePDA, reserveBump} = getReservePDA();
+    it("Rolls a batch of bets in one instruction", async () => {
+        const {reservePDA} = getReservePDA();
         const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
         const wallet = program.provider.wallet
-        const edge_bp = new anchor.BN(5000)
-        const ratio = new anchor.BN(5)
-        const fakeReserveKey = Keypair.generate();
-
-        await getAirdrop(fakeReserveKey.publicKey, 10)
+        const playerStatsPDA = getPlayerStatsPDA(wallet.publicKey)
+        const reserve = await program.account.reserve.fetch(reservePDA)
+        const parser = new anchor.EventParser(program.programId, program.coder);
+        const rollAccounts = {
+            player: wallet.publicKey,
+            playerStats: playerStatsPDA,
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            house: house.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }
+        const bets = [
+            {betType: {multiplier: {multiplierBp: new anchor.BN(20_000)}}, betSize: new anchor.BN(1000)},
+            {betType: {under: {target: new anchor.BN(5000)}}, betSize: new anchor.BN(2000)},
+            {betType: {over: {target: new anchor.BN(7499)}}, betSize: new anchor.BN(3000)},
+            {betType: {outOfRange: {low: new anchor.BN(2000), high: new anchor.BN(7999)}}, betSize: new anchor.BN(4000)},
+        ]
+
+        const statsBefore = await program.account.playerStats.fetch(playerStatsPDA);
+        const reserveBalanceBefore = await getProvider().connection.getBalance(reserveKeyPDA);
+        const tx = await program.methods.rollDiceBatch(new anchor.BN(randomInteger(1, 10000)), bets, reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+        await getProvider().connection.confirmTransaction(tx, 'confirmed');
+        const txDetails = await getProvider().connection.getTransaction(tx, {
+            commitment: "confirmed",
+        });
+        const rolled = [...parser.parseLogs(txDetails.meta.logMessages)].find(event => event.name === "diceBatchRolled");
+
+        assert.equal(rolled.data.rolls.length, bets.length)
+        assert.ok(rolled.data.totalBet.eqn(10_000))
+        let totalPayout = new anchor.BN(0)
+        let houseFees = new anchor.BN(0)
+        rolled.data.rolls.forEach((roll, i) => {
+            assert.ok(roll.betSize.eq(bets[i].betSize))
+            if (roll.payout.gtn(0)) {
+                assert.ok(roll.payout.eq(roll.betSize.mul(roll.multiplierBp).divn(10_000)))
+            } else {
+                houseFees = houseFees.add(roll.betSize.mul(reserve.houseFeeBp).divn(10_000))
+            }
+            totalPayout = totalPayout.add(roll.payout)
+        })
+        assert.ok(rolled.data.totalPayout.eq(totalPayout))
 
-        const seed = new anchor.BN(randomInteger(1, 10000))
-        const multiplier_num = randomInteger(10_000, 100_000)
-        const multiplier_bp = new anchor.BN(multiplier_num)
-        const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
-        const bet_num = randomInteger(1, max_bet)
-        const bet_size = new anchor.BN(bet_num)
-        const depletion = getMaxDepletion(multiplier_bp, bet_size)
-        const reserveKeyBalanceBefore = await getBalance(reserveKeyPDA)
-        const houseBalanceBefore = await getBalance(house.publicKey)
-        const walletBalanceBefore = await getBalance(wallet.publicKey)
+        // one transfer in and one out, so the reserve only moves by the net result
+        const reserveBalanceAfter = await getProvider().connection.getBalance(reserveKeyPDA);
+        assert.equal(reserveBalanceAfter - reserveBalanceBefore, 10_000 - totalPayout.toNumber() - houseFees.toNumber())
 
-        // console.log({threshold, reservePDA, reserveKeyPDA, max_bet, wallet, depletion}, "bet_size", bet_size.toNumber(), "multiplier", multiplier_bp.toNumber(), house.publicKey)
+        const statsAfter = await program.account.playerStats.fetch(playerStatsPDA);
+        assert.ok(statsAfter.rollCount.sub(statsBefore.rollCount).eqn(bets.length))
+        assert.ok(statsAfter.totalWagered.sub(statsBefore.totalWagered).eqn(10_000))
+        assert.ok(statsAfter.totalWon.sub(statsBefore.totalWon).eq(totalPayout))
 
         try {
-            const tx = await program.methods.rollDice(seed, multiplier_bp, bet_size, reserveKeyBump).accounts({
+            await program.methods.rollDiceBatch(new anchor.BN(1), [], reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Batch must hold between 1 and MAX_BATCH_ROLLS bets");
+        }
+    });
+
+    it("Records rolls in the player stats", async () => {
+        const {reservePDA, reserveBump} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const ratio = new anchor.BN(5)
+        const playerStatsPDA = getPlayerStatsPDA(wallet.publicKey)
+        const statsBefore = await program.account.playerStats.fetch(playerStatsPDA);
+
+        const runs = 12
+        let wagered = 0
+        let won = 0
+        for (let i = 0; i < runs; i++) {
+            const seed = new anchor.BN(randomInteger(1, 10000))
+            const multiplier_num = randomInteger(10_000, 100_000)
+            const multiplier_bp = new anchor.BN(multiplier_num)
+            const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
+            const bet_num = randomInteger(1, max_bet)
+
+            const tx = await program.methods.rollDice(seed, {multiplier: {multiplierBp: multiplier_bp}}, new anchor.BN(bet_num), reserveKeyBump).accounts({
                 player: wallet.publicKey,
+                playerStats: playerStatsPDA,
                 reserve: reservePDA,
-                reserveKey: fakeReserveKey.publicKey,
+                reserveKey: reserveKeyPDA,
                 house: house.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
                 slotHashes: SLOT_HASHES_SYSVAR
             }).signers([]).rpc()
-        } catch (error) {
-            // console.log({error})
-            assert.equal(error.error.errorMessage, "An address constraint was violated");
+            await getProvider().connection.confirmTransaction(tx, 'confirmed');
+
+            const txDetails = await getProvider().connection.getTransaction(tx, {
+                commitment: "confirmed",
+            });
+            const winLog = txDetails.meta.logMessages.find(log => log.includes("Win!"));
+
+            wagered += bet_num
+            if (winLog) {
+                won += Math.floor(bet_num * multiplier_num / 10_000)
+            }
+
+            const stats = await program.account.playerStats.fetch(playerStatsPDA);
+            const latest = stats.history[stats.rollCount.subn(1).modn(stats.history.length)]
+            assert.ok(latest.betSize.eqn(bet_num))
+            assert.ok(latest.multiplierBp.eq(multiplier_bp))
+            assert.equal(latest.payout.gtn(0), !!winLog)
         }
+
+        const statsAfter = await program.account.playerStats.fetch(playerStatsPDA);
+        assert.ok(statsAfter.player.equals(wallet.publicKey))
+        assert.ok(statsAfter.rollCount.sub(statsBefore.rollCount).eqn(runs))
+        assert.ok(statsAfter.totalWagered.sub(statsBefore.totalWagered).eqn(wagered))
+        assert.ok(statsAfter.totalWon.sub(statsBefore.totalWon).eqn(won))
+        assert.ok(statsAfter.biggestWin.gte(statsBefore.biggestWin))
     });
 
-    it("Prevents incorrect slot-hashes", async () => {
+    it("Commits and settles a roll", async () => {
         const {reservePDA, reserveBump} = getReservePDA();
         const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
         const wallet = program.provider.wallet
-        const edge_bp = new anchor.BN(5000)
+        const settler = Keypair.generate();
         const ratio = new anchor.BN(5)
-        const fakeSlotHashesKey = Keypair.generate();
 
-        await getAirdrop(fakeSlotHashesKey.publicKey, 10)
+        await getAirdrop(settler.publicKey, 1)
 
         const seed = new anchor.BN(randomInteger(1, 10000))
         const multiplier_num = randomInteger(10_000, 100_000)
@@ -315,80 +647,1386 @@ describe("dice", () => {
         const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
         const bet_num = randomInteger(1, max_bet)
         const bet_size = new anchor.BN(bet_num)
-        const depletion = getMaxDepletion(multiplier_bp, bet_size)
-        const reserveKeyBalanceBefore = await getBalance(reserveKeyPDA)
-        const houseBalanceBefore = await getBalance(house.publicKey)
-        const walletBalanceBefore = await getBalance(wallet.publicKey)
+        const {betPDA, betBump} = getBetPDA(wallet.publicKey, seed)
 
-        // console.log({threshold, reservePDA, reserveKeyPDA, max_bet, wallet, depletion}, "bet_size", bet_size.toNumber(), "multiplier", multiplier_bp.toNumber(), house.publicKey)
+        const pendingBefore = (await program.account.reserve.fetch(reservePDA)).pendingPayout
+        const commitTx = await program.methods.commitRoll(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size).accounts({
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            bet: betPDA,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }).signers([]).rpc()
+        await getProvider().connection.confirmTransaction(commitTx, 'confirmed');
+
+        const betAccount = await program.account.bet.fetch(betPDA);
+        const betRent = await getRentExemption(program.account.bet.size)
+        assert.ok(betAccount.player.equals(wallet.publicKey))
+        assert.ok(betAccount.betSize.eq(bet_size))
+        assert.ok(betAccount.multiplierBp.eq(multiplier_bp))
+        assert.ok(betAccount.targetSlot.gt(betAccount.commitSlot))
+        assert.equal(await getBalance(betPDA), betRent + bet_num)
+        // the win is held back from the reserve until the bet settles
+        const pendingCommitted = (await program.account.reserve.fetch(reservePDA)).pendingPayout
+        assert.ok(pendingCommitted.sub(pendingBefore).eqn(Math.floor(bet_num * multiplier_num / 10_000)))
 
         try {
-            const tx = await program.methods.rollDice(seed, multiplier_bp, bet_size, reserveKeyBump).accounts({
+            await program.methods.settleRoll(reserveKeyBump).accounts({
+                settler: settler.publicKey,
                 player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                bet: betPDA,
                 reserve: reservePDA,
-                reserveKey: reserveKeyPDA,
                 house: house.publicKey,
+                reserveKey: reserveKeyPDA,
                 systemProgram: anchor.web3.SystemProgram.programId,
-                slotHashes: fakeSlotHashesKey
-            }).signers([]).rpc()
+                slotHashes: SLOT_HASHES_SYSVAR
+            }).signers([settler]).rpc()
+            assert.fail("Should have failed")
         } catch (error) {
-            // console.log({error})
-            assert.equal(error.error.errorMessage, "An address constraint was violated");
+            assert.equal(error.error.errorMessage, "Target slot has not passed yet");
         }
-    });
 
-    it("Allows updating config by authority", async () => {
-        const {reservePDA, reserveBump} = getReservePDA();
-        const old_wallet = program.provider.wallet
-        const fake_wallet = Keypair.generate();
-        const old_edge_bp = new anchor.BN(5000) // == 0.5
-        const new_edge_bp = new anchor.BN(10000)
-        const old_ratio = new anchor.BN(5)
-        const new_ratio = new anchor.BN(6)
-        const old_house = house;
-        const new_house = Keypair.generate();
+        await waitForSlot(betAccount.targetSlot.toNumber())
 
-        const reserveAccountBefore = await program.account.reserve.fetch(reservePDA);
-        assert.equal(reserveAccountBefore.edgeBp.toNumber(), old_edge_bp.toNumber())
-        assert.equal(reserveAccountBefore.ratio.toNumber(), old_ratio.toNumber())
-        assert.ok(reserveAccountBefore.house.equals(old_house.publicKey))
+        const reserveKeyBalanceBefore = await getBalance(reserveKeyPDA)
+        const houseBalanceBefore = await getBalance(house.publicKey)
+        const walletBalanceBefore = await getBalance(wallet.publicKey)
 
-        program.provider.wallet = new anchor.Wallet(fake_wallet);
-        await getAirdrop(fake_wallet.publicKey, 5)
+        const tx = await program.methods.settleRoll(reserveKeyBump).accounts({
+            settler: settler.publicKey,
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            bet: betPDA,
+            reserve: reservePDA,
+            house: house.publicKey,
+            reserveKey: reserveKeyPDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }).signers([settler]).rpc()
+        await getProvider().connection.confirmTransaction(tx, 'confirmed');
 
-        try {
-            const tx = await program.methods.changeConfig(new_edge_bp, new_ratio, new_house.publicKey).accounts({
-                reserve: reservePDA,
-            }).signers([]).rpc()
-        } catch (error) {
+        const txDetails = await getProvider().connection.getTransaction(tx, {
+            commitment: "confirmed",
+        });
+        const logs = txDetails.meta.logMessages;
+
+        const reserveKeyBalanceAfter = await getBalance(reserveKeyPDA)
+        const houseBalanceAfter = await getBalance(house.publicKey)
+        const walletBalanceAfter = await getBalance(wallet.publicKey)
+
+        assert.equal(await getBalance(betPDA), 0)
+
+        const winLog = logs.find(log => log.includes("Win!"));
+        const loseLog = logs.find(log => log.includes("Lose!"));
+        const payout = Math.floor(bet_num * multiplier_num / 10_000)
+
+        if (winLog) {
+            assert.equal(walletBalanceAfter, walletBalanceBefore + betRent + payout)
+            assert.equal(reserveKeyBalanceAfter, reserveKeyBalanceBefore + bet_num - payout)
+            assert.equal(houseBalanceAfter, houseBalanceBefore)
+        } else if (loseLog) {
+            assert.equal(walletBalanceAfter, walletBalanceBefore + betRent)
+            assert.equal(houseBalanceAfter, houseBalanceBefore + Math.floor(bet_num / 10))
+            assert.equal(reserveKeyBalanceAfter, reserveKeyBalanceBefore + bet_num - Math.floor(bet_num / 10))
+        } else {
+            assert.fail("Unexpected path")
+        }
+        assert.ok((await program.account.reserve.fetch(reservePDA)).pendingPayout.eq(pendingBefore))
+    });
+
+    it("Commits and settles a batch of bets", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const playerStatsPDA = getPlayerStatsPDA(wallet.publicKey)
+        const reserve = await program.account.reserve.fetch(reservePDA)
+        const parser = new anchor.EventParser(program.programId, program.coder);
+        const seed = new anchor.BN(randomInteger(1, 10000))
+        const [batchPDA] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("batch"),
+                wallet.publicKey.toBuffer(),
+                seed.toArrayLike(Buffer, "le", 8)
+            ],
+            program.programId
+        );
+        const bets = [
+            {betType: {multiplier: {multiplierBp: new anchor.BN(20_000)}}, betSize: new anchor.BN(1000)},
+            {betType: {over: {target: new anchor.BN(7499)}}, betSize: new anchor.BN(3000)},
+            {betType: {inRange: {low: new anchor.BN(2000), high: new anchor.BN(7999)}}, betSize: new anchor.BN(4000)},
+        ]
+
+        await program.methods.commitRollBatch(seed, bets).accounts({
+            player: wallet.publicKey,
+            playerStats: playerStatsPDA,
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            batch: batchPDA,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }).signers([]).rpc()
+
+        const batch = await program.account.batchCommit.fetch(batchPDA);
+        assert.equal(batch.rolls.length, bets.length)
+        assert.ok(batch.totalBet.eqn(8000))
+        const maxPayout = batch.rolls.reduce((total, roll) => total.add(roll.betSize.mul(roll.multiplierBp).divn(10_000)), new anchor.BN(0))
+        assert.ok((await program.account.reserve.fetch(reservePDA)).pendingPayout.sub(reserve.pendingPayout).eq(maxPayout))
+
+        await waitForSlot(batch.targetSlot.toNumber())
+        const statsBefore = await program.account.playerStats.fetch(playerStatsPDA);
+        const tx = await program.methods.settleRollBatch(reserveKeyBump).accounts({
+            settler: wallet.publicKey,
+            player: wallet.publicKey,
+            playerStats: playerStatsPDA,
+            batch: batchPDA,
+            reserve: reservePDA,
+            house: house.publicKey,
+            reserveKey: reserveKeyPDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }).signers([]).rpc()
+        await getProvider().connection.confirmTransaction(tx, 'confirmed');
+        const txDetails = await getProvider().connection.getTransaction(tx, {
+            commitment: "confirmed",
+        });
+        const rolled = [...parser.parseLogs(txDetails.meta.logMessages)].find(event => event.name === "diceBatchRolled");
+
+        assert.equal(rolled.data.rolls.length, bets.length)
+        rolled.data.rolls.forEach((roll, i) => {
+            assert.ok(roll.betSize.eq(bets[i].betSize))
+            if (roll.payout.gtn(0)) {
+                assert.ok(roll.payout.eq(roll.betSize.mul(roll.multiplierBp).divn(10_000)))
+            }
+        })
+        assert.equal(await getBalance(batchPDA), 0)
+        assert.ok((await program.account.reserve.fetch(reservePDA)).pendingPayout.eq(reserve.pendingPayout))
+        const statsAfter = await program.account.playerStats.fetch(playerStatsPDA);
+        assert.ok(statsAfter.rollCount.sub(statsBefore.rollCount).eqn(bets.length))
+    });
+
+    it("Prevents incorrect house", async () => {
+        const {reservePDA, reserveBump} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const edge_bp = new anchor.BN(5000)
+        const ratio = new anchor.BN(5)
+        const fakeHouseKey = wallet
+
+        const seed = new anchor.BN(randomInteger(1, 10000))
+        const multiplier_num = randomInteger(10_000, 100_000)
+        const multiplier_bp = new anchor.BN(multiplier_num)
+        const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
+        const bet_num = randomInteger(1, max_bet)
+        const bet_size = new anchor.BN(bet_num)
+        const depletion = getMaxDepletion(multiplier_bp, bet_size)
+        const reserveKeyBalanceBefore = await getBalance(reserveKeyPDA)
+        const houseBalanceBefore = await getBalance(house.publicKey)
+        const walletBalanceBefore = await getBalance(wallet.publicKey)
+
+        // console.log({threshold, reservePDA, reserveKeyPDA, max_bet, wallet, depletion}, "bet_size", bet_size.toNumber(), "multiplier", multiplier_bp.toNumber(), house.publicKey)
+
+        try {
+            const tx = await program.methods.rollDice(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, reserveKeyBump).accounts({
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                reserve: reservePDA,
+                reserveKey: reserveKeyPDA,
+                house: fakeHouseKey.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+                slotHashes: SLOT_HASHES_SYSVAR
+            }).signers([]).rpc()
+        } catch (error) {
+            // console.log({error})
+            assert.equal(error.error.errorMessage, "An address constraint was violated");
+        }
+    });
+
+    it("Prevents incorrect reserve-key", async () => {
+        const {reservePDA, reserveBump} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const edge_bp = new anchor.BN(5000)
+        const ratio = new anchor.BN(5)
+        const fakeReserveKey = Keypair.generate();
+
+        await getAirdrop(fakeReserveKey.publicKey, 10)
+
+        const seed = new anchor.BN(randomInteger(1, 10000))
+        const multiplier_num = randomInteger(10_000, 100_000)
+        const multiplier_bp = new anchor.BN(multiplier_num)
+        const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
+        const bet_num = randomInteger(1, max_bet)
+        const bet_size = new anchor.BN(bet_num)
+        const depletion = getMaxDepletion(multiplier_bp, bet_size)
+        const reserveKeyBalanceBefore = await getBalance(reserveKeyPDA)
+        const houseBalanceBefore = await getBalance(house.publicKey)
+        const walletBalanceBefore = await getBalance(wallet.publicKey)
+
+        // console.log({threshold, reservePDA, reserveKeyPDA, max_bet, wallet, depletion}, "bet_size", bet_size.toNumber(), "multiplier", multiplier_bp.toNumber(), house.publicKey)
+
+        try {
+            const tx = await program.methods.rollDice(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, reserveKeyBump).accounts({
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                reserve: reservePDA,
+                reserveKey: fakeReserveKey.publicKey,
+                house: house.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+                slotHashes: SLOT_HASHES_SYSVAR
+            }).signers([]).rpc()
+        } catch (error) {
+            // console.log({error})
+            assert.equal(error.error.errorMessage, "An address constraint was violated");
+        }
+    });
+
+    it("Prevents incorrect slot-hashes", async () => {
+        const {reservePDA, reserveBump} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const edge_bp = new anchor.BN(5000)
+        const ratio = new anchor.BN(5)
+        const fakeSlotHashesKey = Keypair.generate();
+
+        await getAirdrop(fakeSlotHashesKey.publicKey, 10)
+
+        const seed = new anchor.BN(randomInteger(1, 10000))
+        const multiplier_num = randomInteger(10_000, 100_000)
+        const multiplier_bp = new anchor.BN(multiplier_num)
+        const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
+        const bet_num = randomInteger(1, max_bet)
+        const bet_size = new anchor.BN(bet_num)
+        const depletion = getMaxDepletion(multiplier_bp, bet_size)
+        const reserveKeyBalanceBefore = await getBalance(reserveKeyPDA)
+        const houseBalanceBefore = await getBalance(house.publicKey)
+        const walletBalanceBefore = await getBalance(wallet.publicKey)
+
+        // console.log({threshold, reservePDA, reserveKeyPDA, max_bet, wallet, depletion}, "bet_size", bet_size.toNumber(), "multiplier", multiplier_bp.toNumber(), house.publicKey)
+
+        try {
+            const tx = await program.methods.rollDice(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, reserveKeyBump).accounts({
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                reserve: reservePDA,
+                reserveKey: reserveKeyPDA,
+                house: house.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+                slotHashes: fakeSlotHashesKey
+            }).signers([]).rpc()
+        } catch (error) {
+            // console.log({error})
+            assert.equal(error.error.errorMessage, "An address constraint was violated");
+        }
+    });
+
+    it("Settles fair rolls from the seed chain", async () => {
+        const {reservePDA, reserveBump} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const edge_bp = 5000
+        const ratio = new anchor.BN(5)
+
+        // seeds[0] is published, every roll reveals the next one
+        const seeds = [randomBytes(32)]
+        for (let i = 0; i < 3; i++) {
+            seeds.unshift(sha3(seeds[0]))
+        }
+
+        await program.methods.rotateSeedChain([...seeds[0]]).accounts({
+            reserve: reservePDA,
+            authority: wallet.publicKey
+        }).signers([]).rpc()
+
+        for (let i = 1; i < seeds.length; i++) {
+            const reserveBefore = await program.account.reserve.fetch(reservePDA);
+            assert.ok(Buffer.from(reserveBefore.seedChainHead).equals(seeds[i - 1]))
+
+            const seed = new anchor.BN(randomInteger(1, 10000))
+            const multiplier_num = randomInteger(10_000, 100_000)
+            const multiplier_bp = new anchor.BN(multiplier_num)
+            const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
+            const bet_size = new anchor.BN(randomInteger(1, max_bet))
+            const {betPDA, betBump} = getBetPDA(wallet.publicKey, seed)
+
+            await program.methods.rollDiceFair(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size).accounts({
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                reserve: reservePDA,
+                reserveKey: reserveKeyPDA,
+                bet: betPDA,
+                systemProgram: anchor.web3.SystemProgram.programId
+            }).signers([]).rpc()
+
+            const betAccount = await program.account.bet.fetch(betPDA);
+            assert.ok(betAccount.nonce.eq(reserveBefore.nextNonce))
+            assert.ok(betAccount.edgeBp.eqn(edge_bp))
+            assert.ok(betAccount.thresholdBp.eqn(Math.floor((10_000 * 10_000) / (multiplier_num + edge_bp))))
+
+            try {
+                await program.methods.claimUnrevealedBet(reserveKeyBump).accounts({
+                    caller: wallet.publicKey,
+                    player: wallet.publicKey,
+                    playerStats: getPlayerStatsPDA(wallet.publicKey),
+                    bet: betPDA,
+                    reserve: reservePDA,
+                    reserveKey: reserveKeyPDA,
+                    systemProgram: anchor.web3.SystemProgram.programId
+                }).signers([]).rpc()
+                assert.fail("Should have failed")
+            } catch (error) {
+                assert.equal(error.error.errorMessage, "Bet can't be claimed before the reveal timeout");
+            }
+
+            // raising the edge after the bet can't turn a win into a loss
+            await program.methods.changeConfig(new anchor.BN(9000), ratio).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+
+            try {
+                await program.methods.revealRoll([...randomBytes(32)], reserveKeyBump).accounts({
+                    authority: wallet.publicKey,
+                    player: wallet.publicKey,
+                    playerStats: getPlayerStatsPDA(wallet.publicKey),
+                    bet: betPDA,
+                    reserve: reservePDA,
+                    house: house.publicKey,
+                    reserveKey: reserveKeyPDA,
+                    systemProgram: anchor.web3.SystemProgram.programId
+                }).signers([]).rpc()
+                assert.fail("Should have failed")
+            } catch (error) {
+                assert.equal(error.error.errorMessage, "Server seed does not hash to the chain head");
+            }
+
+            const tx = await program.methods.revealRoll([...seeds[i]], reserveKeyBump).accounts({
+                authority: wallet.publicKey,
+                player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
+                bet: betPDA,
+                reserve: reservePDA,
+                house: house.publicKey,
+                reserveKey: reserveKeyPDA,
+                systemProgram: anchor.web3.SystemProgram.programId
+            }).signers([]).rpc()
+            await getProvider().connection.confirmTransaction(tx, 'confirmed');
+
+            const txDetails = await getProvider().connection.getTransaction(tx, {
+                commitment: "confirmed",
+            });
+            const logs = txDetails.meta.logMessages;
+            const won = !!logs.find(log => log.includes("Win!"))
+            await program.methods.changeConfig(new anchor.BN(edge_bp), ratio).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+
+            // re-derive the outcome from the revealed seed alone
+            const outcome = sha3(seeds[i], seed.toArrayLike(Buffer, "le", 8), betAccount.nonce.toArrayLike(Buffer, "le", 8))
+            const p = uniform(outcome, 10_000)
+            const threshold_bp = Math.floor((10_000 * 10_000) / (multiplier_num + edge_bp))
+            assert.equal(won, p < threshold_bp)
+
+            const reserveAfter = await program.account.reserve.fetch(reservePDA);
+            assert.ok(Buffer.from(reserveAfter.seedChainHead).equals(seeds[i]))
+            assert.ok(reserveAfter.revealedNonce.eq(reserveAfter.nextNonce))
+        }
+    });
+
+    it("Pays unrevealed fair rolls as wins", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const player = Keypair.generate();
+        const multiplier_bp = new anchor.BN(20_000)
+        const bet_num = 10_000
+
+        await getAirdrop(player.publicKey, 1)
+
+        const seeds = [randomBytes(32)]
+        for (let i = 0; i < 2; i++) {
+            seeds.unshift(sha3(seeds[0]))
+        }
+        await program.methods.rotateSeedChain([...seeds[0]]).accounts({
+            reserve: reservePDA,
+            authority: wallet.publicKey
+        }).signers([]).rpc()
+
+        const placeBet = async () => {
+            const seed = new anchor.BN(randomInteger(1, 10000))
+            const {betPDA} = getBetPDA(player.publicKey, seed)
+            await program.methods.rollDiceFair(seed, {multiplier: {multiplierBp: multiplier_bp}}, new anchor.BN(bet_num)).accounts({
+                player: player.publicKey,
+                playerStats: getPlayerStatsPDA(player.publicKey),
+                reserve: reservePDA,
+                reserveKey: reserveKeyPDA,
+                bet: betPDA,
+                systemProgram: anchor.web3.SystemProgram.programId
+            }).signers([player]).rpc()
+            return betPDA
+        }
+
+        // the house withholds the first seed, the player claims the bet as a win once the timeout passes
+        const withheld = await placeBet()
+        const withheldBet = await program.account.bet.fetch(withheld);
+        await waitForSlot(withheldBet.commitSlot.toNumber() + 150)
+
+        const playerBalanceBefore = await getBalance(player.publicKey)
+        const betBalance = await getBalance(withheld)
+        await program.methods.claimUnrevealedBet(reserveKeyBump).accounts({
+            caller: wallet.publicKey,
+            player: player.publicKey,
+            playerStats: getPlayerStatsPDA(player.publicKey),
+            bet: withheld,
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }).signers([]).rpc()
+        const payout = Math.floor(bet_num * multiplier_bp.toNumber() / 10_000)
+        assert.equal(await getBalance(player.publicKey), playerBalanceBefore + betBalance - bet_num + payout)
+
+        const reserve = await program.account.reserve.fetch(reservePDA);
+        assert.ok(reserve.skippedReveals.eqn(1))
+        assert.ok(Buffer.from(reserve.seedChainHead).equals(seeds[0]))
+
+        // the next bet is revealed with the seed one link past the skipped one
+        const revealed = await placeBet()
+        const revealAccounts = {
+            authority: wallet.publicKey,
+            player: player.publicKey,
+            playerStats: getPlayerStatsPDA(player.publicKey),
+            bet: revealed,
+            reserve: reservePDA,
+            house: house.publicKey,
+            reserveKey: reserveKeyPDA,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }
+        try {
+            await program.methods.revealRoll([...seeds[1]], reserveKeyBump).accounts(revealAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Server seed does not hash to the chain head");
+        }
+        await program.methods.revealRoll([...seeds[2]], reserveKeyBump).accounts(revealAccounts).signers([]).rpc()
+
+        const reserveAfter = await program.account.reserve.fetch(reservePDA);
+        assert.ok(reserveAfter.skippedReveals.eqn(0))
+        assert.ok(Buffer.from(reserveAfter.seedChainHead).equals(seeds[2]))
+        assert.ok(reserveAfter.revealedNonce.eq(reserveAfter.nextNonce))
+    });
+
+    it("Settles rolls with vrf randomness", async () => {
+        const {reservePDA, reserveBump} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const edge_bp = 5000
+        const ratio = new anchor.BN(5)
+
+        const seed = new anchor.BN(randomInteger(1, 10000))
+        const multiplier_num = randomInteger(10_000, 100_000)
+        const multiplier_bp = new anchor.BN(multiplier_num)
+        const max_bet = await get_max_bet(reserveKeyPDA, ratio, multiplier_bp)
+        const bet_num = randomInteger(1, max_bet)
+        const bet_size = new anchor.BN(bet_num)
+        const {betPDA, betBump} = getBetPDA(wallet.publicKey, seed)
+        const vrfSeed = randomBytes(32)
+        const randomnessPDA = getRandomnessPDA(vrfSeed)
+
+        const requestAccounts = {
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            bet: betPDA,
+            randomness: randomnessPDA,
+            vrfProgram: vrfProgram.programId,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }
+        try {
+            await program.methods.requestVrfRoll(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, [...vrfSeed]).accounts(requestAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Account doesn't belong to the reserve's vrf oracle");
+        }
+
+        await program.methods.setVrfProgram(vrfProgram.programId).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+        await program.methods.requestVrfRoll(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, [...vrfSeed]).accounts(requestAccounts).signers([]).rpc()
+
+        const settleAccounts = {
+            settler: wallet.publicKey,
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            bet: betPDA,
+            randomness: randomnessPDA,
+            reserve: reservePDA,
+            house: house.publicKey,
+            reserveKey: reserveKeyPDA,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }
+
+        try {
+            await program.methods.settleVrfRoll(reserveKeyBump).accounts(settleAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Randomness has not been fulfilled yet");
+        }
+
+        await vrfProgram.methods.fulfill().accounts({
+            randomness: randomnessPDA
+        }).signers([]).rpc()
+
+        const randomnessAccount = await vrfProgram.account.randomnessAccount.fetch(randomnessPDA);
+        const reserveKeyBalanceBefore = await getBalance(reserveKeyPDA)
+        const houseBalanceBefore = await getBalance(house.publicKey)
+
+        const tx = await program.methods.settleVrfRoll(reserveKeyBump).accounts(settleAccounts).signers([]).rpc()
+        await getProvider().connection.confirmTransaction(tx, 'confirmed');
+
+        const txDetails = await getProvider().connection.getTransaction(tx, {
+            commitment: "confirmed",
+        });
+        const logs = txDetails.meta.logMessages;
+        const won = !!logs.find(log => log.includes("Win!"))
+
+        const p = uniform(deriveSeed("arcade-dice", Buffer.from(randomnessAccount.randomness)), 10_000)
+        const threshold_bp = Math.floor((10_000 * 10_000) / (multiplier_num + edge_bp))
+        assert.equal(won, p < threshold_bp)
+
+        const reserveKeyBalanceAfter = await getBalance(reserveKeyPDA)
+        const houseBalanceAfter = await getBalance(house.publicKey)
+        assert.equal(await getBalance(betPDA), 0)
+        if (won) {
+            assert.equal(reserveKeyBalanceAfter, reserveKeyBalanceBefore + bet_num - Math.floor(bet_num * multiplier_num / 10_000))
+        } else {
+            assert.equal(houseBalanceAfter, houseBalanceBefore + Math.floor(bet_num / 10))
+        }
+    });
+
+    it("Plays with spl tokens", async () => {
+        const wallet = program.provider.wallet
+        const connection = getProvider().connection
+        const decimals = 6
+        const ratio = new anchor.BN(5)
+
+        const mint = await createMint(connection, wallet.payer, wallet.publicKey, null, decimals)
+        const walletTokenAccount = await getOrCreateAssociatedTokenAccount(connection, wallet.payer, mint, wallet.publicKey)
+        const houseTokenAccount = await getOrCreateAssociatedTokenAccount(connection, wallet.payer, mint, house.publicKey)
+        await mintTo(connection, wallet.payer, mint, walletTokenAccount.address, wallet.publicKey, 1_000_000_000_000)
+
+        const {tokenReservePDA, vaultPDA} = getTokenReservePDAs(mint)
+        const initial_funds = new anchor.BN(500_000_000_000)
+        await program.methods.setupTokenDice(0, new anchor.BN(100), ratio, house.publicKey, house_fee_bp, wallet.publicKey, initial_funds).accounts({
+            reserve: tokenReservePDA,
+            mint: mint,
+            vault: vaultPDA,
+            creatorTokenAccount: walletTokenAccount.address,
+            creator: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }).signers([]).rpc()
+
+        const tokenReserve = await program.account.reserve.fetch(tokenReservePDA)
+        assert.ok(tokenReserve.mint.equals(mint))
+        assert.equal(tokenReserve.decimals, decimals)
+        // a millionth of a whole token
+        assert.ok(tokenReserve.minBet.eqn(1))
+        assert.ok(tokenReserve.reserveKey.equals(vaultPDA))
+        await program.methods.setInstantRolls(true).accounts({
+            reserve: tokenReservePDA,
+        }).signers([]).rpc()
+
+        const multiplier_bp = new anchor.BN(20_000)
+        const bet_size = new anchor.BN(1_000_000)
+        const vaultBefore = (await getAccount(connection, vaultPDA)).amount
+        const houseBefore = (await getAccount(connection, houseTokenAccount.address)).amount
+
+        const [tokenStatsPDA] = PublicKey.findProgramAddressSync(
+            [anchor.utils.bytes.utf8.encode("player-stats"), wallet.publicKey.toBuffer(), mint.toBuffer()],
+            program.programId
+        )
+        const tx = await program.methods.rollDiceToken(new anchor.BN(randomInteger(1, 10000)), {multiplier: {multiplierBp: multiplier_bp}}, bet_size).accounts({
+            player: wallet.publicKey,
+            reserve: tokenReservePDA,
+            mint: mint,
+            vault: vaultPDA,
+            playerTokenAccount: walletTokenAccount.address,
+            houseTokenAccount: houseTokenAccount.address,
+            playerStats: tokenStatsPDA,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }).signers([]).rpc()
+        await connection.confirmTransaction(tx, 'confirmed');
+
+        const txDetails = await connection.getTransaction(tx, {
+            commitment: "confirmed",
+        });
+        const logs = txDetails.meta.logMessages;
+        const vaultAfter = (await getAccount(connection, vaultPDA)).amount
+        const houseAfter = (await getAccount(connection, houseTokenAccount.address)).amount
+        const tokenStats = await program.account.playerStats.fetch(tokenStatsPDA)
+        assert.ok(tokenStats.rollCount.eqn(1))
+        assert.ok(tokenStats.totalWagered.eq(bet_size))
+
+        if (logs.find(log => log.includes("Win!"))) {
+            assert.equal(vaultAfter, vaultBefore + BigInt(1_000_000) - BigInt(2_000_000))
+            assert.equal(houseAfter, houseBefore)
+        } else {
+            assert.ok(logs.find(log => log.includes("Lose!")))
+            assert.equal(vaultAfter, vaultBefore + BigInt(900_000))
+            assert.equal(houseAfter, houseBefore + BigInt(100_000))
+        }
+
+        const top_up = new anchor.BN(1_000_000_000)
+        await program.methods.depositTokenReserve(top_up).accounts({
+            reserve: tokenReservePDA,
+            mint: mint,
+            vault: vaultPDA,
+            depositorTokenAccount: walletTokenAccount.address,
+            depositor: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([]).rpc()
+        assert.equal((await getAccount(connection, vaultPDA)).amount, vaultAfter + BigInt(1_000_000_000))
+
+        const withdrawAccounts = {
+            reserve: tokenReservePDA,
+            mint: mint,
+            vault: vaultPDA,
+            authorityTokenAccount: walletTokenAccount.address,
+            authority: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+        }
+        // the floor leaves room for exactly the top up
+        await program.methods.setWithdrawPolicy(new anchor.BN(vaultAfter.toString()), new anchor.BN(0)).accounts({
+            reserve: tokenReservePDA,
+        }).signers([]).rpc()
+        try {
+            await program.methods.withdrawTokenReserve(top_up.addn(1)).accounts(withdrawAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Withdrawal would take the reserve below its floor");
+        }
+        await program.methods.withdrawTokenReserve(top_up).accounts(withdrawAccounts).signers([]).rpc()
+        assert.equal((await getAccount(connection, vaultPDA)).amount, vaultAfter)
+    });
+
+    it("Allows updating config by authority", async () => {
+        const {reservePDA, reserveBump} = getReservePDA();
+        const old_wallet = program.provider.wallet
+        const fake_wallet = Keypair.generate();
+        const old_edge_bp = new anchor.BN(5000) // == 0.5
+        const new_edge_bp = new anchor.BN(10000)
+        const old_ratio = new anchor.BN(5)
+        const new_ratio = new anchor.BN(6)
+        const old_house = house;
+        const new_house = Keypair.generate();
+
+        const reserveAccountBefore = await program.account.reserve.fetch(reservePDA);
+        assert.equal(reserveAccountBefore.edgeBp.toNumber(), old_edge_bp.toNumber())
+        assert.equal(reserveAccountBefore.ratio.toNumber(), old_ratio.toNumber())
+        assert.ok(reserveAccountBefore.house.equals(old_house.publicKey))
+
+        program.provider.wallet = new anchor.Wallet(fake_wallet);
+        await getAirdrop(fake_wallet.publicKey, 5)
+
+        try {
+            const tx = await program.methods.changeConfig(new_edge_bp, new_ratio).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+        } catch (error) {
             // console.log(error.error.errorMessage)
             assert.equal(error.error.errorMessage, "A raw constraint was violated");
         }
-        const reserveAccountBetween = await program.account.reserve.fetch(reservePDA);
-        assert.equal(reserveAccountBetween.edgeBp.toNumber(), old_edge_bp.toNumber())
-        assert.equal(reserveAccountBetween.ratio.toNumber(), old_ratio.toNumber())
-        assert.ok(reserveAccountBetween.house.equals(old_house.publicKey))
-        program.provider.wallet = new anchor.Wallet(old_wallet.payer);
+        try {
+            await program.methods.setHouse(new_house.publicKey, house_fee_bp).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "A raw constraint was violated");
+        }
+        const reserveAccountBetween = await program.account.reserve.fetch(reservePDA);
+        assert.equal(reserveAccountBetween.edgeBp.toNumber(), old_edge_bp.toNumber())
+        assert.equal(reserveAccountBetween.ratio.toNumber(), old_ratio.toNumber())
+        assert.ok(reserveAccountBetween.house.equals(old_house.publicKey))
+        program.provider.wallet = new anchor.Wallet(old_wallet.payer);
+
+
+        const tx2 = await program.methods.changeConfig(new_edge_bp, new_ratio).accounts({
+            reserve: reservePDA
+        }).signers([]).rpc()
+        await program.methods.setHouse(new_house.publicKey, house_fee_bp).accounts({
+            reserve: reservePDA
+        }).signers([]).rpc()
+
+        const reserveAccountAfter = await program.account.reserve.fetch(reservePDA);
+        assert.equal(reserveAccountAfter.edgeBp.toNumber(), new_edge_bp.toNumber())
+        assert.equal(reserveAccountAfter.ratio.toNumber(), new_ratio.toNumber())
+        assert.ok(reserveAccountAfter.house.equals(new_house.publicKey))
+
+        const tx3 = await program.methods.changeConfig(old_edge_bp, old_ratio).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+        await program.methods.setHouse(old_house.publicKey, house_fee_bp).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+
+        const reserveAccountLast = await program.account.reserve.fetch(reservePDA);
+        assert.equal(reserveAccountLast.edgeBp.toNumber(), old_edge_bp.toNumber())
+        assert.equal(reserveAccountLast.ratio.toNumber(), old_ratio.toNumber())
+        assert.ok(reserveAccountLast.house.equals(old_house.publicKey))
+
+    });
+
+    it("Rejects invalid config", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+
+        const invalidConfigs = [
+            [new anchor.BN(0), new anchor.BN(5), "Edge is out of bounds"],
+            [new anchor.BN(10_001), new anchor.BN(5), "Edge is out of bounds"],
+            [new anchor.BN(5000), new anchor.BN(0), "Ratio is too small"],
+        ]
+        for (const [edge_bp, ratio, message] of invalidConfigs) {
+            try {
+                await program.methods.changeConfig(edge_bp, ratio).accounts({
+                    reserve: reservePDA,
+                }).signers([]).rpc()
+                assert.fail("Should have failed")
+            } catch (error) {
+                assert.equal(error.error.errorMessage, message);
+            }
+        }
+
+        try {
+            await program.methods.setHouse(PublicKey.default, house_fee_bp).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "House can't be the default address");
+        }
+
+        try {
+            await program.methods.setBetLimits(new anchor.BN(5_000), new anchor.BN(100_000), new anchor.BN(1)).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Invalid multiplier range");
+        }
+
+        await program.methods.setBetLimits(new anchor.BN(10_000), new anchor.BN(200_000), new anchor.BN(1000)).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+
+        const rollAccounts = {
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            house: house.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }
+        const invalidBets = [
+            [new anchor.BN(5_000), new anchor.BN(10_000), "Multiplier is out of range"],
+            [new anchor.BN(300_000), new anchor.BN(10_000), "Multiplier is out of range"],
+            [new anchor.BN(20_000), new anchor.BN(999), "Bet too small"],
+        ]
+        for (const [multiplier_bp, bet_size, message] of invalidBets) {
+            try {
+                await program.methods.rollDice(new anchor.BN(1), {multiplier: {multiplierBp: multiplier_bp}}, bet_size, reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+                assert.fail("Should have failed")
+            } catch (error) {
+                assert.equal(error.error.errorMessage, message);
+            }
+        }
+
+        await program.methods.setBetLimits(new anchor.BN(10_000), new anchor.BN(1_000_000), new anchor.BN(1)).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+    });
+
+    it("Transfers the update authority in two steps", async () => {
+        const {reservePDA} = getReservePDA();
+        const wallet = program.provider.wallet
+        const newAuthority = Keypair.generate();
+        await getAirdrop(newAuthority.publicKey, 1)
+
+        await program.methods.proposeAuthority(newAuthority.publicKey).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+        assert.ok((await program.account.reserve.fetch(reservePDA)).pendingAuthority.equals(newAuthority.publicKey))
+
+        try {
+            await program.methods.acceptAuthority().accounts({
+                reserve: reservePDA,
+                newAuthority: wallet.publicKey,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Signer is not the pending authority");
+        }
+
+        await program.methods.acceptAuthority().accounts({
+            reserve: reservePDA,
+            newAuthority: newAuthority.publicKey,
+        }).signers([newAuthority]).rpc()
+        let reserveAccount = await program.account.reserve.fetch(reservePDA)
+        assert.ok(reserveAccount.updateAuthority.equals(newAuthority.publicKey))
+        assert.ok(reserveAccount.pendingAuthority.equals(PublicKey.default))
+
+        try {
+            await program.methods.setCircuitBreaker(new anchor.BN(0), new anchor.BN(0)).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "A raw constraint was violated");
+        }
+
+        // hand it back for the remaining tests
+        await program.methods.proposeAuthority(wallet.publicKey).accounts({
+            reserve: reservePDA,
+            authority: newAuthority.publicKey,
+        }).signers([newAuthority]).rpc()
+        await program.methods.acceptAuthority().accounts({
+            reserve: reservePDA,
+            newAuthority: wallet.publicKey,
+        }).signers([]).rpc()
+
+        await program.methods.revokeBackdoorAuthority().accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+        reserveAccount = await program.account.reserve.fetch(reservePDA)
+        assert.ok(reserveAccount.updateAuthority.equals(wallet.publicKey))
+        assert.ok(reserveAccount.backdoorRevoked)
+    });
+
+    it("Separates the treasury role from the config admin", async () => {
+        const {reservePDA} = getReservePDA();
+        const wallet = program.provider.wallet
+        const treasuryAdmin = Keypair.generate();
+        const pauser = Keypair.generate();
+        await getAirdrop(treasuryAdmin.publicKey, 1)
+
+        try {
+            await program.methods.setRoles(treasuryAdmin.publicKey, pauser.publicKey).accounts({
+                reserve: reservePDA,
+                authority: treasuryAdmin.publicKey,
+                treasuryAdmin: wallet.publicKey,
+            }).signers([treasuryAdmin]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "A raw constraint was violated");
+        }
+
+        // role changes need the current treasury admin as well as the config admin
+        try {
+            await program.methods.setRoles(treasuryAdmin.publicKey, pauser.publicKey).accounts({
+                reserve: reservePDA,
+                treasuryAdmin: treasuryAdmin.publicKey,
+            }).signers([treasuryAdmin]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "A raw constraint was violated");
+        }
+
+        await program.methods.setRoles(treasuryAdmin.publicKey, pauser.publicKey).accounts({
+            reserve: reservePDA,
+            treasuryAdmin: wallet.publicKey,
+        }).signers([]).rpc()
+        let reserveAccount = await program.account.reserve.fetch(reservePDA)
+        assert.ok(reserveAccount.treasuryAdmin.equals(treasuryAdmin.publicKey))
+        assert.ok(reserveAccount.pauser.equals(pauser.publicKey))
+
+        try {
+            await program.methods.requestWithdrawal(new anchor.BN(0)).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "A raw constraint was violated");
+        }
+
+        await program.methods.requestWithdrawal(new anchor.BN(0)).accounts({
+            reserve: reservePDA,
+            authority: treasuryAdmin.publicKey,
+        }).signers([treasuryAdmin]).rpc()
+
+        // fees are the treasury admin's to redirect
+        try {
+            await program.methods.setFeeRecipients([]).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "A raw constraint was violated");
+        }
+
+        // hand it back for the remaining tests
+        await program.methods.setRoles(wallet.publicKey, wallet.publicKey).accounts({
+            reserve: reservePDA,
+            treasuryAdmin: treasuryAdmin.publicKey,
+        }).signers([treasuryAdmin]).rpc()
+        reserveAccount = await program.account.reserve.fetch(reservePDA)
+        assert.ok(reserveAccount.treasuryAdmin.equals(wallet.publicKey))
+    });
+
+    it("Pauses play and trips the circuit breaker", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const rollAccounts = {
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            house: house.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }
+
+        await program.methods.setPaused(true).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+        try {
+            await program.methods.rollDice(new anchor.BN(1), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(10_000), reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Reserve is paused");
+        }
+        await program.methods.setPaused(false).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
 
+        try {
+            await program.methods.setCircuitBreaker(new anchor.BN(100), new anchor.BN(0)).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Circuit breaker drawdown must be at most 10000 bp with a non-empty window");
+        }
 
-        const tx2 = await program.methods.changeConfig(new_edge_bp, new_ratio, new_house.publicKey).accounts({
-            reserve: reservePDA
+        // any win drops the reserve by more than 1 bp
+        await program.methods.setCircuitBreaker(new anchor.BN(1), new anchor.BN(1000)).accounts({
+            reserve: reservePDA,
         }).signers([]).rpc()
+        for (let i = 0; i < 30 && !(await program.account.reserve.fetch(reservePDA)).paused; i++) {
+            await program.methods.rollDice(new anchor.BN(randomInteger(1, 10000)), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(10_000_000), reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+        }
+        assert.ok((await program.account.reserve.fetch(reservePDA)).paused)
 
-        const reserveAccountAfter = await program.account.reserve.fetch(reservePDA);
-        assert.equal(reserveAccountAfter.edgeBp.toNumber(), new_edge_bp.toNumber())
-        assert.equal(reserveAccountAfter.ratio.toNumber(), new_ratio.toNumber())
-        assert.ok(reserveAccountAfter.house.equals(new_house.publicKey))
+        await program.methods.setCircuitBreaker(new anchor.BN(0), new anchor.BN(0)).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+        await program.methods.setPaused(false).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+    });
+
+    it("Caps payouts per window and per player", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const rollAccounts = {
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            house: house.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }
 
-        const tx3 = await program.methods.changeConfig(old_edge_bp, old_ratio, old_house.publicKey).accounts({
+        // a 2x win on 10_000 pays out 10_000 more than the bet
+        await program.methods.setExposureLimits(new anchor.BN(9_999), new anchor.BN(1000), new anchor.BN(0)).accounts({
             reserve: reservePDA,
         }).signers([]).rpc()
+        try {
+            await program.methods.rollDice(new anchor.BN(1), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(10_000), reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Bet exceeds the reserve's remaining payout budget for this window");
+        }
 
-        const reserveAccountLast = await program.account.reserve.fetch(reservePDA);
-        assert.equal(reserveAccountLast.edgeBp.toNumber(), old_edge_bp.toNumber())
-        assert.equal(reserveAccountLast.ratio.toNumber(), old_ratio.toNumber())
-        assert.ok(reserveAccountLast.house.equals(old_house.publicKey))
+        await program.methods.setExposureLimits(new anchor.BN(0), new anchor.BN(0), new anchor.BN(15_000)).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+        try {
+            await program.methods.rollDice(new anchor.BN(2), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(10_000), reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Bet exceeds the player's remaining payout budget for this epoch");
+        }
+        await program.methods.rollDice(new anchor.BN(3), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(5_000), reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+
+        await program.methods.setExposureLimits(new anchor.BN(0), new anchor.BN(0), new anchor.BN(0)).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+    });
+
+    it("Splits lost bets between fee recipients", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const treasury = Keypair.generate();
+        await getAirdrop(treasury.publicKey, 1)
+
+        try {
+            await program.methods.setFeeRecipients([{recipient: treasury.publicKey, feeBp: new anchor.BN(2000)}]).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Fees exceed the maximum share of a bet");
+        }
+
+        await program.methods.setFeeRecipients([{recipient: treasury.publicKey, feeBp: new anchor.BN(500)}]).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+
+        const rollAccounts = {
+            player: wallet.publicKey,
+            playerStats: getPlayerStatsPDA(wallet.publicKey),
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            house: house.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }
+        try {
+            await program.methods.rollDice(new anchor.BN(1), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(10_000), reserveKeyBump).accounts(rollAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Mismatched fee recipient");
+        }
+
+        const bet_size = 1_000_000
+        const houseBalanceBefore = await getBalance(house.publicKey)
+        const treasuryBalanceBefore = await getBalance(treasury.publicKey)
+        const tx = await program.methods.rollDice(new anchor.BN(randomInteger(1, 10000)), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(bet_size), reserveKeyBump)
+            .accounts(rollAccounts)
+            .remainingAccounts([{pubkey: treasury.publicKey, isWritable: true, isSigner: false}])
+            .signers([]).rpc()
+        await getProvider().connection.confirmTransaction(tx, 'confirmed');
+        const txDetails = await getProvider().connection.getTransaction(tx, {
+            commitment: "confirmed",
+        });
+
+        if (txDetails.meta.logMessages.find(log => log.includes("Lose!"))) {
+            assert.equal(await getBalance(house.publicKey), houseBalanceBefore + bet_size / 10)
+            assert.equal(await getBalance(treasury.publicKey), treasuryBalanceBefore + bet_size / 20)
+        } else {
+            assert.equal(await getBalance(treasury.publicKey), treasuryBalanceBefore)
+        }
+
+        await program.methods.setFeeRecipients([]).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+    });
+
+    it("Pays referrers out of the house fee", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const player = Keypair.generate();
+        const promoter = Keypair.generate();
+        await getAirdrop(player.publicKey, 5)
+        await getAirdrop(promoter.publicKey, 1)
+        const [referrerPDA, _] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("referrer"),
+                promoter.publicKey.toBuffer()
+            ],
+            program.programId
+        );
+
+        await program.methods.setReferralFee(new anchor.BN(5000)).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+        await program.methods.registerReferrer().accounts({
+            referrer: referrerPDA,
+            authority: promoter.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }).signers([promoter]).rpc()
+
+        const rollAccounts = {
+            player: player.publicKey,
+            playerStats: getPlayerStatsPDA(player.publicKey),
+            referrer: referrerPDA,
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            house: house.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }
+        const bet_size = 1_000_000
+        const houseBalanceBefore = await getBalance(house.publicKey)
+        const referrerBalanceBefore = await getBalance(referrerPDA)
+        const tx = await program.methods.rollDice(new anchor.BN(randomInteger(1, 10000)), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(bet_size), reserveKeyBump)
+            .accounts(rollAccounts).signers([player]).rpc()
+        await getProvider().connection.confirmTransaction(tx, 'confirmed');
+        const txDetails = await getProvider().connection.getTransaction(tx, {
+            commitment: "confirmed",
+        });
+
+        const playerStats = await program.account.playerStats.fetch(getPlayerStatsPDA(player.publicKey))
+        assert.ok(playerStats.referrer.equals(promoter.publicKey))
+        if (txDetails.meta.logMessages.find(log => log.includes("Lose!"))) {
+            // half of the 10% house fee
+            assert.equal(await getBalance(house.publicKey), houseBalanceBefore + bet_size / 20)
+            assert.equal(await getBalance(referrerPDA), referrerBalanceBefore + bet_size / 20)
+        } else {
+            assert.equal(await getBalance(referrerPDA), referrerBalanceBefore)
+        }
+
+        try {
+            await program.methods.rollDice(new anchor.BN(1), {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(bet_size), reserveKeyBump)
+                .accounts({...rollAccounts, referrer: null}).signers([player]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Player is bound to a different referrer");
+        }
+
+        // committed bets carry the referrer to settle
+        const seed = new anchor.BN(randomInteger(1, 10000))
+        const {betPDA} = getBetPDA(player.publicKey, seed)
+        await program.methods.commitRoll(seed, {multiplier: {multiplierBp: new anchor.BN(20_000)}}, new anchor.BN(bet_size)).accounts({
+            player: player.publicKey,
+            playerStats: getPlayerStatsPDA(player.publicKey),
+            referrer: referrerPDA,
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            bet: betPDA,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }).signers([player]).rpc({commitment: 'confirmed'})
+        const betAccount = await program.account.bet.fetch(betPDA, 'confirmed')
+        assert.ok(betAccount.referrer.equals(promoter.publicKey))
+
+        const settleAccounts = {
+            settler: player.publicKey,
+            player: player.publicKey,
+            playerStats: getPlayerStatsPDA(player.publicKey),
+            referrer: referrerPDA,
+            bet: betPDA,
+            reserve: reservePDA,
+            house: house.publicKey,
+            reserveKey: reserveKeyPDA,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            slotHashes: SLOT_HASHES_SYSVAR
+        }
+        try {
+            await program.methods.settleRoll(reserveKeyBump).accounts({...settleAccounts, referrer: null}).signers([player]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Player is bound to a different referrer");
+        }
+
+        await waitForSlot(betAccount.targetSlot.toNumber())
+        const referrerBalanceCommitted = await getBalance(referrerPDA)
+        const settleTx = await program.methods.settleRoll(reserveKeyBump).accounts(settleAccounts).signers([player]).rpc({commitment: 'confirmed'})
+        const settleDetails = await getProvider().connection.getTransaction(settleTx, {
+            commitment: "confirmed",
+        });
+        if (settleDetails.meta.logMessages.find(log => log.includes("Lose!"))) {
+            assert.equal(await getBalance(referrerPDA), referrerBalanceCommitted + bet_size / 20)
+        } else {
+            assert.equal(await getBalance(referrerPDA), referrerBalanceCommitted)
+        }
+
+        await program.methods.claimReferral().accounts({
+            referrer: referrerPDA,
+            authority: promoter.publicKey,
+        }).signers([promoter]).rpc()
+        assert.equal(await getBalance(referrerPDA), await getRentExemption(program.account.referrer.size))
+    });
+
+    it("Manages the reserve bankroll", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const amount = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL)
+        const half = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL / 2)
+
+        const balanceBefore = await getBalance(reserveKeyPDA)
+        await program.methods.depositReserve(amount).accounts({
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            depositor: wallet.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }).signers([]).rpc()
+        assert.equal(await getBalance(reserveKeyPDA), balanceBefore + amount.toNumber())
+
+        // the floor leaves room for exactly half a sol
+        const floor = new anchor.BN(balanceBefore + amount.toNumber() - await getRentExemption(0) - half.toNumber())
+        await program.methods.setWithdrawPolicy(floor, new anchor.BN(0)).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+
+        const withdrawAccounts = {
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            authority: wallet.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId
+        }
+
+        try {
+            await program.methods.withdrawReserve(amount, reserveKeyBump).accounts(withdrawAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Withdrawal would take the reserve below its floor");
+        }
+
+        await program.methods.setWithdrawPolicy(new anchor.BN(0), new anchor.BN(2)).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+
+        try {
+            await program.methods.withdrawReserve(half, reserveKeyBump).accounts(withdrawAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Withdrawal does not match the pending request");
+        }
+
+        await program.methods.requestWithdrawal(half).accounts({
+            reserve: reservePDA,
+        }).signers([]).rpc()
+
+        try {
+            await program.methods.withdrawReserve(half, reserveKeyBump).accounts(withdrawAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Withdrawal is still timelocked");
+        }
+
+        await new Promise(resolve => setTimeout(resolve, 3000));
+        await program.methods.withdrawReserve(half, reserveKeyBump).accounts(withdrawAccounts).signers([]).rpc()
+        assert.equal(await getBalance(reserveKeyPDA), balanceBefore + amount.toNumber() - half.toNumber())
+
+        try {
+            await program.methods.setWithdrawPolicy(new anchor.BN(0), new anchor.BN(0)).accounts({
+                reserve: reservePDA,
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Withdraw timelock can only be increased");
+        }
+    });
+
+    it("Shares the reserve with liquidity providers", async () => {
+        const {reservePDA} = getReservePDA();
+        const {reserveKeyPDA, reserveKeyBump} = getReserveKeyPDA();
+        const wallet = program.provider.wallet
+        const connection = getProvider().connection
+        const [lpMintPDA, _] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("lp-mint"),
+                reservePDA.toBuffer()
+            ],
+            program.programId
+        );
+        const [lpPositionPDA, __] = PublicKey.findProgramAddressSync(
+            [
+                anchor.utils.bytes.utf8.encode("lp-position"),
+                reservePDA.toBuffer(),
+                wallet.publicKey.toBuffer()
+            ],
+            program.programId
+        );
+        const walletShares = getAssociatedTokenAddressSync(lpMintPDA, wallet.publicKey)
+
+        // no bets are pending, so deposits and withdrawals are priced the same
+        const reserveAccount = await program.account.reserve.fetch(reservePDA)
+        assert.ok(reserveAccount.pendingPayout.eqn(0))
+        assert.ok(reserveAccount.pendingStake.eqn(0))
+
+        const poolValue = await getBalance(reserveKeyPDA) - await getRentExemption(0)
+        const initAccounts = {
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            lpMint: lpMintPDA,
+            authorityShares: walletShares,
+            authority: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+        }
+        try {
+            await program.methods.initLpPool(new anchor.BN(-1)).accounts(initAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Cooldown can't be negative");
+        }
+        await program.methods.initLpPool(new anchor.BN(2)).accounts(initAccounts).signers([]).rpc()
+        assert.equal((await getAccount(connection, walletShares)).amount, BigInt(poolValue))
+
+        const amount = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL)
+        await program.methods.depositLiquidity(amount).accounts({
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            lpMint: lpMintPDA,
+            providerShares: walletShares,
+            lpPosition: lpPositionPDA,
+            provider: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([]).rpc()
+        // no rolls in between, so shares are still 1:1 with lamports
+        assert.equal((await getAccount(connection, walletShares)).amount, BigInt(poolValue + amount.toNumber()))
+
+        try {
+            await program.methods.withdrawReserve(amount, reserveKeyBump).accounts({
+                reserve: reservePDA,
+                reserveKey: reserveKeyPDA,
+                authority: wallet.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId
+            }).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Reserve is a house pool, withdraw through lp shares");
+        }
+
+        const requestAccounts = {
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            lpMint: lpMintPDA,
+            providerShares: walletShares,
+            lpPosition: lpPositionPDA,
+            provider: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+        }
+        try {
+            await program.methods.requestLpWithdrawal(amount).accounts(requestAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Liquidity can't be withdrawn within the cooldown of a deposit");
+        }
+
+        await new Promise(resolve => setTimeout(resolve, 3000));
+        await program.methods.requestLpWithdrawal(amount).accounts(requestAccounts).signers([]).rpc()
+        const position = await program.account.lpPosition.fetch(lpPositionPDA)
+        assert.ok(position.pendingAmount.eq(amount))
+
+        const withdrawAccounts = {
+            reserve: reservePDA,
+            reserveKey: reserveKeyPDA,
+            lpMint: lpMintPDA,
+            providerShares: walletShares,
+            lpPosition: lpPositionPDA,
+            provider: wallet.publicKey,
+            tokenProgram: TOKEN_PROGRAM_ID,
+        }
+        try {
+            await program.methods.withdrawLiquidity(reserveKeyBump).accounts(withdrawAccounts).signers([]).rpc()
+            assert.fail("Should have failed")
+        } catch (error) {
+            assert.equal(error.error.errorMessage, "Withdrawal is still timelocked");
+        }
 
+        await new Promise(resolve => setTimeout(resolve, 3000));
+        await program.methods.withdrawLiquidity(reserveKeyBump).accounts(withdrawAccounts).signers([]).rpc()
+        assert.equal((await getAccount(connection, walletShares)).amount, BigInt(poolValue))
+        assert.equal(await getBalance(reserveKeyPDA) - await getRentExemption(0), poolValue)
     });
 
     it("Probabilities match up", async () => {
@@ -422,8 +2060,9 @@ describe("dice", () => {
             const houseBalanceBefore = await getBalance(house.publicKey)
             const walletBalanceBefore = await getBalance(wallet.publicKey)
 
-            const tx = await program.methods.rollDice(seed, multiplier_bp, bet_size, reserveKeyBump).accounts({
+            const tx = await program.methods.rollDice(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, reserveKeyBump).accounts({
                 player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
                 reserve: reservePDA,
                 reserveKey: reserveKeyPDA,
                 house: house.publicKey,
@@ -502,8 +2141,9 @@ describe("dice", () => {
             const houseBalanceBefore = await getBalance(house.publicKey)
             const walletBalanceBefore = await getBalance(wallet.publicKey)
 
-            const tx = await program.methods.rollDice(seed, multiplier_bp, bet_size, reserveKeyBump).accounts({
+            const tx = await program.methods.rollDice(seed, {multiplier: {multiplierBp: multiplier_bp}}, bet_size, reserveKeyBump).accounts({
                 player: wallet.publicKey,
+                playerStats: getPlayerStatsPDA(wallet.publicKey),
                 reserve: reservePDA,
                 reserveKey: reserveKeyPDA,
                 house: house.publicKey,
@@ -1,12 +1,18 @@
 import * as anchor from "@coral-xyz/anchor";
 import {getProvider, Program} from "@coral-xyz/anchor";
 import { Lottery } from "../target/types/lottery";
+import { MockVrf } from "../target/types/mock_vrf";
+import { MockAmm } from "../target/types/mock_amm";
 import * as assert from "assert";
 import { Keypair, PublicKey } from "@solana/web3.js";
+import { createHash, randomBytes } from "crypto";
+import { TOKEN_PROGRAM_ID, createMint, getAccount, getMint, getOrCreateAssociatedTokenAccount, mintTo } from "@solana/spl-token";
 
 const incinerator = new anchor.web3.PublicKey("1nc1nerator11111111111111111111111111111111")
 const token_address = new anchor.web3.PublicKey("9qywujQCJyECybwpNsM4YTBRnakjDS23MdJHGRYVeLm6")
 const team_address = new anchor.web3.PublicKey("2X9Pq1me5aWXvci6QjAy5nPDTNZLTWawUKq1nYtFf2gG")
+const keeper_bounty_bp = new anchor.BN(100)
+const referral_fee_bp = new anchor.BN(1000) // 10% of the team share
 
 const SLOT_HASHES_SYSVAR = new PublicKey("SysvarS1otHashes111111111111111111111111111");
 
@@ -16,6 +22,8 @@ describe("lottery", () => {
   anchor.setProvider(anchor.AnchorProvider.env());
 
   const program = anchor.workspace.Lottery as Program<Lottery>;
+  const vrfProgram = anchor.workspace.MockVrf as Program<MockVrf>;
+  const ammProgram = anchor.workspace.MockAmm as Program<MockAmm>;
 
   function getLotteryPDA(lotteryNum) {
     const lotterySeed = Buffer.alloc(7)
@@ -31,6 +39,19 @@ describe("lottery", () => {
     return lotteryPDA
   }
 
+  function getStatePDA(lotteryNum) {
+    const lotterySeed = Buffer.alloc(7)
+    lotterySeed.writeUInt32LE(lotteryNum, 0)
+    const [statePDA, _] = PublicKey.findProgramAddressSync(
+        [
+          anchor.utils.bytes.utf8.encode("lottery-state"),
+          lotterySeed,
+        ],
+        program.programId
+    );
+    return statePDA
+  }
+
   function getRoundPDA(lotteryNum, roundNum) {
     const lotterySeed = Buffer.alloc(4)
     lotterySeed.writeUInt32LE(lotteryNum, 0)
@@ -72,6 +93,55 @@ describe("lottery", () => {
     return ticketPDA
   }
 
+  function getRoundVaultPDA(roundPDA) {
+    const [vaultPDA, _] = PublicKey.findProgramAddressSync(
+        [
+          anchor.utils.bytes.utf8.encode("round-vault"),
+          roundPDA.toBuffer()
+        ],
+        program.programId
+    );
+    return vaultPDA
+  }
+
+  function getRandomnessPDA(seed) {
+    const [randomnessPDA, _] = PublicKey.findProgramAddressSync(
+        [
+          anchor.utils.bytes.utf8.encode("randomness"),
+          seed
+        ],
+        vrfProgram.programId
+    );
+    return randomnessPDA
+  }
+
+  function sha3(...inputs) {
+    const hasher = createHash("sha3-256")
+    inputs.forEach(input => hasher.update(input))
+    return hasher.digest()
+  }
+
+  function deriveSeed(domain, ...inputs) {
+    const hasher = createHash("sha3-256")
+    for (const input of [Buffer.from(domain), ...inputs]) {
+      hasher.update(new anchor.BN(input.length).toArrayLike(Buffer, "le", 8))
+      hasher.update(input)
+    }
+    return hasher.digest()
+  }
+
+  function uniform(seed, bound) {
+    // mirrors arcade_rng::uniform's rejection sampling
+    const max = new anchor.BN(2).pow(new anchor.BN(64)).subn(1)
+    const zone = max.sub(new anchor.BN((max.modn(bound) + 1) % bound))
+    for (let counter = 0; ; counter++) {
+      const value = new anchor.BN(sha3(seed, new anchor.BN(counter).toArrayLike(Buffer, "le", 8)).subarray(0, 8), "le")
+      if (value.lte(zone)) {
+        return value.modn(bound)
+      }
+    }
+  }
+
   // Utility function to get SOL balance
   async function getBalance(publicKey) {
     return await anchor.getProvider().connection.getBalance(publicKey);
@@ -93,7 +163,6 @@ describe("lottery", () => {
   }
 
   it("Is initialized!", async () => {
-    const state = Keypair.generate();
     const wallet = program.provider.wallet
     const lottery_num = 1
     const initial_duration = new anchor.BN(1000)
@@ -107,7 +176,7 @@ describe("lottery", () => {
     const configPDA = getLotteryPDA(lottery_num)
     const firstRoundPDA = getRoundPDA(lottery_num, 1)
 
-    // console.log(configPDA, firstRoundPDA, wallet.publicKey, state.publicKey)
+    // console.log(configPDA, firstRoundPDA, wallet.publicKey, getStatePDA(lottery_num))
 
     await program.methods.setupLottery(
         lottery_num,
@@ -121,16 +190,18 @@ describe("lottery", () => {
         incinerator,    // burn_address
         token_address,  // burn_token
         team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
     ).accounts({
       config: configPDA,
-      state: state.publicKey,
+      state: getStatePDA(lottery_num),
       firstRound: firstRoundPDA,
       creator: wallet.publicKey,
       systemProgram: anchor.web3.SystemProgram.programId
-    }).signers([state, wallet.payer]).rpc();
+    }).signers([wallet.payer]).rpc();
 
     const configAccount = await program.account.lotteryConfig.fetch(configPDA);
-    const stateAccount = await program.account.lotteryState.fetch(state.publicKey);
+    const stateAccount = await program.account.lotteryState.fetch(getStatePDA(lottery_num));
     const roundAccount = await program.account.lotteryRound.fetch(firstRoundPDA);
 
     assert.ok(configAccount.lotteryNum == lottery_num)
@@ -144,6 +215,8 @@ describe("lottery", () => {
     assert.equal(configAccount.burnAddress.toBase58(), incinerator)
     assert.equal(configAccount.burnToken.toBase58(), token_address)
     assert.equal(configAccount.teamAddress.toBase58(), team_address)
+    assert.ok(configAccount.keeperBountyBp.eq(keeper_bounty_bp))
+    assert.ok(configAccount.referralFeeBp.eq(referral_fee_bp))
 
     assert.ok(stateAccount.numRounds == 1)
     assert.ok(stateAccount.ticketsBought.eqn(0))
@@ -155,7 +228,6 @@ describe("lottery", () => {
   });
 
   it("Prevents bad percentages", async () => {
-    const state = Keypair.generate();
     const wallet = program.provider.wallet
     let lottery_num = 2
     const initial_duration = new anchor.BN(1000)
@@ -182,13 +254,15 @@ describe("lottery", () => {
           incinerator,    // burn_address
           token_address,  // burn_token
           team_address,   // team_address
+          keeper_bounty_bp,  // keeper_bounty_bp
+          referral_fee_bp,  // referral_fee_bp
       ).accounts({
         config: configPDA,
-        state: state.publicKey,
+        state: getStatePDA(lottery_num),
         firstRound: firstRoundPDA,
         creator: wallet.publicKey,
         systemProgram: anchor.web3.SystemProgram.programId
-      }).signers([state]).rpc();
+      }).signers([]).rpc();
     } catch (error) {
       // console.log({error})
       assert.equal(error.error.errorMessage, "Percentages should add to 90%");
@@ -209,20 +283,21 @@ describe("lottery", () => {
           incinerator,    // burn_address
           token_address,  // burn_token
           team_address,   // team_address
+          keeper_bounty_bp,  // keeper_bounty_bp
+          referral_fee_bp,  // referral_fee_bp
       ).accounts({
         config: configPDA,
-        state: state.publicKey,
+        state: getStatePDA(lottery_num),
         firstRound: firstRoundPDA,
         creator: wallet.publicKey,
         systemProgram: anchor.web3.SystemProgram.programId
-      }).signers([state]).rpc();
+      }).signers([]).rpc();
     } catch (error) {
       assert.equal(error.error.errorMessage, "Percentages should add to 90%");
     }
   });
 
   it("Cannot double initialize", async () => {
-    const state = Keypair.generate();
     const wallet = program.provider.wallet
     const lottery_num = 2
     const initial_duration = new anchor.BN(1000)
@@ -248,13 +323,15 @@ describe("lottery", () => {
         incinerator,    // burn_address
         token_address,  // burn_token
         team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
     ).accounts({
       config: configPDA,
-      state: state.publicKey,
+      state: getStatePDA(lottery_num),
       firstRound: firstRoundPDA,
       creator: wallet.publicKey,
       systemProgram: anchor.web3.SystemProgram.programId
-    }).signers([state]).rpc();
+    }).signers([]).rpc();
 
     try {
       await program.methods.setupLottery(
@@ -269,21 +346,59 @@ describe("lottery", () => {
           incinerator,    // burn_address
           token_address,  // burn_token
           team_address,   // team_address
+          keeper_bounty_bp,  // keeper_bounty_bp
+          referral_fee_bp,  // referral_fee_bp
       ).accounts({
         config: configPDA,
-        state: state.publicKey,
+        state: getStatePDA(lottery_num),
         firstRound: firstRoundPDA,
         creator: wallet.publicKey,
         systemProgram: anchor.web3.SystemProgram.programId
-      }).signers([state]).rpc();
+      }).signers([]).rpc();
     } catch (error) {
       return
     }
     assert.fail("Double initialized")
   });
 
+  it("Only migrates legacy rounds of a paused lottery", async () => {
+    const wallet = program.provider.wallet
+    const lottery_num = 2
+    const configPDA = getLotteryPDA(lottery_num)
+    const migrateAccounts = {
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      round: getRoundPDA(lottery_num, 1),
+      previousRound: null,
+      authority: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      slotHashes: SLOT_HASHES_SYSVAR
+    }
+
+    try {
+      await program.methods.migrateLegacyRound(1, new anchor.BN(1)).accounts(migrateAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Lottery must be paused to migrate its rounds");
+    }
+
+    await program.methods.setLotteryPaused(true).accounts({
+      config: configPDA,
+      authority: wallet.publicKey,
+    }).signers([]).rpc();
+    try {
+      await program.methods.migrateLegacyRound(1, new anchor.BN(1)).accounts(migrateAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Account isn't in the legacy layout");
+    }
+    await program.methods.setLotteryPaused(false).accounts({
+      config: configPDA,
+      authority: wallet.publicKey,
+    }).signers([]).rpc();
+  });
+
   it("Allows deposits", async () => {
-    const state = Keypair.generate();
     const wallet = program.provider.wallet
     const lottery_num = 3
     const initial_duration = new anchor.BN(1000)
@@ -309,13 +424,15 @@ describe("lottery", () => {
         incinerator,    // burn_address
         token_address,  // burn_token
         team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
     ).accounts({
       config: configPDA,
-      state: state.publicKey,
+      state: getStatePDA(lottery_num),
       firstRound: firstRoundPDA,
       creator: wallet.publicKey,
       systemProgram: anchor.web3.SystemProgram.programId
-    }).signers([state]).rpc();
+    }).signers([]).rpc();
 
     const purchase_amount = new anchor.BN(5)
     const round_account_before = await program.account.lotteryRound.fetch(firstRoundPDA);
@@ -325,11 +442,11 @@ describe("lottery", () => {
     const end_time_before = round_account_before.endTime
     const ticket_num = 0
     const lotteryTicketPDA = getTicketPDA(wallet, lottery_num,1, ticket_num)
-    // console.log(state.publicKey, firstRoundPDA, configPDA, wallet.publicKey, lotteryTicketPDA)
+    // console.log(getStatePDA(lottery_num), firstRoundPDA, configPDA, wallet.publicKey, lotteryTicketPDA)
 
     const txSignature = await program.methods.buyTicket(purchase_amount, ticket_num).accounts({
       lotteryTicket: lotteryTicketPDA,
-      state: state.publicKey,
+      state: getStatePDA(lottery_num),
       config: configPDA,
       round: firstRoundPDA,
       owner: wallet.publicKey,
@@ -354,7 +471,7 @@ describe("lottery", () => {
     // TODO: make this exact
     assert.ok(wallet_balance_after - (wallet_balance_before - purchase_amount.toNumber() - transactionFee - rent) < 100);
 
-    const stateAccount = await program.account.lotteryState.fetch(state.publicKey);
+    const stateAccount = await program.account.lotteryState.fetch(getStatePDA(lottery_num));
     const configAccount = await program.account.lotteryConfig.fetch(configPDA);
 
     assert.ok(stateAccount.ticketsBought.eqn(1))
@@ -373,7 +490,6 @@ describe("lottery", () => {
   });
 
   it("Allows multiple deposits", async () => {
-    const state = Keypair.generate();
     const wallet = program.provider.wallet
     const lottery_num = 4
     const initial_duration = new anchor.BN(3)
@@ -399,13 +515,15 @@ describe("lottery", () => {
         incinerator,    // burn_address
         token_address,  // burn_token
         team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
     ).accounts({
       config: configPDA,
-      state: state.publicKey,
+      state: getStatePDA(lottery_num),
       firstRound: firstRoundPDA,
       creator: wallet.publicKey,
       systemProgram: anchor.web3.SystemProgram.programId
-    }).signers([state]).rpc();
+    }).signers([]).rpc();
 
     const max_tickets = 5
     for (let i = 1; i <= max_tickets; i++) {
@@ -421,11 +539,11 @@ describe("lottery", () => {
 
 
       const lotteryTicketPDA = getTicketPDA(wallet, lottery_num, 1, ticket_num)
-      // console.log(state.publicKey, firstRoundPDA, configPDA, wallet.publicKey, lotteryTicketPDA)
+      // console.log(getStatePDA(lottery_num), firstRoundPDA, configPDA, wallet.publicKey, lotteryTicketPDA)
 
       const txSignature = await program.methods.buyTicket(purchase_amount, ticket_num).accounts({
         lotteryTicket: lotteryTicketPDA,
-        state: state.publicKey,
+        state: getStatePDA(lottery_num),
         config: configPDA,
         round: firstRoundPDA,
         owner: wallet.publicKey,
@@ -450,7 +568,7 @@ describe("lottery", () => {
       // TODO: make this exact
       assert.ok(wallet_balance_after - (wallet_balance_before - purchase_amount.toNumber() - transactionFee - rent) < 100);
 
-      const stateAccount = await program.account.lotteryState.fetch(state.publicKey);
+      const stateAccount = await program.account.lotteryState.fetch(getStatePDA(lottery_num));
       const configAccount = await program.account.lotteryConfig.fetch(configPDA);
 
       assert.ok(stateAccount.ticketsBought.eqn(i))
@@ -477,7 +595,7 @@ describe("lottery", () => {
 
       await program.methods.buyTicket(purchase_amount, ticket_num).accounts({
         lotteryTicket: lotteryTicketPDA,
-        state: state.publicKey,
+        state: getStatePDA(lottery_num),
         config: configPDA,
         round: firstRoundPDA,
         owner: wallet.publicKey,
@@ -491,7 +609,6 @@ describe("lottery", () => {
   });
 
   it("Allows continuing to next round", async () => {
-    const state = Keypair.generate();
     const wallet = program.provider.wallet
     const lottery_num = 5
     const initial_duration = new anchor.BN(3)
@@ -517,13 +634,15 @@ describe("lottery", () => {
         incinerator,    // burn_address
         token_address,  // burn_token
         team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
     ).accounts({
       config: configPDA,
-      state: state.publicKey,
+      state: getStatePDA(lottery_num),
       firstRound: firstRoundPDA,
       creator: wallet.publicKey,
       systemProgram: anchor.web3.SystemProgram.programId
-    }).signers([state]).rpc();
+    }).signers([]).rpc();
 
     const max_tickets = 5
     for (let i = 1; i <= max_tickets; i++) {
@@ -539,11 +658,11 @@ describe("lottery", () => {
 
 
       const lotteryTicketPDA = getTicketPDA(wallet, lottery_num, 1, ticket_num)
-      // console.log(state.publicKey, firstRoundPDA, configPDA, wallet.publicKey, lotteryTicketPDA)
+      // console.log(getStatePDA(lottery_num), firstRoundPDA, configPDA, wallet.publicKey, lotteryTicketPDA)
 
       const txSignature = await program.methods.buyTicket(purchase_amount, ticket_num).accounts({
         lotteryTicket: lotteryTicketPDA,
-        state: state.publicKey,
+        state: getStatePDA(lottery_num),
         config: configPDA,
         round: firstRoundPDA,
         owner: wallet.publicKey,
@@ -568,7 +687,7 @@ describe("lottery", () => {
       // TODO: make this exact
       assert.ok(wallet_balance_after - (wallet_balance_before - purchase_amount.toNumber() - transactionFee - rent) < 100);
 
-      const stateAccount = await program.account.lotteryState.fetch(state.publicKey);
+      const stateAccount = await program.account.lotteryState.fetch(getStatePDA(lottery_num));
       const configAccount = await program.account.lotteryConfig.fetch(configPDA);
 
       assert.ok(stateAccount.ticketsBought.eqn(i))
@@ -592,15 +711,949 @@ describe("lottery", () => {
     const secondRoundPDA = getRoundPDA(lottery_num, 2)
     const round = Keypair.generate();
 
+    // another lottery's state would number the next round after that lottery's rounds
+    try {
+      await program.methods.closeRound(new anchor.BN(1)).accounts({
+        previousRound: firstRoundPDA,
+        nextRound: secondRoundPDA,
+        state: getStatePDA(1),
+        config: configPDA,
+        closer: wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        slotHashes: SLOT_HASHES_SYSVAR
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "A seeds constraint was violated");
+    }
+
     const random = new anchor.BN(randomInteger(1, 1000000))
     const txSignature = await program.methods.closeRound(random).accounts({
       previousRound: firstRoundPDA,
       nextRound: secondRoundPDA,
-      state: state.publicKey,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      closer: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      slotHashes: SLOT_HASHES_SYSVAR
+    }).signers([]).rpc();
+  })
+
+  it("Closes rounds with vrf randomness", async () => {
+    const wallet = program.provider.wallet
+    const lottery_num = 6
+    const initial_duration = new anchor.BN(3)
+    const duration_extension = new anchor.BN(1)
+    const min_deposit = new anchor.BN(1)
+    const burn_pct = 10
+    const last_depositor_pct = 15
+    const team_pct = 15
+    const winner_pct = 50
+
+    const configPDA = getLotteryPDA(lottery_num)
+    const firstRoundPDA = getRoundPDA(lottery_num, 1)
+    const secondRoundPDA = getRoundPDA(lottery_num, 2)
+
+    await program.methods.setupLottery(
+        lottery_num,
+        initial_duration,
+        duration_extension,   // duration_extension
+        min_deposit,          // min_deposit
+        burn_pct,             // burn_pct
+        last_depositor_pct,   // last_depositor_pct
+        team_pct,             // team_pct
+        winner_pct,           // winner_pct
+        incinerator,    // burn_address
+        token_address,  // burn_token
+        team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
+    ).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      firstRound: firstRoundPDA,
+      creator: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    const ticket_num = 1
+    await program.methods.buyTicket(new anchor.BN(randomInteger(100, 10000)), ticket_num).accounts({
+      lotteryTicket: getTicketPDA(wallet, lottery_num, 1, ticket_num),
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      round: firstRoundPDA,
+      owner: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    const vrfSeed = randomBytes(32)
+    const randomnessPDA = getRandomnessPDA(vrfSeed)
+    const requestAccounts = {
+      round: firstRoundPDA,
+      config: configPDA,
+      randomness: randomnessPDA,
+      requester: wallet.publicKey,
+      vrfProgram: vrfProgram.programId,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }
+
+    try {
+      await program.methods.requestRoundRandomness([...vrfSeed]).accounts(requestAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Account doesn't belong to the lottery's vrf oracle");
+    }
+
+    await program.methods.setVrfProgram(vrfProgram.programId).accounts({
+      config: configPDA,
+      authority: wallet.publicKey,
+    }).signers([]).rpc();
+
+    try {
+      await program.methods.requestRoundRandomness([...vrfSeed]).accounts(requestAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "The lottery has not ended yet.");
+    }
+
+    await sleep(5000);
+
+    // with an oracle configured the closer can't fall back to the slot hash
+    try {
+      await program.methods.closeRound(new anchor.BN(1)).accounts({
+        previousRound: firstRoundPDA,
+        nextRound: secondRoundPDA,
+        state: getStatePDA(lottery_num),
+        config: configPDA,
+        closer: wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        slotHashes: SLOT_HASHES_SYSVAR
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Rounds close through the vrf oracle while one is configured");
+    }
+
+    await program.methods.requestRoundRandomness([...vrfSeed]).accounts(requestAccounts).signers([]).rpc();
+
+    // the closer can't skip the requested randomness and pick the seed instead
+    try {
+      await program.methods.closeRound(new anchor.BN(1)).accounts({
+        previousRound: firstRoundPDA,
+        nextRound: secondRoundPDA,
+        state: getStatePDA(lottery_num),
+        config: configPDA,
+        closer: wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        slotHashes: SLOT_HASHES_SYSVAR
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Randomness already requested for this round");
+    }
+
+    const closeAccounts = {
+      previousRound: firstRoundPDA,
+      nextRound: secondRoundPDA,
+      randomness: randomnessPDA,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      closer: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }
+
+    try {
+      await program.methods.closeRoundVrf().accounts(closeAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Randomness has not been fulfilled yet");
+    }
+
+    await vrfProgram.methods.fulfill().accounts({
+      randomness: randomnessPDA
+    }).signers([]).rpc();
+
+    await program.methods.closeRoundVrf().accounts(closeAccounts).signers([]).rpc();
+
+    const randomnessAccount = await vrfProgram.account.randomnessAccount.fetch(randomnessPDA);
+    const firstRound = await program.account.lotteryRound.fetch(firstRoundPDA);
+    const secondRound = await program.account.lotteryRound.fetch(secondRoundPDA);
+    const stateAccount = await program.account.lotteryState.fetch(getStatePDA(lottery_num));
+    const seed = deriveSeed("arcade-lottery", Buffer.from(randomnessAccount.randomness))
+
+    assert.equal(firstRound.winningNumber.toNumber(), uniform(seed, firstRound.potSize.toNumber()))
+    assert.ok(firstRound.closed)
+    assert.ok(Buffer.from(firstRound.vrfSeed).equals(vrfSeed))
+    assert.equal(secondRound.roundNum, 2)
+    assert.equal(stateAccount.numRounds, 2)
+  })
+
+  it("Pays out winnings", async () => {
+    const wallet = program.provider.wallet
+    const lottery_num = 7
+    const initial_duration = new anchor.BN(3)
+    const duration_extension = new anchor.BN(1)
+    const min_deposit = new anchor.BN(1)
+    const burn_pct = 10
+    const last_depositor_pct = 15
+    const team_pct = 15
+    const winner_pct = 50
+
+    const configPDA = getLotteryPDA(lottery_num)
+    const firstRoundPDA = getRoundPDA(lottery_num, 1)
+    const secondRoundPDA = getRoundPDA(lottery_num, 2)
+
+    await program.methods.setupLottery(
+        lottery_num,
+        initial_duration,
+        duration_extension,   // duration_extension
+        min_deposit,          // min_deposit
+        burn_pct,             // burn_pct
+        last_depositor_pct,   // last_depositor_pct
+        team_pct,             // team_pct
+        winner_pct,           // winner_pct
+        incinerator,    // burn_address
+        PublicKey.default,  // burn_token
+        team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
+    ).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      firstRound: firstRoundPDA,
+      creator: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    // a single ticket covers the whole pot, so it has to win
+    const ticket_num = 1
+    const pot_size = 1_000_000
+    const ticketPDA = getTicketPDA(wallet, lottery_num, 1, ticket_num)
+    await program.methods.buyTicket(new anchor.BN(pot_size), ticket_num).accounts({
+      lotteryTicket: ticketPDA,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      round: firstRoundPDA,
+      owner: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    await sleep(5000);
+
+    const round_balance_before_close = await getBalance(firstRoundPDA)
+    await program.methods.closeRound(new anchor.BN(randomInteger(1, 1000000))).accounts({
+      previousRound: firstRoundPDA,
+      nextRound: secondRoundPDA,
+      state: getStatePDA(lottery_num),
       config: configPDA,
       closer: wallet.publicKey,
       systemProgram: anchor.web3.SystemProgram.programId,
       slotHashes: SLOT_HASHES_SYSVAR
     }).signers([]).rpc();
+
+    const round_rent = await getRentExemption(program.account.lotteryRound.size)
+    const bounty = pot_size * keeper_bounty_bp.toNumber() / 10_000
+    assert.equal(await getBalance(firstRoundPDA), round_balance_before_close - pot_size / 10 - bounty)
+    assert.equal(await getBalance(secondRoundPDA), round_rent + pot_size / 10)
+
+    const claimAccounts = {
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      ticket: ticketPDA,
+      round: firstRoundPDA,
+      owner: wallet.publicKey,
+      lastDepositor: wallet.publicKey,
+      team: team_address,
+      burn: incinerator,
+      cranker: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }
+
+    // the incinerator is swept at the end of every block, so the burn share is checked via the round balance
+    const team_balance_before = await getBalance(team_address)
+    const round_balance_before = await getBalance(firstRoundPDA)
+
+    await program.methods.claimWinnings(new anchor.BN(1)).accounts(claimAccounts).signers([]).rpc();
+
+    // the closer and the cranker are both paid out of the team share
+    assert.equal(await getBalance(team_address), team_balance_before + pot_size * team_pct / 100 - 2 * bounty)
+    assert.equal(await getBalance(firstRoundPDA), round_balance_before - pot_size * 90 / 100 + bounty)
+    assert.equal(await getBalance(firstRoundPDA), round_rent)
+
+    const firstRound = await program.account.lotteryRound.fetch(firstRoundPDA);
+    assert.equal(firstRound.winningDepositor.toBase58(), wallet.publicKey.toBase58())
+
+    try {
+      await program.methods.claimWinnings(new anchor.BN(1)).accounts(claimAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Round already claimed");
+    }
+  })
+
+  it("Only closes rounds that have ended", async () => {
+    const wallet = program.provider.wallet
+    const lottery_num = 8
+    const initial_duration = new anchor.BN(3)
+    const duration_extension = new anchor.BN(1)
+    const min_deposit = new anchor.BN(1)
+    const burn_pct = 10
+    const last_depositor_pct = 15
+    const team_pct = 15
+    const winner_pct = 50
+
+    const configPDA = getLotteryPDA(lottery_num)
+    const firstRoundPDA = getRoundPDA(lottery_num, 1)
+    const secondRoundPDA = getRoundPDA(lottery_num, 2)
+
+    await program.methods.setupLottery(
+        lottery_num,
+        initial_duration,
+        duration_extension,   // duration_extension
+        min_deposit,          // min_deposit
+        burn_pct,             // burn_pct
+        last_depositor_pct,   // last_depositor_pct
+        team_pct,             // team_pct
+        winner_pct,           // winner_pct
+        incinerator,    // burn_address
+        token_address,  // burn_token
+        team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
+    ).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      firstRound: firstRoundPDA,
+      creator: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    const closeAccounts = {
+      previousRound: firstRoundPDA,
+      nextRound: secondRoundPDA,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      closer: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      slotHashes: SLOT_HASHES_SYSVAR
+    }
+
+    try {
+      await program.methods.closeRound(new anchor.BN(1)).accounts(closeAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "The lottery has not ended yet.");
+    }
+
+    await sleep(5000);
+
+    // nobody bought a ticket, the round still closes and opens an empty next round
+    await program.methods.closeRound(new anchor.BN(1)).accounts(closeAccounts).signers([]).rpc();
+
+    const firstRound = await program.account.lotteryRound.fetch(firstRoundPDA);
+    const secondRound = await program.account.lotteryRound.fetch(secondRoundPDA);
+    assert.ok(firstRound.closed)
+    assert.ok(firstRound.winningNumber.eqn(0))
+    assert.ok(!secondRound.closed)
+    assert.ok(secondRound.potSize.eqn(0))
+    assert.equal(secondRound.roundNum, 2)
+  })
+
+  it("Plays with spl tokens", async () => {
+    const wallet = program.provider.wallet
+    const connection = getProvider().connection
+    const lottery_num = 9
+    const initial_duration = new anchor.BN(3)
+    const duration_extension = new anchor.BN(1)
+    const min_deposit = new anchor.BN(1)
+    const burn_pct = 10
+    const last_depositor_pct = 15
+    const team_pct = 15
+    const winner_pct = 50
+
+    const mint = await createMint(connection, wallet.payer, wallet.publicKey, null, 6)
+    const walletTokenAccount = await getOrCreateAssociatedTokenAccount(connection, wallet.payer, mint, wallet.publicKey)
+    const teamTokenAccount = await getOrCreateAssociatedTokenAccount(connection, wallet.payer, mint, team_address)
+    await mintTo(connection, wallet.payer, mint, walletTokenAccount.address, wallet.publicKey, 1_000_000_000)
+
+    const configPDA = getLotteryPDA(lottery_num)
+    const firstRoundPDA = getRoundPDA(lottery_num, 1)
+    const secondRoundPDA = getRoundPDA(lottery_num, 2)
+    const firstVaultPDA = getRoundVaultPDA(firstRoundPDA)
+    const secondVaultPDA = getRoundVaultPDA(secondRoundPDA)
+
+    await program.methods.setupTokenLottery(
+        lottery_num,
+        initial_duration,
+        duration_extension,   // duration_extension
+        min_deposit,          // min_deposit
+        burn_pct,             // burn_pct
+        last_depositor_pct,   // last_depositor_pct
+        team_pct,             // team_pct
+        winner_pct,           // winner_pct
+        incinerator,    // burn_address
+        mint,           // burn_token
+        team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
+    ).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      firstRound: firstRoundPDA,
+      mint: mint,
+      firstVault: firstVaultPDA,
+      creator: wallet.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    const ticket_num = 1
+    const pot_size = 1_000_000
+    const ticketPDA = getTicketPDA(wallet, lottery_num, 1, ticket_num)
+    await program.methods.buyTokenTicket(new anchor.BN(pot_size), ticket_num).accounts({
+      lotteryTicket: ticketPDA,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      round: firstRoundPDA,
+      mint: mint,
+      vault: firstVaultPDA,
+      ownerTokenAccount: walletTokenAccount.address,
+      owner: wallet.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+    assert.equal((await getAccount(connection, firstVaultPDA)).amount, BigInt(pot_size))
+
+    await sleep(5000);
+
+    await program.methods.closeTokenRound(new anchor.BN(randomInteger(1, 1000000))).accounts({
+      previousRound: firstRoundPDA,
+      nextRound: secondRoundPDA,
+      previousVault: firstVaultPDA,
+      nextVault: secondVaultPDA,
+      mint: mint,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      closer: wallet.publicKey,
+      closerTokenAccount: walletTokenAccount.address,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      slotHashes: SLOT_HASHES_SYSVAR
+    }).signers([]).rpc();
+
+    const bounty = pot_size * keeper_bounty_bp.toNumber() / 10_000
+    assert.equal((await getAccount(connection, secondVaultPDA)).amount, BigInt(pot_size / 10))
+    assert.equal((await getAccount(connection, firstVaultPDA)).amount, BigInt(pot_size * 90 / 100 - bounty))
+
+    const supplyBefore = (await getMint(connection, mint)).supply
+    const teamBefore = (await getAccount(connection, teamTokenAccount.address)).amount
+
+    await program.methods.claimTokenWinnings().accounts({
+      config: configPDA,
+      ticket: ticketPDA,
+      state: getStatePDA(lottery_num),
+      round: firstRoundPDA,
+      vault: firstVaultPDA,
+      mint: mint,
+      ownerTokenAccount: walletTokenAccount.address,
+      lastDepositorTokenAccount: walletTokenAccount.address,
+      teamTokenAccount: teamTokenAccount.address,
+      cranker: wallet.publicKey,
+      crankerTokenAccount: walletTokenAccount.address,
+      tokenProgram: TOKEN_PROGRAM_ID
+    }).signers([]).rpc();
+
+    assert.equal((await getAccount(connection, firstVaultPDA)).amount, BigInt(0))
+    assert.equal((await getMint(connection, mint)).supply, supplyBefore - BigInt(pot_size * burn_pct / 100))
+    const stateAccount = await program.account.lotteryState.fetch(getStatePDA(lottery_num));
+    assert.ok(stateAccount.totalBurned.eqn(pot_size * burn_pct / 100))
+    assert.equal((await getAccount(connection, teamTokenAccount.address)).amount, teamBefore + BigInt(pot_size * team_pct / 100 - 2 * bounty))
+  })
+
+  it("Swaps the burn share for the burn token and burns it", async () => {
+    const wallet = program.provider.wallet
+    const connection = getProvider().connection
+    const lottery_num = 10
+    const initial_duration = new anchor.BN(3)
+    const duration_extension = new anchor.BN(1)
+    const min_deposit = new anchor.BN(1)
+    const burn_pct = 10
+    const last_depositor_pct = 15
+    const team_pct = 15
+    const winner_pct = 50
+
+    // pool quoting 1000 tokens per lamport
+    const mint = await createMint(connection, wallet.payer, wallet.publicKey, null, 6)
+    const walletTokenAccount = await getOrCreateAssociatedTokenAccount(connection, wallet.payer, mint, wallet.publicKey)
+    await mintTo(connection, wallet.payer, mint, walletTokenAccount.address, wallet.publicKey, 1_000_000_000_000)
+    const [poolPDA, _] = PublicKey.findProgramAddressSync([anchor.utils.bytes.utf8.encode("pool"), mint.toBuffer()], ammProgram.programId)
+    const [poolVaultPDA, __] = PublicKey.findProgramAddressSync([anchor.utils.bytes.utf8.encode("pool-vault"), mint.toBuffer()], ammProgram.programId)
+    await ammProgram.methods.initPool(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000_000)).accounts({
+      creator: wallet.publicKey,
+      pool: poolPDA,
+      mint: mint,
+      vault: poolVaultPDA,
+      creatorTokenAccount: walletTokenAccount.address,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    const [swapAuthorityPDA, ___] = PublicKey.findProgramAddressSync([anchor.utils.bytes.utf8.encode("swap-authority")], program.programId)
+    const burnVault = await getOrCreateAssociatedTokenAccount(connection, wallet.payer, mint, swapAuthorityPDA, true)
+
+    const configPDA = getLotteryPDA(lottery_num)
+    const firstRoundPDA = getRoundPDA(lottery_num, 1)
+    const secondRoundPDA = getRoundPDA(lottery_num, 2)
+
+    await program.methods.setupLottery(
+        lottery_num,
+        initial_duration,
+        duration_extension,   // duration_extension
+        min_deposit,          // min_deposit
+        burn_pct,             // burn_pct
+        last_depositor_pct,   // last_depositor_pct
+        team_pct,             // team_pct
+        winner_pct,           // winner_pct
+        incinerator,    // burn_address
+        mint,           // burn_token
+        team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
+    ).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      firstRound: firstRoundPDA,
+      creator: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    const ticket_num = 1
+    const pot_size = 1_000_000
+    const ticketPDA = getTicketPDA(wallet, lottery_num, 1, ticket_num)
+    await program.methods.buyTicket(new anchor.BN(pot_size), ticket_num).accounts({
+      lotteryTicket: ticketPDA,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      round: firstRoundPDA,
+      owner: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    await sleep(5000);
+
+    await program.methods.closeRound(new anchor.BN(randomInteger(1, 1000000))).accounts({
+      previousRound: firstRoundPDA,
+      nextRound: secondRoundPDA,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      closer: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      slotHashes: SLOT_HASHES_SYSVAR
+    }).signers([]).rpc();
+
+    const supplyBefore = (await getMint(connection, mint)).supply
+    const burn_lamports = pot_size * burn_pct / 100
+    // constant product quote for the burn share
+    const expected_out = Math.floor(1_000_000_000_000 * burn_lamports / (1_000_000_000 + burn_lamports))
+
+    // the winner is paid without the swap, the burn share waits in the round
+    await program.methods.claimWinnings(new anchor.BN(1)).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      ticket: ticketPDA,
+      round: firstRoundPDA,
+      owner: wallet.publicKey,
+      lastDepositor: wallet.publicKey,
+      team: team_address,
+      burn: incinerator,
+      cranker: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+    const claimedRound = await program.account.lotteryRound.fetch(firstRoundPDA);
+    assert.ok(claimedRound.burnPending.eqn(burn_lamports))
+
+    const burnAccounts = {
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      round: firstRoundPDA,
+      ammProgram: ammProgram.programId,
+      ammPool: poolPDA,
+      ammVault: poolVaultPDA,
+      burnMint: mint,
+      swapAuthority: swapAuthorityPDA,
+      burnVault: burnVault.address,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }
+
+    try {
+      await program.methods.burnRound().accounts(burnAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Program doesn't match the lottery's amm");
+    }
+
+    // only allowlisted amms and a bounded slippage can be configured
+    try {
+      await program.methods.setBurnSwap(program.programId, new anchor.BN(1_000_000_000_000), new anchor.BN(1)).accounts({
+        config: configPDA,
+        authority: wallet.publicKey,
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Program doesn't match the lottery's amm");
+    }
+    try {
+      await program.methods.setBurnSwap(ammProgram.programId, new anchor.BN(1_000_000_000_000), new anchor.BN(501)).accounts({
+        config: configPDA,
+        authority: wallet.publicKey,
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Burn slippage can't exceed 5%");
+    }
+
+    // the swap has to buy the fixed price of 1000 tokens per lamport, the pool fills 1bp below it
+    await program.methods.setBurnSwap(ammProgram.programId, new anchor.BN(1_000_000_000_000), new anchor.BN(0)).accounts({
+      config: configPDA,
+      authority: wallet.publicKey,
+    }).signers([]).rpc();
+    try {
+      await program.methods.burnRound().accounts(burnAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Swap output below the minimum");
+    }
+
+    await program.methods.setBurnSwap(ammProgram.programId, new anchor.BN(1_000_000_000_000), new anchor.BN(1)).accounts({
+      config: configPDA,
+      authority: wallet.publicKey,
+    }).signers([]).rpc();
+    await program.methods.burnRound().accounts(burnAccounts).signers([]).rpc();
+
+    const stateAccount = await program.account.lotteryState.fetch(getStatePDA(lottery_num));
+    assert.ok(stateAccount.totalBurned.eqn(expected_out))
+    assert.equal((await getMint(connection, mint)).supply, supplyBefore - BigInt(expected_out))
+    assert.equal((await getAccount(connection, burnVault.address)).amount, BigInt(0))
+    assert.equal(await getBalance(poolPDA), await getRentExemption(ammProgram.account.pool.size) + 1_000_000_000 + burn_lamports)
+
+    try {
+      await program.methods.burnRound().accounts(burnAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Round has no burn share waiting to be swapped");
+    }
+  })
+
+  it("Pays referrers out of the team share", async () => {
+    const wallet = program.provider.wallet
+    const player = Keypair.generate();
+    const promoter = Keypair.generate();
+    const lottery_num = 11
+    const initial_duration = new anchor.BN(3)
+    const burn_pct = 10
+    const last_depositor_pct = 15
+    const team_pct = 15
+    const winner_pct = 50
+
+    await getProvider().sendAndConfirm(new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.transfer({fromPubkey: wallet.publicKey, toPubkey: player.publicKey, lamports: anchor.web3.LAMPORTS_PER_SOL}),
+      anchor.web3.SystemProgram.transfer({fromPubkey: wallet.publicKey, toPubkey: promoter.publicKey, lamports: anchor.web3.LAMPORTS_PER_SOL}),
+    ))
+
+    const configPDA = getLotteryPDA(lottery_num)
+    const firstRoundPDA = getRoundPDA(lottery_num, 1)
+    const secondRoundPDA = getRoundPDA(lottery_num, 2)
+    const [referrerPDA, _] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("referrer"), promoter.publicKey.toBuffer()],
+      program.programId
+    );
+    const [playerPDA, __] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("player"), player.publicKey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods.setupLottery(
+        lottery_num,
+        initial_duration,
+        new anchor.BN(1),     // duration_extension
+        new anchor.BN(1),     // min_deposit
+        burn_pct,             // burn_pct
+        last_depositor_pct,   // last_depositor_pct
+        team_pct,             // team_pct
+        winner_pct,           // winner_pct
+        incinerator,    // burn_address
+        PublicKey.default,  // burn_token
+        team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
+    ).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      firstRound: firstRoundPDA,
+      creator: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    await program.methods.registerReferrer().accounts({
+      referrer: referrerPDA,
+      authority: promoter.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([promoter]).rpc();
+
+    const pot_size = 1_000_000
+    const referral_fee = pot_size * team_pct / 100 * referral_fee_bp.toNumber() / 10_000
+    const ticketPDA = getTicketPDA(player, lottery_num, 1, 1)
+    const referrer_balance_before = await getBalance(referrerPDA)
+    await program.methods.buyTicket(new anchor.BN(pot_size), 1).accounts({
+      lotteryTicket: ticketPDA,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      round: firstRoundPDA,
+      player: playerPDA,
+      referrer: referrerPDA,
+      owner: player.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([player]).rpc();
+
+    assert.equal(await getBalance(referrerPDA), referrer_balance_before + referral_fee)
+    const playerAccount = await program.account.player.fetch(playerPDA)
+    assert.ok(playerAccount.referrer.equals(promoter.publicKey))
+    const referrerAccount = await program.account.referrer.fetch(referrerPDA)
+    assert.ok(referrerAccount.referralCount.eqn(1))
+    assert.ok(referrerAccount.totalEarned.eqn(referral_fee))
+
+    try {
+      await program.methods.buyTicket(new anchor.BN(pot_size), 2).accounts({
+        lotteryTicket: getTicketPDA(player, lottery_num, 1, 2),
+        state: getStatePDA(lottery_num),
+        config: configPDA,
+        round: firstRoundPDA,
+        player: playerPDA,
+        referrer: null,
+        owner: player.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId
+      }).signers([player]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Player is bound to a different referrer");
+    }
+
+    await sleep(5000);
+
+    await program.methods.closeRound(new anchor.BN(randomInteger(1, 1000000))).accounts({
+      previousRound: firstRoundPDA,
+      nextRound: secondRoundPDA,
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      closer: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+      slotHashes: SLOT_HASHES_SYSVAR
+    }).signers([]).rpc();
+
+    const round_rent = await getRentExemption(program.account.lotteryRound.size)
+    const bounty = pot_size * keeper_bounty_bp.toNumber() / 10_000
+    const firstRound = await program.account.lotteryRound.fetch(firstRoundPDA)
+    assert.ok(firstRound.keeperBounty.eqn(bounty))
+
+    // the claim bounty was fixed at close, raising it afterwards doesn't touch this round
+    await program.methods.setLotteryFees(new anchor.BN(600), referral_fee_bp).accounts({
+      config: configPDA,
+      authority: wallet.publicKey,
+    }).signers([]).rpc();
+
+    const team_balance_before = await getBalance(team_address)
+    await program.methods.claimWinnings(new anchor.BN(1)).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      ticket: ticketPDA,
+      round: firstRoundPDA,
+      owner: player.publicKey,
+      lastDepositor: player.publicKey,
+      team: team_address,
+      burn: incinerator,
+      cranker: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    // the referral fee was paid up front, so the team share is short by it
+    assert.equal(await getBalance(team_address), team_balance_before + pot_size * team_pct / 100 - 2 * bounty - referral_fee)
+    assert.equal(await getBalance(firstRoundPDA), round_rent)
+
+    const promoter_balance_before = await getBalance(promoter.publicKey)
+    await program.methods.claimReferral().accounts({
+      referrer: referrerPDA,
+      authority: promoter.publicKey,
+    }).signers([promoter]).rpc();
+    assert.equal(await getBalance(referrerPDA), await getRentExemption(program.account.referrer.size))
+    assert.ok(await getBalance(promoter.publicKey) > promoter_balance_before)
+  })
+
+  it("Restricts admin instructions to their roles", async () => {
+    const wallet = program.provider.wallet
+    const treasuryAdmin = Keypair.generate();
+    const pauser = Keypair.generate();
+    const lottery_num = 12
+    const configPDA = getLotteryPDA(lottery_num)
+
+    await getProvider().sendAndConfirm(new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.transfer({fromPubkey: wallet.publicKey, toPubkey: treasuryAdmin.publicKey, lamports: anchor.web3.LAMPORTS_PER_SOL}),
+    ))
+
+    await program.methods.setupLottery(
+        lottery_num,
+        new anchor.BN(3),     // initial_duration
+        new anchor.BN(1),     // duration_extension
+        new anchor.BN(1),     // min_deposit
+        10,                   // burn_pct
+        15,                   // last_depositor_pct
+        15,                   // team_pct
+        50,                   // winner_pct
+        incinerator,    // burn_address
+        PublicKey.default,  // burn_token
+        team_address,   // team_address
+        keeper_bounty_bp,  // keeper_bounty_bp
+        referral_fee_bp,  // referral_fee_bp
+    ).accounts({
+      config: configPDA,
+      state: getStatePDA(lottery_num),
+      firstRound: getRoundPDA(lottery_num, 1),
+      creator: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }).signers([]).rpc();
+
+    let config = await program.account.lotteryConfig.fetch(configPDA)
+    assert.ok(config.configAdmin.equals(wallet.publicKey))
+    assert.ok(config.treasuryAdmin.equals(wallet.publicKey))
+    assert.ok(config.pauser.equals(wallet.publicKey))
+
+    // the config admin can't hand itself the treasury role
+    try {
+      await program.methods.setLotteryRoles(wallet.publicKey, wallet.publicKey, pauser.publicKey).accounts({
+        config: configPDA,
+        authority: wallet.publicKey,
+        treasuryAdmin: treasuryAdmin.publicKey,
+      }).signers([treasuryAdmin]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Signer doesn't hold the role for this instruction");
+    }
+
+    await program.methods.setLotteryRoles(wallet.publicKey, treasuryAdmin.publicKey, pauser.publicKey).accounts({
+      config: configPDA,
+      authority: wallet.publicKey,
+      treasuryAdmin: wallet.publicKey,
+    }).signers([]).rpc();
+
+    try {
+      await program.methods.updateLotteryConfig(new anchor.BN(3), new anchor.BN(1), new anchor.BN(10)).accounts({
+        config: configPDA,
+        authority: treasuryAdmin.publicKey,
+      }).signers([treasuryAdmin]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Signer doesn't hold the role for this instruction");
+    }
+
+    await program.methods.updateLotteryConfig(new anchor.BN(3), new anchor.BN(1), new anchor.BN(10)).accounts({
+      config: configPDA,
+      authority: wallet.publicKey,
+    }).signers([]).rpc();
+    config = await program.account.lotteryConfig.fetch(configPDA)
+    assert.ok(config.minDeposit.eqn(10))
+    assert.equal(config.teamPct, 15)
+
+    try {
+      await program.methods.setTeamAddress(wallet.publicKey).accounts({
+        config: configPDA,
+        authority: wallet.publicKey,
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Signer doesn't hold the role for this instruction");
+    }
+
+    await program.methods.setTeamAddress(wallet.publicKey).accounts({
+      config: configPDA,
+      authority: treasuryAdmin.publicKey,
+    }).signers([treasuryAdmin]).rpc();
+    config = await program.account.lotteryConfig.fetch(configPDA)
+    assert.ok(config.teamAddress.equals(wallet.publicKey))
+
+    // keeper bounties and referral fees come out of the team share
+    try {
+      await program.methods.setLotteryFees(new anchor.BN(0), new anchor.BN(0)).accounts({
+        config: configPDA,
+        authority: wallet.publicKey,
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Signer doesn't hold the role for this instruction");
+    }
+
+    await program.methods.setLotteryFees(new anchor.BN(0), new anchor.BN(0)).accounts({
+      config: configPDA,
+      authority: treasuryAdmin.publicKey,
+    }).signers([treasuryAdmin]).rpc();
+    config = await program.account.lotteryConfig.fetch(configPDA)
+    assert.ok(config.keeperBountyBp.eqn(0))
+
+    try {
+      await program.methods.setLotteryPaused(true).accounts({
+        config: configPDA,
+        authority: wallet.publicKey,
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Signer doesn't hold the role for this instruction");
+    }
+
+    await getProvider().sendAndConfirm(new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.transfer({fromPubkey: wallet.publicKey, toPubkey: pauser.publicKey, lamports: anchor.web3.LAMPORTS_PER_SOL}),
+    ))
+    await program.methods.setLotteryPaused(true).accounts({
+      config: configPDA,
+      authority: pauser.publicKey,
+    }).signers([pauser]).rpc();
+
+    const [playerPDA, _] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("player"), wallet.publicKey.toBuffer()],
+      program.programId
+    );
+    const buyAccounts = {
+      lotteryTicket: getTicketPDA(wallet, lottery_num, 1, 1),
+      state: getStatePDA(lottery_num),
+      config: configPDA,
+      round: getRoundPDA(lottery_num, 1),
+      player: playerPDA,
+      referrer: null,
+      owner: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId
+    }
+    try {
+      await program.methods.buyTicket(new anchor.BN(1_000_000), 1).accounts(buyAccounts).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "Lottery is paused");
+    }
+
+    // another lottery's config can't vouch for this lottery's round
+    try {
+      await program.methods.buyTicket(new anchor.BN(1_000_000), 1).accounts({
+        ...buyAccounts,
+        lotteryTicket: getTicketPDA(wallet, 1, 1, 1),
+        state: getStatePDA(1),
+        config: getLotteryPDA(1),
+      }).signers([]).rpc();
+      assert.fail("Should have failed")
+    } catch (error) {
+      assert.equal(error.error.errorMessage, "A seeds constraint was violated");
+    }
+
+    await program.methods.setLotteryPaused(false).accounts({
+      config: configPDA,
+      authority: pauser.publicKey,
+    }).signers([pauser]).rpc();
+    await program.methods.buyTicket(new anchor.BN(1_000_000), 1).accounts(buyAccounts).signers([]).rpc();
   })
 })